@@ -0,0 +1,91 @@
+//! Counts the heap allocations of common operations.
+//!
+//! Run with `cargo bench --bench allocations`.
+
+extern crate vnodes;
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
+
+use vnodes::{ErrorKind, Interned, MapNode, Node, NodeHandle, Result, Value, ValueConv, Vnodes};
+
+struct Counting;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: Counting = Counting;
+
+struct Adder;
+
+impl Node for Adder {
+    fn call(&self, _: &Vnodes, args: &[Value]) -> Result<Value<'_>> {
+        let sum = args.iter()
+            .map(|arg| u64::from_value(arg.clone()))
+            .sum::<Result<u64>>()?;
+
+        Ok(Value::Unsigned(sum))
+    }
+
+    fn get(&self, _: &Vnodes, _: Interned) -> Result<Value<'_>> {
+        Err(ErrorKind::NoSuchEntry.into())
+    }
+}
+
+const ITERATIONS: usize = 100_000;
+
+fn measure<F>(name: &str, mut f: F)
+where
+    F: FnMut(),
+{
+    // Warm up, e.g. to grow maps to their final size
+    f();
+
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed);
+    let start = Instant::now();
+    for _ in 0..ITERATIONS {
+        f();
+    }
+    let elapsed = start.elapsed();
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed) - allocations;
+
+    println!(
+        "{:<24} {:>6.2} allocations/op {:>10.1} ns/op",
+        name,
+        allocations as f64 / ITERATIONS as f64,
+        elapsed.as_secs_f64() * 1e9 / ITERATIONS as f64
+    );
+}
+
+fn main() {
+    let nodes = Vnodes::new();
+    nodes.insert("/map", MapNode::new_node()).unwrap();
+    nodes.insert("/add", NodeHandle::new(Adder)).unwrap();
+
+    measure("insert /map/a", || nodes.insert("/map/a", 5u64).unwrap());
+    measure("get /map/a", || {
+        nodes.get::<_, u64>("/map/a").unwrap();
+    });
+    measure("call /add (1, 2)", || {
+        nodes.call::<_, _, u64>("/add", (1u64, 2u64)).unwrap();
+    });
+}
@@ -0,0 +1,68 @@
+#![feature(test)]
+
+extern crate test;
+extern crate vnodes;
+
+use test::{black_box, Bencher};
+use vnodes::{InternedPathBuf, MapNode, Vnodes};
+
+fn nodes() -> Vnodes {
+    let nodes = Vnodes::new();
+    nodes.insert("/map", MapNode::new_node()).unwrap();
+    nodes.insert("/map/nested", MapNode::new_node()).unwrap();
+
+    nodes
+}
+
+#[bench]
+fn insert_unsigned(b: &mut Bencher) {
+    let nodes = nodes();
+
+    b.iter(|| {
+        nodes.insert(black_box("/map/a"), black_box(5u64)).unwrap();
+    });
+}
+
+#[bench]
+fn insert_unsigned_nested(b: &mut Bencher) {
+    let nodes = nodes();
+
+    b.iter(|| {
+        nodes.insert(black_box("/map/nested/a"), black_box(5u64)).unwrap();
+    });
+}
+
+#[bench]
+fn insert_string(b: &mut Bencher) {
+    let nodes = nodes();
+
+    b.iter(|| {
+        nodes.insert(black_box("/map/a"), black_box("a string")).unwrap();
+    });
+}
+
+#[bench]
+fn insert_interned_path(b: &mut Bencher) {
+    let nodes = nodes();
+    let path = InternedPathBuf::from("/map/a");
+
+    b.iter(|| {
+        nodes.insert(black_box(path.clone()), black_box(5u64)).unwrap();
+    });
+}
+
+#[bench]
+fn insert_many_keys(b: &mut Bencher) {
+    let paths: Vec<_> = (0..64)
+        .map(|i| InternedPathBuf::from(format!("/map/key{}", i).as_str()))
+        .collect();
+
+    b.iter(|| {
+        let nodes = nodes();
+        for path in &paths {
+            nodes.insert(path.clone(), 5u64).unwrap();
+        }
+
+        nodes
+    });
+}
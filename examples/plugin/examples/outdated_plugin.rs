@@ -0,0 +1,15 @@
+//! A plugin declaring an ABI version which doesn't exist; loading it must fail.
+
+extern crate vnodes;
+
+use vnodes::raw::{PluginDeclaration, RawContextPtr, RawResult};
+
+unsafe extern "C" fn init(_: RawContextPtr) -> RawResult {
+    panic!("must not be called")
+}
+
+#[no_mangle]
+pub static VNODES_PLUGIN: PluginDeclaration = PluginDeclaration {
+    abi_version: 0,
+    init,
+};
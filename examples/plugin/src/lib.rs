@@ -0,0 +1,55 @@
+//! An example plugin; build it with `cargo build` and load it with `Vnodes::load_plugin`.
+
+#[macro_use]
+extern crate vnodes;
+
+use vnodes::{ErrorKind, Interned, MapNode, Node, NodeHandle, Result, SharedBuffer, Value,
+             ValueConv, Vnodes};
+
+/// Greets its argument, or whoever is stored under `/plugin/name`.
+struct Greeter;
+
+impl Node for Greeter {
+    fn call(&self, context: &Vnodes, args: &[Value]) -> Result<Value> {
+        let greeting = match args.first() {
+            Some(name) => format!("Hello, {}!", String::from_value(name.clone())?),
+            None => format!("Hello, {}!", context.get::<_, String>("/plugin/name")?),
+        };
+
+        Ok(Value::SharedString(SharedBuffer::from(greeting)))
+    }
+
+    fn get(&self, _: &Vnodes, _: Interned) -> Result<Value> {
+        Err(ErrorKind::NoSuchEntry.into())
+    }
+}
+
+/// Inserts a `Greeter` and a greeting into the tree of the host.
+struct Publisher;
+
+impl Node for Publisher {
+    fn call(&self, context: &Vnodes, _: &[Value]) -> Result<Value> {
+        context.insert("/greeter", NodeHandle::new(Greeter))?;
+        context.insert(
+            "/greeting",
+            Value::SharedString(SharedBuffer::from("Hello!".to_owned())),
+        )?;
+
+        Ok(Value::Void)
+    }
+
+    fn get(&self, _: &Vnodes, _: Interned) -> Result<Value> {
+        Err(ErrorKind::NoSuchEntry.into())
+    }
+}
+
+fn init(context: &Vnodes) -> Result<NodeHandle> {
+    let root = MapNode::new_node();
+    root.insert(context, Interned::from("name"), "plugin".into_value())?;
+    root.insert(context, Interned::from("greet"), NodeHandle::new(Greeter).into_value())?;
+    root.insert(context, Interned::from("publish"), NodeHandle::new(Publisher).into_value())?;
+
+    Ok(root)
+}
+
+export_plugin!(init);
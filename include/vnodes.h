@@ -0,0 +1,490 @@
+#pragma once
+
+/* Generated by cbindgen, don't edit. Run `VNODES_BLESS=1 cargo test --test c_api` to update. */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+struct RawValue;
+struct RawResult;
+struct RawNodeData;
+typedef struct RawMapEntry RawMapEntry;
+
+/**
+ * The version of the raw interface; plugins built against another version are rejected.
+ *
+ * Has to be bumped whenever the layout of one of the types below, the meaning of
+ * an action or flag or the `Vnodes` context changes. Adding actions or using
+ * reserved flags is compatible, since unknown ones are rejected by the receiver.
+ */
+#define ABI_VERSION 3
+
+/**
+ * The first code of the range reserved for `ErrorKind::Custom`.
+ */
+#define ErrorKind_CUSTOM_BASE 65536
+
+/**
+ * Everything a node or shared buffer can be asked to do.
+ *
+ * For nodes, the actions up to `Drop` have their own `RawNodeVtable` entries.
+ */
+enum Action
+#ifdef __cplusplus
+  : uint32_t
+#endif // __cplusplus
+ {
+  Action_Call = 0,
+  Action_List = 1,
+  Action_Get = 16,
+  Action_Set = 17,
+  Action_Remove = 18,
+  /**
+   * Sets a child unless it exists; the argument is a map with the entry
+   * to set. Returns a `Bool` telling if it was set.
+   */
+  Action_SetIfAbsent = 19,
+  Action_Clone = 32,
+  Action_Drop = 33,
+  Action_CloneWeak = 34,
+  Action_DropWeak = 35,
+  /**
+   * Tries to acquire a strong reference from a weak one; returns a `Bool`.
+   */
+  Action_Upgrade = 36,
+  /**
+   * Checks if the node has the Rust type whose `TypeId` is passed as blob;
+   * returns a `Bool`.
+   */
+  Action_TypeId = 48,
+  /**
+   * Returns a `NodeMeta` describing the node.
+   */
+  Action_Meta = 49,
+  /**
+   * Returns the `ABI_VERSION` the node was built against as `Unsigned`;
+   * the argument is the version of the caller.
+   */
+  Action_Handshake = 64,
+};
+#ifndef __cplusplus
+typedef uint32_t Action;
+#endif // __cplusplus
+
+typedef struct Vnodes Vnodes;
+
+typedef struct Vnodes *RawContextPtr;
+
+typedef struct Flags {
+  uint32_t bits;
+} Flags;
+#define Flags__NODE (Flags){ .bits = (uint32_t)1 }
+#define Flags__STRING (Flags){ .bits = (uint32_t)2 }
+#define Flags__INTEGER (Flags){ .bits = (uint32_t)4 }
+#define Flags__FLOAT (Flags){ .bits = (uint32_t)8 }
+#define Flags__BOOL (Flags){ .bits = (uint32_t)16 }
+#define Flags__INTERNED (Flags){ .bits = (uint32_t)32 }
+#define Flags__MAP (Flags){ .bits = (uint32_t)128 }
+#define Flags__BLOB (Flags){ .bits = (uint32_t)65536 }
+#define Flags__SIGNED (Flags){ .bits = (uint32_t)256 }
+#define Flags__ALLOCATED (Flags){ .bits = (uint32_t)512 }
+#define Flags__BOXED (Flags){ .bits = (uint32_t)1024 }
+#define Flags__ARRAY (Flags){ .bits = (uint32_t)2048 }
+#define Flags__SHARED (Flags){ .bits = (uint32_t)4096 }
+#define Flags__RESERVED (Flags){ .bits = (uint32_t)4294893632 }
+#define Flags_BLOB (Flags){ .bits = (uint32_t)(Flags__BLOB).bits }
+#define Flags_BLOB_BOXED (Flags){ .bits = (uint32_t)((Flags__BLOB).bits | (Flags__BOXED).bits) }
+#define Flags_BLOB_SHARED (Flags){ .bits = (uint32_t)((Flags__BLOB).bits | (Flags__SHARED).bits) }
+#define Flags_BOOL (Flags){ .bits = (uint32_t)(Flags__BOOL).bits }
+#define Flags_FLOAT (Flags){ .bits = (uint32_t)(Flags__FLOAT).bits }
+#define Flags_INTEGER (Flags){ .bits = (uint32_t)(Flags__INTEGER).bits }
+#define Flags_INTEGER_SIGNED (Flags){ .bits = (uint32_t)((Flags__INTEGER).bits | (Flags__SIGNED).bits) }
+#define Flags_INTERNED (Flags){ .bits = (uint32_t)(Flags__INTERNED).bits }
+#define Flags_INTERNED_PATH (Flags){ .bits = (uint32_t)((Flags__INTERNED).bits | (Flags__ARRAY).bits) }
+#define Flags_INTERNED_PATH_BUF (Flags){ .bits = (uint32_t)(((Flags__INTERNED).bits | (Flags__ARRAY).bits) | (Flags__BOXED).bits) }
+#define Flags_MAP (Flags){ .bits = (uint32_t)(Flags__MAP).bits }
+#define Flags_MAP_BOXED (Flags){ .bits = (uint32_t)((Flags__MAP).bits | (Flags__BOXED).bits) }
+#define Flags_NODE (Flags){ .bits = (uint32_t)(Flags__NODE).bits }
+#define Flags_NODE_BOXED (Flags){ .bits = (uint32_t)((Flags__NODE).bits | (Flags__BOXED).bits) }
+#define Flags_STRING (Flags){ .bits = (uint32_t)(Flags__STRING).bits }
+#define Flags_STRING_BOXED (Flags){ .bits = (uint32_t)((Flags__STRING).bits | (Flags__BOXED).bits) }
+#define Flags_STRING_SHARED (Flags){ .bits = (uint32_t)((Flags__STRING).bits | (Flags__SHARED).bits) }
+#define Flags_VALUE_ARRAY (Flags){ .bits = (uint32_t)(Flags__ARRAY).bits }
+#define Flags_VALUE_ARRAY_BOXED (Flags){ .bits = (uint32_t)((Flags__ARRAY).bits | (Flags__BOXED).bits) }
+#define Flags_VOID (Flags){ .bits = (uint32_t)0 }
+
+typedef struct Interned {
+  uint64_t _0;
+} Interned;
+
+/**
+ * An `Action` as passed through the raw interface.
+ *
+ * Receivers must not assume that it's a known action: codes added by newer
+ * versions are answered with `ErrorKind::ActionNotSupported`.
+ */
+typedef uint32_t RawAction;
+
+/**
+ * The functions implementing a node.
+ *
+ * The common operations have their own entries taking typed arguments;
+ * all other actions go through `action`.
+ */
+typedef struct RawNodeVtable {
+  /**
+   * Returns the child `ident`.
+   */
+  struct RawResult (*get)(struct RawNodeData*, struct Vnodes*, struct Interned);
+  /**
+   * Sets the child `ident`, taking ownership of the value.
+   */
+  struct RawResult (*set)(struct RawNodeData*, struct Vnodes*, struct Interned, struct RawValue);
+  /**
+   * Calls the node, taking ownership of the arguments;
+   * multiple arguments are passed as value array.
+   */
+  struct RawResult (*call)(struct RawNodeData*, struct Vnodes*, struct RawValue);
+  /**
+   * Returns the identifiers of all children as array of interned values.
+   */
+  struct RawResult (*list)(struct RawNodeData*, struct Vnodes*);
+  /**
+   * Removes the child `ident`, returning its value.
+   */
+  struct RawResult (*remove)(struct RawNodeData*, struct Vnodes*, struct Interned);
+  /**
+   * Acquires another strong reference.
+   */
+  void (*clone)(struct RawNodeData*);
+  /**
+   * Releases a strong reference; the node is dropped with the last one.
+   */
+  void (*drop)(struct RawNodeData*);
+  /**
+   * Performs any other action; the actions above must be answered
+   * with `ErrorKind::ActionNotSupported`.
+   *
+   * ## Parameters
+   *
+   * 1. self pointer
+   * 2. pointer to the `vnodes` context
+   * 3. the requested action
+   * 4. argument(s)
+   */
+  struct RawResult (*action)(struct RawNodeData*, struct Vnodes*, RawAction, struct RawValue);
+} RawNodeVtable;
+
+typedef struct RawNodeData {
+  /**
+   * The functions implementing this node; usually a static shared by all
+   * nodes of the same type.
+   */
+  const struct RawNodeVtable *vtable;
+} RawNodeData;
+
+/**
+ * The header of a reference counted, immutable byte buffer.
+ */
+typedef struct RawSharedData {
+  /**
+   * A function pointer to the `action` function of this buffer.
+   * Only `Action::Clone` and `Action::Drop` are valid here.
+   *
+   * ## Parameters
+   *
+   * 1. self pointer
+   * 2. the requested action
+   */
+  void (*action)(struct RawSharedData*, RawAction);
+  /**
+   * Pointer to the first byte of the buffer.
+   */
+  const uint8_t *data;
+  /**
+   * The length of the buffer in bytes.
+   */
+  uintptr_t len;
+} RawSharedData;
+
+/**
+ * `float`, `signed` and `unsigned` are keywords in C, so they get a suffix there.
+ *
+ */
+typedef union RawValueInner {
+  const uint8_t *blob;
+  bool boolean;
+  double float_;
+  struct Interned interned;
+  const struct Interned *interned_path;
+  const RawMapEntry *map;
+  struct RawNodeData *node_data;
+  struct RawSharedData *shared;
+  int64_t signed_;
+  const uint8_t *string;
+  uint64_t unsigned_;
+  const struct RawValue *value_array;
+} RawValueInner;
+
+/**
+ * The C representation of a `Value`.
+ *
+ * ## Ownership
+ *
+ * A raw value owns its payload if it has the `_BOXED` or the `_SHARED` flag set;
+ * owned arrays and maps also own all of their elements.
+ * All other values are either plain data or borrow their payload from somewhere else.
+ *
+ * Passing a raw value by value transfers ownership of its payload.
+ * Owned payloads must be released exactly once, either by converting the raw value
+ * back using `Value::from_raw` or by calling `RawValue::drop_in_place`.
+ */
+typedef struct RawValue {
+  struct Flags flags;
+  uintptr_t extra;
+  union RawValueInner value;
+} RawValue;
+
+/**
+ * The result of a node action.
+ *
+ * `status` is zero on success, in which case `value` is the returned value.
+ * Otherwise, `status` is the code of an `ErrorKind` and `value` is either void
+ * or an owned string with an error message.
+ *
+ * Like `RawValue`, passing a raw result by value transfers ownership of its payload.
+ */
+typedef struct RawResult {
+  uint32_t status;
+  struct RawValue value;
+} RawResult;
+
+typedef struct RawNodeData *RawNodePtr;
+
+/**
+ * The entry point of a plugin, exported as static `VNODES_PLUGIN`.
+ *
+ * Rust plugins declare it with `export_plugin!`.
+ */
+typedef struct PluginDeclaration {
+  /**
+   * Must be `ABI_VERSION`.
+   */
+  uint32_t abi_version;
+  /**
+   * Creates the root node of the plugin and returns it as owned node value.
+   */
+  struct RawResult (*init)(RawContextPtr context);
+} PluginDeclaration;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/**
+ * Creates a new, empty tree; free it with `vnodes_context_free`.
+ */
+RawContextPtr vnodes_context_new(void);
+
+/**
+ * Frees a tree created by `vnodes_context_new`.
+ *
+ * # Safety
+ *
+ * `context` must be null or a tree returned by `vnodes_context_new`
+ * which has not been freed before.
+ */
+void vnodes_context_free(RawContextPtr context);
+
+/**
+ * Returns the value at `path`; the value is owned by the caller.
+ *
+ * # Safety
+ *
+ * `context` must be a valid tree and `path` must point to `path_len` bytes of UTF-8.
+ */
+struct RawResult vnodes_get(RawContextPtr context, const uint8_t *path, uintptr_t path_len);
+
+/**
+ * Inserts `value` at `path`, taking ownership of it.
+ *
+ * # Safety
+ *
+ * `context` must be a valid tree, `path` must point to `path_len` bytes of UTF-8
+ * and `value` must be a valid raw value.
+ */
+struct RawResult vnodes_insert(RawContextPtr context,
+                               const uint8_t *path,
+                               uintptr_t path_len,
+                               struct RawValue value);
+
+/**
+ * Removes the entry at `path`, returning its value.
+ *
+ * # Safety
+ *
+ * `context` must be a valid tree and `path` must point to `path_len` bytes of UTF-8.
+ */
+struct RawResult vnodes_remove(RawContextPtr context, const uint8_t *path, uintptr_t path_len);
+
+/**
+ * Lists the children of the node at `path` as an array of interned identifiers.
+ *
+ * # Safety
+ *
+ * `context` must be a valid tree and `path` must point to `path_len` bytes of UTF-8.
+ */
+struct RawResult vnodes_list(RawContextPtr context, const uint8_t *path, uintptr_t path_len);
+
+/**
+ * Calls the node at `path`, taking ownership of `args`.
+ *
+ * Multiple arguments are passed as value array.
+ *
+ * # Safety
+ *
+ * `context` must be a valid tree, `path` must point to `path_len` bytes of UTF-8
+ * and `args` must be a valid raw value.
+ */
+struct RawResult vnodes_call(RawContextPtr context,
+                             const uint8_t *path,
+                             uintptr_t path_len,
+                             struct RawValue args);
+
+/**
+ * Interns the `len` bytes at `ptr`.
+ *
+ * Returns an interned value with all bits set (`UINT64_MAX`) if the bytes
+ * aren't a name of at most 10 ASCII letters, digits, `-`, `_` and `.`;
+ * no name interns to it.
+ *
+ * # Safety
+ *
+ * `ptr` must point to `len` bytes.
+ */
+struct Interned vnodes_intern(const uint8_t *ptr, uintptr_t len);
+
+/**
+ * Writes the name of `interned` to `buf` and returns its length.
+ *
+ * Codes which don't stand for a character are written as `?`.
+ *
+ * # Safety
+ *
+ * `buf` must be valid for writes of 10 bytes.
+ */
+uintptr_t vnodes_un_intern(struct Interned interned, uint8_t *buf);
+
+struct RawValue vnodes_value_void(void);
+
+struct RawValue vnodes_value_bool(bool value);
+
+struct RawValue vnodes_value_signed(int64_t value);
+
+struct RawValue vnodes_value_unsigned(uint64_t value);
+
+struct RawValue vnodes_value_float(double value);
+
+struct RawValue vnodes_value_interned(struct Interned value);
+
+/**
+ * Creates an owned string value by copying `len` bytes of UTF-8 from `ptr`.
+ *
+ * # Safety
+ *
+ * `ptr` must point to `len` bytes.
+ */
+struct RawValue vnodes_value_string(const uint8_t *ptr, uintptr_t len);
+
+/**
+ * Creates an owned blob value by copying `len` bytes from `ptr`.
+ *
+ * # Safety
+ *
+ * `ptr` must point to `len` bytes.
+ */
+struct RawValue vnodes_value_blob(const uint8_t *ptr, uintptr_t len);
+
+/**
+ * Creates an owned node value holding a new strong handle to `node`.
+ *
+ * # Safety
+ *
+ * `node` must be a valid node.
+ */
+struct RawValue vnodes_value_node(RawNodePtr node);
+
+/**
+ * Creates an owned array, moving the `len` values at `values` into it.
+ *
+ * The values must not be used or freed afterwards. Returns void if one of
+ * them has unknown flags, in which case the values are leaked.
+ *
+ * # Safety
+ *
+ * `values` must point to `len` valid raw values.
+ */
+struct RawValue vnodes_value_array(const struct RawValue *values, uintptr_t len);
+
+/**
+ * Creates a deep copy of `*value`; returns void if it has unknown flags.
+ *
+ * # Safety
+ *
+ * `value` must point to a valid raw value.
+ */
+struct RawValue vnodes_value_clone(const struct RawValue *value);
+
+/**
+ * Releases the payload of `*value` (if it's owned) and resets it to void.
+ *
+ * Values with unknown flags are leaked.
+ *
+ * # Safety
+ *
+ * `value` must be null or point to a valid raw value.
+ */
+void vnodes_value_free(struct RawValue *value);
+
+/**
+ * Releases the value or error message of `*result` and resets it to a void success.
+ *
+ * # Safety
+ *
+ * `result` must be null or point to a valid raw result.
+ */
+void vnodes_result_free(struct RawResult *result);
+
+/**
+ * Acquires another strong reference to `node`.
+ *
+ * # Safety
+ *
+ * `node` must be a valid node.
+ */
+RawNodePtr vnodes_node_clone(RawNodePtr node);
+
+/**
+ * Releases a strong reference to `node`; the node is freed with the last one.
+ *
+ * # Safety
+ *
+ * `node` must be null or a strong reference owned by the caller.
+ */
+void vnodes_node_drop(RawNodePtr node);
+
+#ifdef __cplusplus
+} // extern "C"
+#endif // __cplusplus
+
+/**
+ * A key / value pair of a `MAP` value.
+ *
+ * The entries of a map are always sorted by key and each key occurs only once.
+ */
+struct RawMapEntry {
+  struct Interned key;
+  struct RawValue value;
+};
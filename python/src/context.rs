@@ -0,0 +1,96 @@
+use std::sync::Arc;
+
+use pyo3::prelude::*;
+use pyo3::types::PyTuple;
+
+use vnodes::{Value, Vnodes};
+
+use crate::conv::{args_from_py, from_py, to_py};
+use crate::error::{path, to_py_err};
+
+/// A vnodes context; paths are given as strings like `"/a/b"`.
+///
+/// Also supports `nodes[path]`, `nodes[path] = value`, `del nodes[path]`
+/// and `path in nodes`.
+#[pyclass(name = "Vnodes", module = "vnodes", frozen)]
+pub struct PyVnodes {
+    nodes: Arc<Vnodes>,
+}
+
+impl PyVnodes {
+    /// Wraps a context shared with Rust code.
+    pub fn new(nodes: Arc<Vnodes>) -> Self {
+        PyVnodes { nodes }
+    }
+
+    pub fn nodes(&self) -> &Arc<Vnodes> {
+        &self.nodes
+    }
+}
+
+#[pymethods]
+impl PyVnodes {
+    #[new]
+    fn py_new() -> Self {
+        PyVnodes::new(Arc::new(Vnodes::new()))
+    }
+
+    fn get(&self, py: Python, path: &str) -> PyResult<PyObject> {
+        let value: Value = self.nodes.get(self::path(path)?).map_err(to_py_err)?;
+
+        to_py(py, value)
+    }
+
+    fn insert(&self, path: &str, value: &Bound<PyAny>) -> PyResult<()> {
+        self.nodes
+            .insert(self::path(path)?, from_py(value)?)
+            .map_err(to_py_err)
+    }
+
+    /// Creates all missing parents of `path` as map nodes.
+    fn create_dirs(&self, path: &str) -> PyResult<()> {
+        self.nodes.create_dirs(self::path(path)?).map_err(to_py_err)
+    }
+
+    fn remove(&self, py: Python, path: &str) -> PyResult<PyObject> {
+        let value: Value = self.nodes.remove(self::path(path)?).map_err(to_py_err)?;
+
+        to_py(py, value)
+    }
+
+    fn list(&self, path: &str) -> PyResult<Vec<String>> {
+        let names = self.nodes.list(self::path(path)?).map_err(to_py_err)?;
+
+        Ok(names.iter().map(ToString::to_string).collect())
+    }
+
+    fn exists(&self, path: &str) -> PyResult<bool> {
+        Ok(self.nodes.exists(self::path(path)?))
+    }
+
+    #[pyo3(signature = (path, *args))]
+    fn call(&self, py: Python, path: &str, args: &Bound<PyTuple>) -> PyResult<PyObject> {
+        let value: Value = self
+            .nodes
+            .call(self::path(path)?, args_from_py(args)?)
+            .map_err(to_py_err)?;
+
+        to_py(py, value)
+    }
+
+    fn __getitem__(&self, py: Python, path: &str) -> PyResult<PyObject> {
+        self.get(py, path)
+    }
+
+    fn __setitem__(&self, path: &str, value: &Bound<PyAny>) -> PyResult<()> {
+        self.insert(path, value)
+    }
+
+    fn __delitem__(&self, py: Python, path: &str) -> PyResult<()> {
+        self.remove(py, path).map(drop)
+    }
+
+    fn __contains__(&self, path: &str) -> PyResult<bool> {
+        self.exists(path)
+    }
+}
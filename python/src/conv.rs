@@ -0,0 +1,125 @@
+use std::str::from_utf8;
+
+use pyo3::exceptions::{PyOverflowError, PyTypeError};
+use pyo3::prelude::*;
+use pyo3::types::{PyBool, PyBytes, PyDict, PyFloat, PyInt, PyList, PyString, PyTuple};
+use pyo3::IntoPyObjectExt;
+
+use vnodes::{ErrorKind, NodeHandle, Value};
+
+use crate::error::{intern, to_py_err};
+use crate::node::{PyNode, PyNodeBase, PyNodeHandle};
+
+/// Converts a value to the corresponding Python object.
+///
+/// Interned strings and paths become `str`, nodes implemented in Python are
+/// returned as the original object and other nodes as `NodeHandle`.
+pub fn to_py(py: Python, value: Value) -> PyResult<PyObject> {
+    match value.make_owned() {
+        Value::Blob(blob) => PyBytes::new(py, &blob).into_py_any(py),
+        Value::SharedBlob(blob) => PyBytes::new(py, blob.as_slice()).into_py_any(py),
+        Value::Bool(b) => b.into_py_any(py),
+        Value::Float(f) => f.into_py_any(py),
+        Value::Signed(i) => i.into_py_any(py),
+        Value::Unsigned(u) => u.into_py_any(py),
+        Value::Interned(interned) => interned.to_string().into_py_any(py),
+        Value::InternedPathBuf(path) => path.to_string().into_py_any(py),
+        Value::String(string) => string_to_py(py, &string),
+        Value::SharedString(string) => string_to_py(py, string.as_slice()),
+        Value::Map(map) => {
+            let dict = PyDict::new(py);
+            for (key, value) in map {
+                dict.set_item(key.to_string(), to_py(py, value)?)?;
+            }
+
+            dict.into_py_any(py)
+        }
+        Value::ValueArray(array) => {
+            let items = array
+                .into_iter()
+                .map(|value| to_py(py, value))
+                .collect::<PyResult<Vec<_>>>()?;
+
+            PyList::new(py, items)?.into_py_any(py)
+        }
+        Value::Node(node) => match node.downcast_ref::<PyNode>() {
+            Some(node) => Ok(node.object().clone_ref(py)),
+            None => PyNodeHandle::from(node).into_py_any(py),
+        },
+        Value::Void => Ok(py.None()),
+        value => Err(PyTypeError::new_err(format!(
+            "can't convert {} to a Python object",
+            value.kind()
+        ))),
+    }
+}
+
+fn string_to_py(py: Python, bytes: &[u8]) -> PyResult<PyObject> {
+    let string = from_utf8(bytes).map_err(|_| to_py_err(ErrorKind::Utf8Error.into()))?;
+
+    string.into_py_any(py)
+}
+
+/// Converts a Python object to a value.
+///
+/// `dict` keys have to be strings which can be interned, `list`s and `tuple`s
+/// become value arrays and instances of `vnodes.Node` become nodes.
+pub fn from_py(object: &Bound<PyAny>) -> PyResult<Value<'static>> {
+    if object.is_none() {
+        Ok(Value::Void)
+    } else if let Ok(b) = object.downcast::<PyBool>() {
+        // `bool` is a subclass of `int`, so it needs to come first
+        Ok(Value::Bool(b.is_true()))
+    } else if object.is_instance_of::<PyInt>() {
+        if let Ok(i) = object.extract::<i64>() {
+            Ok(match i < 0 {
+                true => Value::Signed(i),
+                false => Value::Unsigned(i as u64),
+            })
+        } else if let Ok(u) = object.extract::<u64>() {
+            Ok(Value::Unsigned(u))
+        } else {
+            Err(PyOverflowError::new_err("int doesn't fit into 64 bits"))
+        }
+    } else if let Ok(f) = object.downcast::<PyFloat>() {
+        Ok(Value::Float(f.value()))
+    } else if let Ok(s) = object.downcast::<PyString>() {
+        Ok(Value::String(s.to_str()?.as_bytes().into()))
+    } else if let Ok(bytes) = object.downcast::<PyBytes>() {
+        Ok(Value::Blob(bytes.as_bytes().into()))
+    } else if let Ok(dict) = object.downcast::<PyDict>() {
+        dict.iter()
+            .map(|(key, value)| Ok((intern(key.downcast::<PyString>()?.to_str()?)?, from_py(&value)?)))
+            .collect::<PyResult<_>>()
+            .map(Value::Map)
+    } else if let Ok(list) = object.downcast::<PyList>() {
+        list.iter()
+            .map(|item| from_py(&item))
+            .collect::<PyResult<_>>()
+            .map(Value::ValueArray)
+    } else if let Ok(tuple) = object.downcast::<PyTuple>() {
+        tuple
+            .iter()
+            .map(|item| from_py(&item))
+            .collect::<PyResult<_>>()
+            .map(Value::ValueArray)
+    } else if let Ok(handle) = object.downcast::<PyNodeHandle>() {
+        Ok(Value::Node(handle.get().handle().clone()))
+    } else if object.is_instance_of::<PyNodeBase>() {
+        Ok(Value::Node(NodeHandle::new(PyNode::new(object.clone().unbind()))))
+    } else {
+        Err(PyTypeError::new_err(format!(
+            "can't convert `{}` to a vnodes value",
+            object.get_type().name()?
+        )))
+    }
+}
+
+/// Converts the arguments of a Python call, using an array for any non-zero
+/// number of arguments so a single list argument is passed as one argument.
+pub fn args_from_py(args: &Bound<PyTuple>) -> PyResult<Value<'static>> {
+    match args.len() {
+        0 => Ok(Value::Void),
+        _ => from_py(args.as_any()),
+    }
+}
@@ -0,0 +1,71 @@
+use pyo3::exceptions::{
+    PyKeyError, PyNotImplementedError, PyOverflowError, PyTypeError, PyValueError,
+};
+use pyo3::prelude::*;
+use pyo3::types::PyTuple;
+
+use vnodes::{is_internable, Error, ErrorKind, Interned, InternedPathBuf};
+
+use crate::VnodesError;
+
+/// Maps a vnodes error to the closest Python exception.
+pub fn to_py_err(error: Error) -> PyErr {
+    let message = error.to_string();
+
+    match error.kind() {
+        ErrorKind::NoSuchEntry => PyKeyError::new_err(message),
+        ErrorKind::ActionNotSupported => PyNotImplementedError::new_err(message),
+        ErrorKind::ExpectedNode | ErrorKind::InvalidArgumentTypes | ErrorKind::WrongType => {
+            PyTypeError::new_err(message)
+        }
+        ErrorKind::OutOfRange => PyOverflowError::new_err(message),
+        kind => VnodesError::new_err((message, kind.code())),
+    }
+}
+
+/// Maps an exception raised by a Python node back to a vnodes error.
+///
+/// Exceptions without a vnodes counterpart are reported like a panicking node.
+pub fn from_py_err(py: Python, error: PyErr) -> Error {
+    let kind = if error.is_instance_of::<VnodesError>(py) {
+        let args = error.value(py).getattr("args").ok();
+        let code = args
+            .as_ref()
+            .and_then(|args| args.downcast::<PyTuple>().ok())
+            .and_then(|args| args.get_item(1).ok())
+            .and_then(|code| code.extract::<u32>().ok());
+
+        code.map(ErrorKind::from_code).unwrap_or(ErrorKind::Panicked)
+    } else if error.is_instance_of::<PyKeyError>(py) {
+        ErrorKind::NoSuchEntry
+    } else if error.is_instance_of::<PyNotImplementedError>(py) {
+        ErrorKind::ActionNotSupported
+    } else if error.is_instance_of::<PyTypeError>(py) {
+        ErrorKind::InvalidArgumentTypes
+    } else if error.is_instance_of::<PyOverflowError>(py) {
+        ErrorKind::OutOfRange
+    } else {
+        ErrorKind::Panicked
+    };
+
+    Error::new(kind).with_message(error.to_string())
+}
+
+/// Interns `name`, raising `ValueError` instead of panicking for names
+/// which can't be interned.
+pub fn intern(name: &str) -> PyResult<Interned> {
+    match is_internable(name) {
+        true => Ok(Interned::from(name)),
+        false => Err(PyValueError::new_err(format!(
+            "`{}` can't be interned; names have at most 10 of `a-z`, `0-9`, `-`, `_` and `.` \
+             (uppercase letters are folded to lowercase and `-` to `_`)",
+            name
+        ))),
+    }
+}
+
+pub fn path(path: &str) -> PyResult<InternedPathBuf> {
+    let components = path.split('/').map(intern).collect::<PyResult<Vec<_>>>()?;
+
+    Ok(InternedPathBuf::from_iter(components))
+}
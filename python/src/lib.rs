@@ -0,0 +1,39 @@
+//! Python bindings for vnodes.
+//!
+//! The `vnodes` module exposes a `Vnodes` context with path based access,
+//! converts values to and from Python objects and allows Python classes
+//! deriving from `vnodes.Node` to be mounted as nodes.
+//!
+//! Build it with the `extension-module` feature to load it from Python;
+//! Rust applications embedding an interpreter can register it using
+//! `pyo3::wrap_pymodule!(vnodes_python::vnodes_python)`.
+
+pub use context::PyVnodes;
+pub use node::{PyNode, PyNodeBase, PyNodeHandle};
+
+use pyo3::create_exception;
+use pyo3::prelude::*;
+
+mod context;
+mod conv;
+mod error;
+mod node;
+
+create_exception!(
+    vnodes,
+    VnodesError,
+    pyo3::exceptions::PyException,
+    "Raised for vnodes errors without a more specific Python exception; \
+     `args` are the message and the error code."
+);
+
+#[pymodule]
+#[pyo3(name = "vnodes")]
+pub fn vnodes_python(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<PyVnodes>()?;
+    m.add_class::<PyNodeBase>()?;
+    m.add_class::<PyNodeHandle>()?;
+    m.add("VnodesError", m.py().get_type::<VnodesError>())?;
+
+    Ok(())
+}
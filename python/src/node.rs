@@ -0,0 +1,155 @@
+use pyo3::prelude::*;
+use pyo3::types::PyTuple;
+use pyo3::IntoPyObjectExt;
+
+use vnodes::{ErrorKind, Interned, Node, NodeHandle, NodeMeta, Result, Value, Vnodes};
+
+use crate::conv::{from_py, to_py};
+use crate::error::{from_py_err, intern};
+
+/// Base class for nodes implemented in Python.
+///
+/// Subclasses implement any of `get(name)`, `set(name, value)`, `list()`,
+/// `remove(name)` and `call(*args)`; missing methods report the action as
+/// not supported.
+#[pyclass(name = "Node", module = "vnodes", subclass, frozen)]
+pub struct PyNodeBase;
+
+#[pymethods]
+impl PyNodeBase {
+    #[new]
+    fn new() -> Self {
+        PyNodeBase
+    }
+}
+
+/// A node forwarding to an instance of a `vnodes.Node` subclass.
+pub struct PyNode {
+    object: PyObject,
+}
+
+impl PyNode {
+    pub fn new(object: PyObject) -> Self {
+        PyNode { object }
+    }
+
+    pub fn object(&self) -> &PyObject {
+        &self.object
+    }
+
+    fn invoke<A, F, R>(&self, method: &str, args: A, f: F) -> Result<R>
+    where
+        A: FnOnce(Python) -> PyResult<Bound<PyTuple>>,
+        F: FnOnce(&Bound<PyAny>) -> PyResult<R>,
+    {
+        Python::with_gil(|py| {
+            let object = self.object.bind(py);
+            if !object.hasattr(method).map_err(|e| from_py_err(py, e))? {
+                return Err(ErrorKind::ActionNotSupported.into());
+            }
+
+            args(py)
+                .and_then(|args| object.call_method1(method, args))
+                .and_then(|ret| f(&ret))
+                .map_err(|e| from_py_err(py, e))
+        })
+    }
+}
+
+fn name(py: Python, ident: Interned) -> PyResult<Bound<PyTuple>> {
+    PyTuple::new(py, [ident.to_string()])
+}
+
+impl Node for PyNode {
+    fn call(&self, _context: &Vnodes, args: &[Value]) -> Result<Value<'_>> {
+        self.invoke(
+            "call",
+            |py| {
+                let args = args
+                    .iter()
+                    .map(|arg| to_py(py, arg.clone()))
+                    .collect::<PyResult<Vec<_>>>()?;
+
+                PyTuple::new(py, args)
+            },
+            from_py,
+        )
+    }
+
+    fn get(&self, _context: &Vnodes, ident: Interned) -> Result<Value<'_>> {
+        self.invoke("get", |py| name(py, ident), from_py)
+    }
+
+    fn list(&self, _context: &Vnodes) -> Result<Vec<Interned>> {
+        self.invoke(
+            "list",
+            |py| Ok(PyTuple::empty(py)),
+            |names| {
+                names
+                    .try_iter()?
+                    .map(|name| intern(name?.extract::<&str>()?))
+                    .collect()
+            },
+        )
+    }
+
+    fn remove(&self, _context: &Vnodes, ident: Interned) -> Result<Value<'static>> {
+        self.invoke("remove", |py| name(py, ident), from_py)
+    }
+
+    fn set(&self, _context: &Vnodes, ident: Interned, value: Value<'static>) -> Result<()> {
+        self.invoke(
+            "set",
+            |py| PyTuple::new(py, [ident.to_string().into_py_any(py)?, to_py(py, value)?]),
+            |_| Ok(()),
+        )
+    }
+
+    /// Reports the actions whose methods the subclass implements and its
+    /// docstring.
+    fn meta(&self, _context: &Vnodes) -> NodeMeta {
+        Python::with_gil(|py| {
+            let object = self.object.bind(py);
+            let implements = |method| object.hasattr(method).unwrap_or(false);
+
+            NodeMeta {
+                call: implements("call"),
+                list: implements("list"),
+                set: implements("set"),
+                remove: implements("remove"),
+                doc: object
+                    .getattr("__doc__")
+                    .and_then(|doc| doc.extract::<Option<String>>())
+                    .unwrap_or(None),
+                ..NodeMeta::new::<Self>()
+            }
+        })
+    }
+}
+
+/// A node which isn't implemented in Python.
+///
+/// Handles can't be used directly, but they can be stored in the tree again.
+#[pyclass(name = "NodeHandle", module = "vnodes", frozen)]
+pub struct PyNodeHandle {
+    handle: NodeHandle,
+}
+
+impl PyNodeHandle {
+    pub fn handle(&self) -> &NodeHandle {
+        &self.handle
+    }
+}
+
+impl From<NodeHandle> for PyNodeHandle {
+    fn from(handle: NodeHandle) -> Self {
+        PyNodeHandle { handle }
+    }
+}
+
+#[pymethods]
+impl PyNodeHandle {
+    fn __repr__(&self) -> String {
+        "<vnodes.NodeHandle>".to_owned()
+    }
+}
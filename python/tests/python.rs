@@ -0,0 +1,231 @@
+use std::ffi::CString;
+use std::sync::Arc;
+
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+use pyo3::wrap_pymodule;
+
+use vnodes::{Error, ErrorKind, Interned, MapNode, Node, NodeHandle, Result, Value, ValueKind, Vnodes};
+use vnodes_python::PyVnodes;
+
+/// Runs `code` in the embedded interpreter, with the context available as `nodes`.
+fn run(nodes: &Arc<Vnodes>, code: &str) {
+    Python::with_gil(|py| {
+        let module = wrap_pymodule!(vnodes_python::vnodes_python)(py);
+        let modules = py.import("sys").unwrap().getattr("modules").unwrap();
+        modules.set_item("vnodes", module).unwrap();
+
+        let globals = PyDict::new(py);
+        let context = Bound::new(py, PyVnodes::new(nodes.clone())).unwrap();
+        globals.set_item("nodes", context).unwrap();
+
+        let code = CString::new(format!("import vnodes\n{}", code)).unwrap();
+        if let Err(e) = py.run(&code, Some(&globals), None) {
+            e.print(py);
+            panic!("{}", e);
+        }
+    });
+}
+
+/// A Rust node reporting a custom error for every entry.
+struct Unloaded;
+
+impl Node for Unloaded {
+    fn get(&self, _: &Vnodes, _: Interned) -> Result<Value<'_>> {
+        Err(Error::custom(4, "asset not loaded"))
+    }
+}
+
+#[test]
+fn values() {
+    let nodes = Arc::new(Vnodes::new());
+    nodes.insert("/answer", 42u64).unwrap();
+    nodes.insert("/offset", -3i64).unwrap();
+    nodes.insert("/name", "vnodes").unwrap();
+    nodes.insert("/key", Interned::from("pos")).unwrap();
+    nodes.insert("/pair", (1u64, 0.5f64)).unwrap();
+
+    run(
+        &nodes,
+        r#"
+assert nodes.get("/answer") == 42
+assert nodes["/offset"] == -3
+assert nodes["/name"] == "vnodes"
+assert nodes["/key"] == "pos"
+assert nodes["/pair"] == [1, 0.5]
+
+nodes["/flag"] = True
+nodes["/data"] = b"\x00\x01"
+nodes["/nothing"] = None
+nodes["/config"] = {"width": 800, "title": "demo", "tags": ["a", "b"]}
+assert nodes["/config"] == {"width": 800, "title": "demo", "tags": ["a", "b"]}
+"#,
+    );
+
+    assert_eq!(nodes.get::<_, bool>("/flag").unwrap(), true);
+    assert_eq!(&*nodes.get::<_, Box<[u8]>>("/data").unwrap(), &[0, 1]);
+    assert_eq!(nodes.type_of("/nothing"), Ok(Some(ValueKind::Void)));
+    assert_eq!(nodes.type_of("/config"), Ok(Some(ValueKind::Map)));
+}
+
+#[test]
+fn paths() {
+    let nodes = Arc::new(Vnodes::new());
+
+    run(
+        &nodes,
+        r#"
+nodes.create_dirs("/a/b")
+nodes.insert("/a/b/c", 1)
+assert "/a/b/c" in nodes
+assert nodes.exists("/a/b")
+assert "/a/x" not in nodes
+assert nodes.list("/a") == ["b"]
+
+assert nodes.remove("/a/b/c") == 1
+del nodes["/a/b"]
+assert nodes.list("/a") == []
+"#,
+    );
+}
+
+#[test]
+fn errors() {
+    let nodes = Arc::new(Vnodes::new());
+    nodes.insert("/number", 1u64).unwrap();
+    nodes.insert("/unloaded", NodeHandle::new(Unloaded)).unwrap();
+
+    run(
+        &nodes,
+        r#"
+def raises(exception, f, *args):
+    try:
+        f(*args)
+    except exception as e:
+        return e
+    raise AssertionError("expected " + exception.__name__)
+
+raises(KeyError, nodes.get, "/missing")
+raises(TypeError, nodes.get, "/number/x")
+e = raises(ValueError, nodes.get, "/not allowed")
+assert "folded" in str(e)
+nodes.insert("/Fold-Name", 1)
+assert nodes.get("/fold_name") == 1
+raises(ValueError, nodes.insert, "/much_too_long", 1)
+raises(ValueError, nodes.insert, "/map", {"much_too_long": 1})
+raises(OverflowError, nodes.insert, "/big", 2 ** 64)
+raises(TypeError, nodes.insert, "/object", object())
+nodes.create_dirs("/dir/x")
+raises(NotImplementedError, nodes.call, "/dir")
+
+e = raises(vnodes.VnodesError, nodes.get, "/unloaded/cube")
+assert e.args[1] == 0x1_0004
+"#,
+    );
+}
+
+#[test]
+fn python_nodes() {
+    let nodes = Arc::new(Vnodes::new());
+
+    run(
+        &nodes,
+        r#"
+class Counter(vnodes.Node):
+    """Counts calls."""
+
+    def __init__(self):
+        self.values = {"count": 0}
+
+    def get(self, name):
+        return self.values[name]
+
+    def set(self, name, value):
+        self.values[name] = value
+
+    def list(self):
+        return list(self.values)
+
+    def call(self, step=1):
+        self.values["count"] += step
+        return self.values["count"]
+
+counter = Counter()
+nodes["/counter"] = counter
+assert nodes["/counter"] is counter
+assert nodes.call("/counter") == 1
+assert nodes.call("/counter", 2) == 3
+"#,
+    );
+
+    assert_eq!(nodes.get::<_, u64>("/counter/count"), Ok(3));
+    assert_eq!(nodes.call::<_, _, u64>("/counter", 4u64), Ok(7));
+    nodes.insert("/counter/step", 2u64).unwrap();
+    assert_eq!(nodes.get::<_, u64>("/counter/step"), Ok(2));
+
+    let mut names = nodes.list("/counter").unwrap();
+    names.sort_by_key(|i| i.into_inner());
+    assert_eq!(names, vec![Interned::from("step"), Interned::from("count")]);
+
+    let error = nodes.get::<_, Value>("/counter/missing").unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::NoSuchEntry);
+    let error = nodes.call::<_, _, Value>("/counter", "a").unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::InvalidArgumentTypes);
+    let error = nodes.remove::<_, Value>("/counter/count").unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::ActionNotSupported);
+
+    let meta = nodes.meta("/counter").unwrap();
+    assert!(meta.call && meta.list && meta.set);
+    assert!(!meta.remove);
+    assert_eq!(meta.doc.as_ref().map(|doc| &doc[..]), Some("Counts calls."));
+}
+
+#[test]
+fn failing_python_nodes() {
+    let nodes = Arc::new(Vnodes::new());
+    nodes.insert("/unloaded", NodeHandle::new(Unloaded)).unwrap();
+
+    run(
+        &nodes,
+        r#"
+class Failing(vnodes.Node):
+    def get(self, name):
+        raise RuntimeError("broken " + name)
+
+    def call(self):
+        return nodes.get("/unloaded/cube")
+
+nodes["/failing"] = Failing()
+"#,
+    );
+
+    let error = nodes.get::<_, Value>("/failing/x").unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::Panicked);
+    assert!(error.message().unwrap().contains("broken x"));
+
+    // Errors of nested calls keep their kind
+    let error = nodes.call::<_, _, Value>("/failing", ()).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::Custom(4));
+}
+
+#[test]
+fn node_handles() {
+    let nodes = Arc::new(Vnodes::new());
+    nodes.insert("/map", MapNode::new_node()).unwrap();
+    nodes.insert("/map/value", 5u64).unwrap();
+
+    run(
+        &nodes,
+        r#"
+handle = nodes["/map"]
+assert isinstance(handle, vnodes.NodeHandle)
+nodes["/copy"] = handle
+nodes["/copy/other"] = 6
+"#,
+    );
+
+    let map: NodeHandle = nodes.get("/map").unwrap();
+    let copy: NodeHandle = nodes.get("/copy").unwrap();
+    assert_eq!(map.as_raw(), copy.as_raw());
+    assert_eq!(nodes.get::<_, u64>("/map/other"), Ok(6));
+}
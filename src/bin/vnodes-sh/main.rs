@@ -0,0 +1,500 @@
+//! An interactive shell for browsing and editing a vnodes tree.
+//!
+//! ```text
+//! vnodes-sh [--mount PATH=DIR]... [FILE]
+//! ```
+//!
+//! Directories are mounted read-only as `FsNode`s, then the commands in
+//! `FILE` are run before reading commands from stdin. Type `help` for the
+//! list of commands.
+
+extern crate rustyline;
+extern crate vnodes;
+
+use std::cell::RefCell;
+use std::env;
+use std::fs;
+use std::io::{self, BufRead, IsTerminal};
+use std::iter::once;
+use std::process;
+use std::rc::Rc;
+
+use rustyline::completion::{Completer, Pair};
+use rustyline::error::ReadlineError;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::history::DefaultHistory;
+use rustyline::validate::Validator;
+use rustyline::{Context, Editor, Helper};
+
+use vnodes::console::{self, Command};
+use vnodes::{is_internable, Error, FsNode, Interned, InternedPathBuf, Value, ValueKind, Vnodes};
+
+const COMMANDS: &[&str] = &[
+    "call", "cd", "exit", "get", "help", "ls", "mkdir", "pwd", "quit", "rm", "set", "tree",
+];
+
+const HELP: &str = "\
+ls [PATH]              list the children of a node, nodes end with `/`
+cd [PATH]              change the working node, the root by default
+pwd                    print the working node
+get PATH               print a value
+/PATH                  print a value, like `get /PATH`
+set PATH VALUE...      set a value; several values are stored as an array
+rm PATH                remove a value and print it
+call PATH [ARG...]     call a node and print the result
+mkdir PATH             create a node and all missing parents
+tree [PATH]            print a node and its children recursively
+help                   print this help
+exit, quit             leave the shell
+
+Values are `true` and `false`, integers, floats, \"quoted strings\",
+absolute paths or bare words, which are strings.";
+
+/// The number of levels printed by `tree`; nodes can contain themselves.
+const TREE_DEPTH: usize = 8;
+
+/// An error aborting a command.
+struct Failure(String);
+
+impl From<String> for Failure {
+    fn from(message: String) -> Self {
+        Failure(message)
+    }
+}
+
+impl From<Error> for Failure {
+    fn from(e: Error) -> Self {
+        Failure(e.to_string())
+    }
+}
+
+enum Flow {
+    Continue,
+    Exit,
+}
+
+struct Shell {
+    nodes: Vnodes,
+    /// The working node, as components below the root.
+    cwd: RefCell<Vec<Interned>>,
+}
+
+impl Shell {
+    fn new() -> Self {
+        Shell {
+            nodes: Vnodes::new(),
+            cwd: RefCell::new(Vec::new()),
+        }
+    }
+
+    /// Resolves `path` against the working node, handling `.` and `..`.
+    fn resolve(&self, path: &str) -> Result<Vec<Interned>, String> {
+        let mut components = match path.starts_with('/') {
+            true => Vec::new(),
+            false => self.cwd.borrow().clone(),
+        };
+
+        for component in path.split('/') {
+            match component {
+                "" | "." => {}
+                ".." => {
+                    components.pop();
+                }
+                component if is_internable(component) => components.push(Interned::from(component)),
+                component => return Err(format!("invalid name `{}`", component)),
+            }
+        }
+
+        Ok(components)
+    }
+
+    fn prompt(&self) -> String {
+        format!("{}> ", display(&self.cwd.borrow()))
+    }
+
+    fn run(&self, line: &str) -> Result<Flow, Failure> {
+        if line.trim_start().starts_with('#') {
+            return Ok(Flow::Continue);
+        }
+
+        let words = console::split(line)?;
+        let (command, args) = match words.split_first() {
+            Some((command, args)) => (command.as_str(), args),
+            None => return Ok(Flow::Continue),
+        };
+
+        let path = |usage: &str| match args.first() {
+            Some(path) => self.resolve(path.as_str()),
+            None => Err(format!("usage: {}", usage)),
+        };
+        let path_or_cwd = || match args.first() {
+            Some(path) => self.resolve(path.as_str()),
+            None => Ok(self.cwd.borrow().clone()),
+        };
+
+        match command {
+            "ls" => self.ls(&path_or_cwd()?)?,
+            "cd" => {
+                let path = match args.first() {
+                    Some(_) => path_or_cwd()?,
+                    None => Vec::new(),
+                };
+                match self.nodes.type_of(absolute(&path))? {
+                    Some(ValueKind::Node) => *self.cwd.borrow_mut() = path,
+                    _ => return Err(Failure(format!("`{}` is not a node", display(&path)))),
+                }
+            }
+            "pwd" => println!("{}", display(&self.cwd.borrow())),
+            // Paths are relative to the working directory, so the console
+            // commands are built here instead of parsed from the line
+            "get" => {
+                let value = Command::Get(absolute(&path("get PATH")?)).run(&self.nodes)?;
+                println!("{}", console::format(value, 0));
+            }
+            "set" => {
+                let path = path("set PATH VALUE...")?;
+                if args.len() < 2 {
+                    return Err(Failure("usage: set PATH VALUE...".to_owned()));
+                }
+                Command::Set(absolute(&path), console::literals(&args[1..])?).run(&self.nodes)?;
+            }
+            "rm" => print_result(Command::Remove(absolute(&path("rm PATH")?)).run(&self.nodes)?),
+            "call" => {
+                let path = path("call PATH [ARG...]")?;
+                let args = console::literals(&args[1..])?;
+                print_result(Command::Call(absolute(&path), args).run(&self.nodes)?);
+            }
+            "mkdir" => self.nodes.create_dirs(absolute(&path("mkdir PATH")?))?,
+            "tree" => {
+                let path = path_or_cwd()?;
+                println!("{}", display(&path));
+                self.tree(&path, 1)?;
+            }
+            "help" => println!("{}", HELP),
+            "exit" | "quit" => return Ok(Flow::Exit),
+            // A path on its own prints the value, like in the console
+            other if other.starts_with('/') && args.is_empty() => {
+                println!("{}", console::execute(&self.nodes, line)?)
+            }
+            other => return Err(Failure(format!("unknown command `{}`, try `help`", other))),
+        }
+
+        Ok(Flow::Continue)
+    }
+
+    /// Returns the sorted children of the node at `path` and whether they're nodes.
+    fn children(&self, path: &[Interned]) -> Result<Vec<(Interned, bool)>, Error> {
+        let mut children = Vec::new();
+        for name in self.nodes.list(absolute(path))? {
+            let child = path.iter().cloned().chain(once(name)).collect::<Vec<_>>();
+            let is_node = self.nodes.type_of(absolute(&child))?;
+            children.push((name, is_node == Some(ValueKind::Node)));
+        }
+        children.sort_by_key(|&(name, _)| name.to_string());
+
+        Ok(children)
+    }
+
+    fn ls(&self, path: &[Interned]) -> Result<(), Error> {
+        for (name, is_node) in self.children(path)? {
+            println!("{}{}", name, if is_node { "/" } else { "" });
+        }
+
+        Ok(())
+    }
+
+    fn tree(&self, path: &[Interned], depth: usize) -> Result<(), Failure> {
+        let indent = 2 * depth;
+        if depth > TREE_DEPTH {
+            println!("{:1$}...", "", indent);
+            return Ok(());
+        }
+
+        for (name, is_node) in self.children(path)? {
+            let mut child = path.to_vec();
+            child.push(name);
+
+            if is_node {
+                println!("{:1$}{2}/", "", indent, name);
+                self.tree(&child, depth + 1)?;
+            } else {
+                let value: Value = self.nodes.get(absolute(&child))?;
+                println!(
+                    "{:1$}{2} = {3}",
+                    "",
+                    indent,
+                    name,
+                    console::format(value, depth)
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Mounts the directory `dir` at `path`, which is given as `PATH=DIR`.
+    fn mount(&self, mount: &str) -> Result<(), Failure> {
+        let (path, dir) = match mount.find('=') {
+            Some(i) => (&mount[..i], &mount[i + 1..]),
+            None => return Err(Failure(format!("expected `PATH=DIR`, got `{}`", mount))),
+        };
+        if !fs::metadata(dir).map(|m| m.is_dir()).unwrap_or(false) {
+            return Err(Failure(format!("`{}` is not a directory", dir)));
+        }
+
+        let path = self.resolve(path)?;
+        self.nodes
+            .insert_all(absolute(&path), FsNode::new_node(dir))?;
+
+        Ok(())
+    }
+}
+
+/// Completes command names and the names of children.
+struct ShellHelper {
+    shell: Rc<Shell>,
+}
+
+impl Completer for ShellHelper {
+    type Candidate = Pair;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _: &Context,
+    ) -> rustyline::Result<(usize, Vec<Pair>)> {
+        let line = &line[..pos];
+        let start = line.rfind(char::is_whitespace).map(|i| i + 1).unwrap_or(0);
+        let word = &line[start..];
+
+        if start == 0 {
+            let commands = COMMANDS
+                .iter()
+                .filter(|command| command.starts_with(word))
+                .map(|command| Pair {
+                    display: command.to_string(),
+                    replacement: format!("{} ", command),
+                })
+                .collect();
+
+            return Ok((0, commands));
+        }
+
+        // Completes the last component of `word`
+        let (dir, prefix) = word.split_at(word.rfind('/').map(|i| i + 1).unwrap_or(0));
+        let children = self
+            .shell
+            .resolve(dir)
+            .ok()
+            .and_then(|path| self.shell.children(&path).ok())
+            .unwrap_or_default();
+        let prefix = prefix.to_lowercase().replace('-', "_");
+
+        let candidates = children
+            .into_iter()
+            .map(|(name, is_node)| (name.to_string(), is_node))
+            .filter(|(name, _)| name.starts_with(&prefix))
+            .map(|(name, is_node)| {
+                let name = if is_node { name + "/" } else { name };
+
+                Pair {
+                    replacement: format!("{}{}", dir, name),
+                    display: name,
+                }
+            })
+            .collect();
+
+        Ok((start, candidates))
+    }
+}
+
+impl Hinter for ShellHelper {
+    type Hint = String;
+}
+
+impl Highlighter for ShellHelper {}
+
+impl Validator for ShellHelper {}
+
+impl Helper for ShellHelper {}
+
+/// Returns the absolute path of the components below the root.
+fn absolute(components: &[Interned]) -> InternedPathBuf {
+    InternedPathBuf::from_iter(once(Interned(0)).chain(components.iter().cloned()))
+}
+
+fn display(components: &[Interned]) -> String {
+    match components.len() {
+        0 => "/".to_owned(),
+        _ => absolute(components).to_string(),
+    }
+}
+
+/// Prints the result of `call` and `rm`, omitting `void`.
+fn print_result(value: Value) {
+    match value {
+        Value::Void => {}
+        value => println!("{}", console::format(value, 0)),
+    }
+}
+
+/// Runs the commands of the script `file`, stopping at the first error.
+fn run_script(shell: &Shell, file: &str) -> Result<Flow, String> {
+    let script = fs::read_to_string(file).map_err(|e| format!("{}: {}", file, e))?;
+
+    for (number, line) in script.lines().enumerate() {
+        match shell.run(line) {
+            Ok(Flow::Continue) => {}
+            Ok(Flow::Exit) => return Ok(Flow::Exit),
+            Err(Failure(message)) => return Err(format!("{}:{}: {}", file, number + 1, message)),
+        }
+    }
+
+    Ok(Flow::Continue)
+}
+
+fn interactive(shell: Rc<Shell>) -> rustyline::Result<()> {
+    let mut editor = Editor::<ShellHelper, DefaultHistory>::new()?;
+    editor.set_helper(Some(ShellHelper {
+        shell: shell.clone(),
+    }));
+
+    loop {
+        let line = match editor.readline(&shell.prompt()) {
+            Ok(line) => line,
+            Err(ReadlineError::Interrupted) => continue,
+            Err(ReadlineError::Eof) => return Ok(()),
+            Err(e) => return Err(e),
+        };
+        editor.add_history_entry(line.as_str())?;
+
+        match shell.run(&line) {
+            Ok(Flow::Continue) => {}
+            Ok(Flow::Exit) => return Ok(()),
+            Err(Failure(message)) => eprintln!("error: {}", message),
+        }
+    }
+}
+
+/// Runs the commands piped to stdin, without prompts.
+fn piped(shell: &Shell) -> io::Result<()> {
+    let stdin = io::stdin();
+    for line in stdin.lock().lines() {
+        match shell.run(&line?) {
+            Ok(Flow::Continue) => {}
+            Ok(Flow::Exit) => break,
+            Err(Failure(message)) => eprintln!("error: {}", message),
+        }
+    }
+
+    Ok(())
+}
+
+fn usage() -> ! {
+    eprintln!("usage: vnodes-sh [--mount PATH=DIR]... [FILE]");
+    process::exit(2);
+}
+
+fn main() {
+    let shell = Rc::new(Shell::new());
+    let mut script = None;
+
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match &arg[..] {
+            "-m" | "--mount" => {
+                let mount = args.next().unwrap_or_else(|| usage());
+                if let Err(Failure(message)) = shell.mount(&mount) {
+                    eprintln!("error: {}", message);
+                    process::exit(1);
+                }
+            }
+            "-h" | "--help" => usage(),
+            _ if script.is_none() && !arg.starts_with('-') => script = Some(arg),
+            _ => usage(),
+        }
+    }
+
+    if let Some(file) = script {
+        match run_script(&shell, &file) {
+            Ok(Flow::Continue) => {}
+            Ok(Flow::Exit) => return,
+            Err(message) => {
+                eprintln!("error: {}", message);
+                process::exit(1);
+            }
+        }
+    }
+
+    let res = match io::stdin().is_terminal() {
+        true => interactive(shell).map_err(|e| e.to_string()),
+        false => piped(&shell).map_err(|e| e.to_string()),
+    };
+    if let Err(e) = res {
+        eprintln!("error: {}", e);
+        process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn complete(shell: &Rc<Shell>, line: &str) -> (usize, Vec<String>) {
+        let helper = ShellHelper {
+            shell: shell.clone(),
+        };
+        let history = DefaultHistory::new();
+        let (start, pairs) = helper
+            .complete(line, line.len(), &Context::new(&history))
+            .unwrap();
+
+        (
+            start,
+            pairs.into_iter().map(|pair| pair.replacement).collect(),
+        )
+    }
+
+    #[test]
+    fn check_resolve() {
+        let shell = Shell::new();
+        *shell.cwd.borrow_mut() = vec![Interned::from("a")];
+
+        assert_eq!(
+            shell.resolve("b/./c").unwrap(),
+            shell.resolve("/a/b/c").unwrap()
+        );
+        assert_eq!(shell.resolve("..").unwrap(), vec![]);
+        assert_eq!(shell.resolve("/../..").unwrap(), vec![]);
+        assert!(shell.resolve("not allowed").is_err());
+        assert_eq!(display(&shell.resolve("/A/b/").unwrap()), "/a/b");
+        assert_eq!(display(&[]), "/");
+    }
+
+    #[test]
+    fn check_complete() {
+        let shell = Rc::new(Shell::new());
+        shell.run("mkdir /graphics").ok().unwrap();
+        shell.run("mkdir /game").ok().unwrap();
+        shell.run("set /graphics/vsync true").ok().unwrap();
+
+        assert_eq!(
+            complete(&shell, "c"),
+            (0, vec!["call ".into(), "cd ".into()])
+        );
+        assert_eq!(
+            complete(&shell, "ls /g"),
+            (3, vec!["/game/".into(), "/graphics/".into()])
+        );
+        assert_eq!(
+            complete(&shell, "get /Graphics/V"),
+            (4, vec!["/Graphics/vsync".into()])
+        );
+        assert_eq!(complete(&shell, "ls /missing/"), (3, vec![]));
+
+        shell.run("cd graphics").ok().unwrap();
+        assert_eq!(complete(&shell, "get v"), (4, vec!["vsync".into()]));
+    }
+}
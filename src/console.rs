@@ -0,0 +1,490 @@
+//! Parsing console commands into path operations and pretty printing values.
+//!
+//! A line is a command followed by whitespace separated words:
+//!
+//! ```text
+//! get /player/health
+//! /player/health
+//! set /graphics/vsync true
+//! set /graphics/size 800 600
+//! call /world/spawn orc 3
+//! rm /world/orc
+//! ls /world
+//! ```
+//!
+//! A path on its own is a shorthand for `get`. Values are parsed as literals,
+//! see `literal`; more than one value is passed as an array.
+
+use std::fmt::Write;
+
+use {is_internable, Error, ErrorKind, Interned, InternedPathBuf, Result, Value, ValueArray};
+use {ValueConv, Vnodes};
+
+/// The number of bytes of a blob which are printed.
+const BLOB_BYTES: usize = 16;
+
+/// A parsed console command.
+#[derive(Debug)]
+pub enum Command {
+    Get(InternedPathBuf),
+    Set(InternedPathBuf, Value<'static>),
+    Call(InternedPathBuf, Value<'static>),
+    Remove(InternedPathBuf),
+    List(InternedPathBuf),
+}
+
+impl Command {
+    /// Parses `line`, returning `None` if it's empty.
+    pub fn parse(line: &str) -> Result<Option<Self>> {
+        let words = split(line)?;
+        let (command, args) = match words.split_first() {
+            Some((command, args)) => (command, args),
+            None => return Ok(None),
+        };
+
+        let command = match (command.as_str(), args.len()) {
+            ("get", 1) => Command::Get(path(args[0].as_str())?),
+            ("set", n) if n >= 2 => Command::Set(path(args[0].as_str())?, literals(&args[1..])?),
+            ("call", n) if n >= 1 => Command::Call(path(args[0].as_str())?, literals(&args[1..])?),
+            ("rm", 1) => Command::Remove(path(args[0].as_str())?),
+            ("ls", 0) => Command::List(InternedPathBuf::from_iter(Vec::<Interned>::new())),
+            ("ls", 1) => Command::List(path(args[0].as_str())?),
+            (s, 0) if s.starts_with('/') => Command::Get(path(s)?),
+            (name, _) => return Err(usage(name)),
+        };
+
+        Ok(Some(command))
+    }
+
+    /// Runs the command, returning the value it produced.
+    ///
+    /// `set` returns `Value::Void` and `ls` an array of the sorted names.
+    pub fn run(self, nodes: &Vnodes) -> Result<Value<'static>> {
+        match self {
+            Command::Get(path) => nodes.get(path),
+            Command::Set(path, value) => nodes.insert(path, value).map(|_| Value::Void),
+            Command::Call(path, args) => nodes.call(path, args),
+            Command::Remove(path) => nodes.remove(path),
+            Command::List(path) => {
+                let mut names = nodes.list(path)?;
+                names.sort_by_key(|name| name.to_string());
+
+                Ok(Value::ValueArray(
+                    names.into_iter().map(Value::Interned).collect(),
+                ))
+            }
+        }
+    }
+}
+
+/// Parses and runs `line`, returning the pretty printed result.
+///
+/// The result is empty for empty lines and commands returning `Value::Void`.
+pub fn execute(nodes: &Vnodes, line: &str) -> Result<String> {
+    match Command::parse(line)? {
+        Some(command) => match command.run(nodes)? {
+            Value::Void => Ok(String::new()),
+            value => Ok(format(value, 0)),
+        },
+        None => Ok(String::new()),
+    }
+}
+
+/// A word of a command line.
+#[derive(Debug, PartialEq)]
+pub enum Word {
+    /// A bare word, parsed as a literal when used as a value.
+    Bare(String),
+    /// A double quoted word, which is always a string.
+    Quoted(String),
+}
+
+impl Word {
+    pub fn as_str(&self) -> &str {
+        match *self {
+            Word::Bare(ref s) | Word::Quoted(ref s) => s,
+        }
+    }
+}
+
+/// Splits `line` at whitespace.
+///
+/// Double quotes group words containing whitespace; inside them `\"` and
+/// `\\` are escapes for a quote and a backslash.
+pub fn split(line: &str) -> Result<Vec<Word>> {
+    let mut words = Vec::new();
+    let mut chars = line.chars().peekable();
+
+    loop {
+        while chars.peek().map(|c| c.is_whitespace()).unwrap_or(false) {
+            chars.next();
+        }
+
+        match chars.peek().cloned() {
+            None => break,
+            Some('"') => {
+                chars.next();
+                let mut word = String::new();
+                loop {
+                    match chars.next() {
+                        None => return Err(syntax("unterminated quote")),
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some(c @ '"') | Some(c @ '\\') => word.push(c),
+                            Some(c) => return Err(syntax(format!("unknown escape `\\{}`", c))),
+                            None => return Err(syntax("unterminated quote")),
+                        },
+                        Some(c) => word.push(c),
+                    }
+                }
+                words.push(Word::Quoted(word));
+            }
+            Some(_) => {
+                let mut word = String::new();
+                while let Some(c) = chars.peek().cloned() {
+                    if c.is_whitespace() {
+                        break;
+                    }
+                    word.push(c);
+                    chars.next();
+                }
+                words.push(Word::Bare(word));
+            }
+        }
+    }
+
+    Ok(words)
+}
+
+/// Parses a word into a value.
+///
+/// Quoted words are strings; bare words are booleans (`true`, `false`),
+/// integers (unsigned unless negative), floats, absolute paths or strings,
+/// tried in that order.
+pub fn literal(word: &Word) -> Result<Value<'static>> {
+    let s = match *word {
+        Word::Quoted(ref s) => return Ok(s.clone().into_value()),
+        Word::Bare(ref s) => s,
+    };
+
+    let value = match &s[..] {
+        "true" => Value::Bool(true),
+        "false" => Value::Bool(false),
+        s if s.starts_with('/') => Value::InternedPathBuf(path(s)?),
+        s => {
+            // Don't turn words like `inf` or `nan` into floats
+            let float = s
+                .parse()
+                .ok()
+                .filter(|_| s.contains(|c: char| c.is_ascii_digit()));
+
+            if let Ok(u) = s.parse() {
+                Value::Unsigned(u)
+            } else if let Ok(i) = s.parse() {
+                Value::Signed(i)
+            } else if let Some(f) = float {
+                Value::Float(f)
+            } else {
+                s.to_owned().into_value()
+            }
+        }
+    };
+
+    Ok(value)
+}
+
+/// Parses the words into a single value; more than one word gives an array.
+pub fn literals(words: &[Word]) -> Result<Value<'static>> {
+    match words.len() {
+        0 => Ok(Value::Void),
+        1 => literal(&words[0]),
+        _ => words
+            .iter()
+            .map(literal)
+            .collect::<Result<ValueArray>>()
+            .map(Value::ValueArray),
+    }
+}
+
+/// Parses an absolute or relative path, rejecting names which can't be interned.
+///
+/// Unlike `InternedPathBuf::from`, this doesn't panic and `/` is the root.
+pub fn path(s: &str) -> Result<InternedPathBuf> {
+    let (root, s) = match s.starts_with('/') {
+        true => (Some(Interned(0)), s[1..].trim_end_matches('/')),
+        false => (None, s.trim_end_matches('/')),
+    };
+    let mut components: Vec<_> = root.into_iter().collect();
+
+    if !s.is_empty() {
+        for name in s.split('/') {
+            if name.is_empty() || !is_internable(name) {
+                return Err(syntax(format!("invalid name `{}`", name)));
+            }
+            components.push(Interned::from(name));
+        }
+    }
+
+    Ok(InternedPathBuf::from_iter(components))
+}
+
+/// Formats `value` for humans.
+///
+/// Maps are printed with one entry per line, indented by two spaces for
+/// each level of `indent`.
+pub fn format(value: Value, indent: usize) -> String {
+    let mut out = String::new();
+    write(&mut out, value.make_owned(), indent);
+
+    out
+}
+
+fn write(out: &mut String, value: Value<'static>, indent: usize) {
+    match value {
+        Value::Blob(bytes) => blob(out, &bytes),
+        Value::SharedBlob(bytes) => blob(out, bytes.as_slice()),
+        Value::Bool(b) => write!(out, "{}", b).unwrap(),
+        Value::Float(f) => write!(out, "{:?}", f).unwrap(),
+        Value::Interned(i) => write!(out, "{}", i).unwrap(),
+        Value::InternedPathBuf(path) => write!(out, "{}", path).unwrap(),
+        Value::Map(map) => {
+            if map.is_empty() {
+                return out.push_str("{}");
+            }
+
+            out.push_str("{\n");
+            for (key, value) in map {
+                write!(out, "{:1$}{2}: ", "", 2 * (indent + 1), key).unwrap();
+                write(out, value, indent + 1);
+                out.push('\n');
+            }
+            write!(out, "{:1$}}}", "", 2 * indent).unwrap();
+        }
+        Value::Node(_) => out.push_str("<node>"),
+        Value::Signed(i) => write!(out, "{}", i).unwrap(),
+        Value::String(s) => write!(out, "{:?}", String::from_utf8_lossy(&s)).unwrap(),
+        Value::SharedString(s) => {
+            write!(out, "{:?}", String::from_utf8_lossy(s.as_slice())).unwrap()
+        }
+        Value::Unsigned(u) => write!(out, "{}", u).unwrap(),
+        Value::ValueArray(array) => {
+            out.push('[');
+            for (i, value) in array.into_iter().enumerate() {
+                if i != 0 {
+                    out.push_str(", ");
+                }
+                write(out, value, indent);
+            }
+            out.push(']');
+        }
+        Value::Void => out.push_str("void"),
+        // `make_owned` doesn't return borrowed values
+        _ => unreachable!(),
+    }
+}
+
+fn blob(out: &mut String, bytes: &[u8]) {
+    out.push('<');
+    for (i, byte) in bytes.iter().take(BLOB_BYTES).enumerate() {
+        if i != 0 {
+            out.push(' ');
+        }
+        write!(out, "{:02x}", byte).unwrap();
+    }
+    if bytes.len() > BLOB_BYTES {
+        write!(out, " ... ({} bytes)", bytes.len()).unwrap();
+    }
+    out.push('>');
+}
+
+/// Returns the error for the command `name` with wrong arguments.
+fn usage(name: &str) -> Error {
+    let usage = match name {
+        "get" => "get PATH",
+        "set" => "set PATH VALUE...",
+        "call" => "call PATH [ARG...]",
+        "rm" => "rm PATH",
+        "ls" => "ls [PATH]",
+        other => return syntax(format!("unknown command `{}`", other)),
+    };
+
+    syntax(format!("usage: {}", usage))
+}
+
+fn syntax<M>(message: M) -> Error
+where
+    M: Into<String>,
+{
+    Error::new(ErrorKind::InvalidSyntax).with_message(message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use error::error_kind;
+    use {MapNode, Node, NodeHandle, ValueKind};
+
+    fn bare(s: &str) -> Word {
+        Word::Bare(s.to_owned())
+    }
+
+    fn kind(s: &str) -> ValueKind {
+        literal(&bare(s)).unwrap().kind()
+    }
+
+    struct Spawn;
+
+    impl Node for Spawn {
+        fn call(&self, _: &Vnodes, args: &[Value]) -> Result<Value<'_>> {
+            match *args {
+                [ref name, ref count] => {
+                    let name = String::from_value(name.clone())?;
+                    let count = u64::from_value(count.clone())?;
+
+                    Ok(format!("{} x{}", name, count).into_value())
+                }
+                _ => Err(ErrorKind::InvalidArgumentTypes.into()),
+            }
+        }
+
+        fn get(&self, _: &Vnodes, _: Interned) -> Result<Value<'_>> {
+            Err(ErrorKind::NoSuchEntry.into())
+        }
+    }
+
+    #[test]
+    fn check_split() {
+        assert_eq!(
+            split("  set /a  \"x \\\"y\\\"\" 3").unwrap(),
+            vec![
+                bare("set"),
+                bare("/a"),
+                Word::Quoted("x \"y\"".to_owned()),
+                bare("3"),
+            ]
+        );
+        assert_eq!(split("").unwrap(), vec![]);
+        assert_eq!(
+            error_kind(split("get \"open")),
+            Some(ErrorKind::InvalidSyntax)
+        );
+        assert_eq!(
+            error_kind(split("get \"\\n\"")),
+            Some(ErrorKind::InvalidSyntax)
+        );
+    }
+
+    #[test]
+    fn check_literals() {
+        assert_eq!(kind("true"), ValueKind::Bool);
+        assert_eq!(kind("3"), ValueKind::Unsigned);
+        assert_eq!(kind("-3"), ValueKind::Signed);
+        assert_eq!(kind("0.5"), ValueKind::Float);
+        assert_eq!(kind("1e3"), ValueKind::Float);
+        assert_eq!(kind("/a/b"), ValueKind::InternedPath);
+        assert_eq!(kind("orc"), ValueKind::String);
+        assert_eq!(kind("inf"), ValueKind::String);
+        assert_eq!(
+            literal(&Word::Quoted("3".to_owned())).unwrap().kind(),
+            ValueKind::String
+        );
+        assert_eq!(
+            error_kind(literal(&bare("/not allowed"))),
+            Some(ErrorKind::InvalidSyntax)
+        );
+
+        assert_eq!(literals(&[]).unwrap().kind(), ValueKind::Void);
+        assert_eq!(
+            literals(&[bare("a"), bare("1")]).unwrap().kind(),
+            ValueKind::ValueArray
+        );
+    }
+
+    #[test]
+    fn check_paths() {
+        assert_eq!(
+            path("/").unwrap(),
+            InternedPathBuf::from_iter(vec![Interned(0)])
+        );
+        assert_eq!(path("/a/b/").unwrap(), InternedPathBuf::from("/a/b"));
+        assert_eq!(path("a/b").unwrap(), InternedPathBuf::from("a/b"));
+        assert_eq!(error_kind(path("/a//b")), Some(ErrorKind::InvalidSyntax));
+        assert_eq!(
+            error_kind(path("/much_too_long")),
+            Some(ErrorKind::InvalidSyntax)
+        );
+    }
+
+    #[test]
+    fn check_format() {
+        assert_eq!(format(Value::Float(1.0), 0), "1.0");
+        assert_eq!(format("a \"b\"".into_value(), 0), "\"a \\\"b\\\"\"");
+        assert_eq!(format(Value::Blob(vec![0, 255].into()), 0), "<00 ff>");
+        assert_eq!(
+            format(Value::Blob(vec![1; 17].into()), 0),
+            "<01 01 01 01 01 01 01 01 01 01 01 01 01 01 01 01 ... (17 bytes)>"
+        );
+        assert_eq!(format((1u64, -2i64, true).into_value(), 0), "[1, -2, true]");
+
+        let map = vec![
+            (Interned::from("a"), Value::Unsigned(1)),
+            (
+                Interned::from("b"),
+                Value::Map(
+                    vec![(Interned::from("c"), Value::Void)]
+                        .into_iter()
+                        .collect(),
+                ),
+            ),
+        ];
+        assert_eq!(
+            format(Value::Map(map.into_iter().collect()), 0),
+            "{\n  a: 1\n  b: {\n    c: void\n  }\n}"
+        );
+    }
+
+    #[test]
+    fn check_commands() {
+        let nodes = Vnodes::new();
+        nodes.insert("/graphics", MapNode::new_node()).unwrap();
+        nodes.insert("/world", MapNode::new_node()).unwrap();
+        nodes
+            .insert("/world/spawn", NodeHandle::new(Spawn))
+            .unwrap();
+
+        assert_eq!(execute(&nodes, "set /graphics/vsync true").unwrap(), "");
+        assert_eq!(nodes.get("/graphics/vsync"), Ok(true));
+        assert_eq!(execute(&nodes, "set /graphics/size 800 600").unwrap(), "");
+        assert_eq!(execute(&nodes, "/graphics/size").unwrap(), "[800, 600]");
+        assert_eq!(execute(&nodes, "get /graphics/vsync").unwrap(), "true");
+        assert_eq!(
+            execute(&nodes, "call /world/spawn orc 3").unwrap(),
+            "\"orc x3\""
+        );
+        assert_eq!(execute(&nodes, "ls /graphics").unwrap(), "[size, vsync]");
+        assert_eq!(execute(&nodes, "ls").unwrap(), "[graphics, world]");
+        assert_eq!(execute(&nodes, "rm /graphics/vsync").unwrap(), "true");
+        assert_eq!(execute(&nodes, "  ").unwrap(), "");
+
+        assert_eq!(
+            error_kind(execute(&nodes, "/graphics/vsync")),
+            Some(ErrorKind::NoSuchEntry)
+        );
+        for line in &[
+            "get",
+            "set /a",
+            "call",
+            "rm",
+            "ls a b",
+            "/graphics 1",
+            "jump /a",
+        ] {
+            assert_eq!(
+                error_kind(execute(&nodes, line)),
+                Some(ErrorKind::InvalidSyntax),
+                "{}",
+                line
+            );
+        }
+    }
+}
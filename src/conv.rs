@@ -1,18 +1,32 @@
 //! Conversion traits for type -> value and value -> type
 
+use std::convert::TryFrom;
+
 use raw::RawValue;
-use {Error, Interned, InternedPath, InternedPathBuf, NodeHandle, NodeHandleRef, Result, Value};
+use {Error, ErrorKind, Interned, InternedMap, InternedPath, InternedPathBuf, NodeHandle,
+     NodeHandleRef, Result, SharedBuffer, Value, ValueArray, ValueKind};
 
 pub trait ValueConv<'a>: Sized {
     fn from_value(value: Value<'a>) -> Result<Self>;
     fn into_value(self) -> Value<'a>;
+
+    /// Passes `self` to `f` as raw value, e.g. to call a node with it.
+    ///
+    /// `f` owns the value, unless it's a (non-boxed) array which borrows its
+    /// elements; tuples use that to keep their elements on the stack.
+    fn with_raw<F, R>(self, f: F) -> R
+    where
+        F: FnOnce(RawValue) -> R,
+    {
+        f(self.into_value().into())
+    }
 }
 
 impl<'a> ValueConv<'a> for () {
     fn from_value(value: Value) -> Result<Self> {
         match value {
             Value::Void => Ok(()),
-            _ => Err(Error::WrongType),
+            other => Err(Error::wrong_type(ValueKind::Void, other.kind())),
         }
     }
 
@@ -23,7 +37,7 @@ impl<'a> ValueConv<'a> for () {
 
 impl<'a> ValueConv<'a> for Value<'a> {
     fn from_value(value: Value<'a>) -> Result<Self> {
-        value.into_res()
+        Ok(value)
     }
 
     fn into_value(self) -> Value<'a> {
@@ -33,8 +47,14 @@ impl<'a> ValueConv<'a> for Value<'a> {
 
 impl<'a> ValueConv<'a> for String {
     fn from_value(value: Value) -> Result<Self> {
-        ValueConv::from_value(value)
-            .and_then(|s: Box<[u8]>| String::from_utf8(Vec::from(s)).map_err(|_| Error::Utf8Error))
+        let bytes = match value {
+            Value::String(s) => Vec::from(s),
+            Value::StringRef(s) => s.to_vec(),
+            Value::SharedString(s) => s.to_vec(),
+            other => return Err(Error::wrong_type(ValueKind::String, other.kind())),
+        };
+
+        String::from_utf8(bytes).map_err(|_| ErrorKind::Utf8Error.into())
     }
 
     fn into_value(self) -> Value<'a> {
@@ -46,7 +66,10 @@ impl<'a> ValueConv<'a> for &'a str {
     fn from_value(value: Value<'a>) -> Result<Self> {
         use std::str::from_utf8;
 
-        ValueConv::from_value(value).and_then(|s| from_utf8(s).map_err(|_| Error::Utf8Error))
+        match value {
+            Value::StringRef(s) => from_utf8(s).map_err(|_| ErrorKind::Utf8Error.into()),
+            other => Err(Error::wrong_type(ValueKind::String, other.kind())),
+        }
     }
 
     fn into_value(self) -> Value<'a> {
@@ -54,30 +77,47 @@ impl<'a> ValueConv<'a> for &'a str {
     }
 }
 
+/// Byte slices are binary data; they never convert from or into strings.
 impl<'a> ValueConv<'a> for Box<[u8]> {
     fn from_value(value: Value) -> Result<Self> {
         match value {
-            Value::String(s) => Ok(s),
-            Value::StringRef(s) => Ok(s.to_vec().into_boxed_slice()),
-            _ => Err(Error::WrongType),
+            Value::Blob(b) => Ok(b),
+            Value::BlobRef(b) => Ok(b.to_vec().into_boxed_slice()),
+            Value::SharedBlob(b) => Ok(b.to_vec().into_boxed_slice()),
+            other => Err(Error::wrong_type(ValueKind::Blob, other.kind())),
+        }
+    }
+
+    fn into_value(self) -> Value<'a> {
+        Value::Blob(self)
+    }
+}
+
+impl<'a> ValueConv<'a> for SharedBuffer {
+    fn from_value(value: Value) -> Result<Self> {
+        match value {
+            Value::SharedBlob(b) | Value::SharedString(b) => Ok(b),
+            Value::Blob(b) => Ok(SharedBuffer::from(b)),
+            Value::BlobRef(b) => Ok(SharedBuffer::from(b)),
+            other => Err(Error::wrong_type(ValueKind::Blob, other.kind())),
         }
     }
 
     fn into_value(self) -> Value<'a> {
-        Value::String(self)
+        Value::SharedBlob(self)
     }
 }
 
 impl<'a> ValueConv<'a> for &'a [u8] {
     fn from_value(value: Value<'a>) -> Result<Self> {
         match value {
-            Value::StringRef(s) => Ok(s),
-            _ => Err(Error::WrongType),
+            Value::BlobRef(b) => Ok(b),
+            other => Err(Error::wrong_type(ValueKind::Blob, other.kind())),
         }
     }
 
     fn into_value(self) -> Value<'a> {
-        Value::StringRef(self)
+        Value::BlobRef(self)
     }
 }
 
@@ -90,40 +130,118 @@ macro_rules! impl_conv_tuple {
             fn from_value(value: Value<'a>) -> Result<Self> {
                 match value {
                     Value::ValueArrayRef(raw) =>
-                        Ok(($(conv_opt(raw.get($field))?,)*)),
-                    Value::ValueArray(ref raw) =>
-                        Ok(($(conv_opt(raw.get($field))?,)*)),
-                    _ => Err(Error::WrongType),
+                        Ok(($(conv_ref(raw.get($field))?,)*)),
+                    Value::ValueArray(array) => {
+                        // Moves the elements out, the remaining ones get dropped
+                        let mut values = array.into_iter();
+
+                        Ok(($(conv_opt::<$tys>(values.next())?,)*))
+                    }
+                    other => Err(Error::wrong_type(ValueKind::ValueArray, other.kind())),
                 }
             }
 
             fn into_value(self) -> Value<'a> {
-                // TODO: allow without boxing?
+                let values = [$(self.$field.into_value()),*];
+                let array: ValueArray = IntoIterator::into_iter(values).collect();
+
+                Value::ValueArray(array)
+            }
 
-                $(
-                    #[allow(non_snake_case)]
-                    let $tys: RawValue = self.$field.into_value().into();
-                )*
-                let v: Vec<RawValue> = vec![$($tys),*];
+            fn with_raw<F, R>(self, f: F) -> R
+            where
+                F: FnOnce(RawValue) -> R,
+            {
+                let values = StackValues([$(RawValue::from(self.$field.into_value())),*]);
 
-                Value::ValueArray(v.into_boxed_slice())
+                f(Value::ValueArrayRef(&values.0).into())
             }
         }
     };
 }
 
+/// Raw values kept on the stack; their payloads are released on drop.
+struct StackValues<A>(A)
+where
+    A: AsMut<[RawValue]>;
+
+impl<A> Drop for StackValues<A>
+where
+    A: AsMut<[RawValue]>,
+{
+    fn drop(&mut self) {
+        for raw in self.0.as_mut() {
+            unsafe {
+                raw.drop_in_place();
+            }
+        }
+    }
+}
+
 impl_conv_tuple!(A.0 B.1);
 impl_conv_tuple!(A.0 B.1 C.2);
 impl_conv_tuple!(A.0 B.1 C.2 D.3);
 impl_conv_tuple!(A.0 B.1 C.2 D.3 E.4);
 
+impl<'a, T> ValueConv<'a> for Vec<T>
+where
+    T: ValueConv<'a>,
+{
+    fn from_value(value: Value<'a>) -> Result<Self> {
+        match value {
+            Value::ValueArrayRef(raw) => raw.iter().map(|raw| conv_ref(Some(raw))).collect(),
+            Value::ValueArray(array) => array.into_iter().map(|v| conv_opt(Some(v))).collect(),
+            other => Err(Error::wrong_type(ValueKind::ValueArray, other.kind())),
+        }
+    }
+
+    fn into_value(self) -> Value<'a> {
+        Value::ValueArray(self.into_iter().map(ValueConv::into_value).collect())
+    }
+}
+
+impl<'a, T> ValueConv<'a> for InternedMap<T>
+where
+    T: ValueConv<'a>,
+{
+    fn from_value(value: Value<'a>) -> Result<Self> {
+        match value {
+            Value::MapRef(entries) => {
+                let mut map = InternedMap::new();
+                for entry in entries {
+                    map.insert(entry.key, conv_ref(Some(&entry.value))?);
+                }
+
+                Ok(map)
+            }
+            Value::Map(entries) => {
+                let mut map = InternedMap::new();
+                for (key, value) in entries {
+                    map.insert(key, T::from_value(value)?);
+                }
+
+                Ok(map)
+            }
+            other => Err(Error::wrong_type(ValueKind::Map, other.kind())),
+        }
+    }
+
+    fn into_value(self) -> Value<'a> {
+        let map = self.into_iter()
+            .map(|(key, value)| (key, value.into_value()))
+            .collect();
+
+        Value::Map(map)
+    }
+}
+
 macro_rules! impl_value_conv {
-    ($ty:ident, $variant:ident $(($lt:tt))*) => {
+    ($ty:ident, $variant:ident $(($lt:tt))*, $kind:ident) => {
         impl<'a> ValueConv<'a> for ($ty$(<$lt>)*) {
             fn from_value(value: Value<'a>) -> Result<Self> {
                 match value {
                     Value::$variant(value) => Ok(value),
-                    _ => Err(Error::WrongType),
+                    other => Err(Error::wrong_type(ValueKind::$kind, other.kind())),
                 }
             }
 
@@ -137,28 +255,169 @@ macro_rules! impl_value_conv {
 // Typedef to make macro call below work
 type InternedPathRef<'a> = &'a InternedPath;
 
-impl_value_conv!(i64, Signed);
-impl_value_conv!(u64, Unsigned);
-impl_value_conv!(bool, Bool);
-impl_value_conv!(f64, Float);
-impl_value_conv!(Interned, Interned);
-impl_value_conv!(InternedPathBuf, InternedPathBuf);
-impl_value_conv!(InternedPathRef, InternedPathRef ('a));
-impl_value_conv!(NodeHandle, Node);
-impl_value_conv!(NodeHandleRef, NodeRef ('a));
-
-fn conv_opt<'a, T>(raw: Option<&RawValue>) -> Result<T>
+impl_value_conv!(bool, Bool, Bool);
+impl_value_conv!(Interned, Interned, Interned);
+impl_value_conv!(InternedPathRef, InternedPathRef ('a), InternedPath);
+impl_value_conv!(NodeHandleRef, NodeRef ('a), Node);
+
+// 2^63 and 2^64, the first values which don't fit into `i64` / `u64`
+const I64_END: f64 = 9_223_372_036_854_775_808.0;
+const U64_END: f64 = 18_446_744_073_709_551_616.0;
+
+impl<'a> ValueConv<'a> for InternedPathBuf {
+    fn from_value(value: Value) -> Result<Self> {
+        match value {
+            Value::InternedPathBuf(path) => Ok(path),
+            Value::InternedPathRef(path) => Ok(InternedPathBuf::from(path)),
+            other => Err(Error::wrong_type(ValueKind::InternedPath, other.kind())),
+        }
+    }
+
+    fn into_value(self) -> Value<'a> {
+        Value::InternedPathBuf(self)
+    }
+}
+
+impl<'a> ValueConv<'a> for NodeHandle {
+    fn from_value(value: Value) -> Result<Self> {
+        match value {
+            Value::Node(node) => Ok(node),
+            Value::NodeRef(node) => Ok(node.to_handle()),
+            other => Err(Error::wrong_type(ValueKind::Node, other.kind())),
+        }
+    }
+
+    fn into_value(self) -> Value<'a> {
+        Value::Node(self)
+    }
+}
+
+/// Integers accept any numeric value which can be represented
+/// without loss; everything else is rejected with `OutOfRange`.
+macro_rules! impl_conv_int {
+    ($($ty:ident => $variant:ident,)*) => {
+        $(
+            impl<'a> ValueConv<'a> for $ty {
+                fn from_value(value: Value<'a>) -> Result<Self> {
+                    int_from_value(value, ValueKind::$variant)
+                }
+
+                fn into_value(self) -> Value<'a> {
+                    Value::$variant(self as _)
+                }
+            }
+        )*
+    };
+}
+
+impl_conv_int! {
+    i8 => Signed,
+    i16 => Signed,
+    i32 => Signed,
+    i64 => Signed,
+    isize => Signed,
+    u8 => Unsigned,
+    u16 => Unsigned,
+    u32 => Unsigned,
+    u64 => Unsigned,
+    usize => Unsigned,
+}
+
+impl<'a> ValueConv<'a> for f64 {
+    fn from_value(value: Value<'a>) -> Result<Self> {
+        match value {
+            Value::Float(f) => Ok(f),
+            Value::Signed(s) => {
+                let f = s as f64;
+
+                match f < I64_END && f as i64 == s {
+                    true => Ok(f),
+                    false => Err(ErrorKind::OutOfRange.into()),
+                }
+            }
+            Value::Unsigned(u) => {
+                let f = u as f64;
+
+                match f < U64_END && f as u64 == u {
+                    true => Ok(f),
+                    false => Err(ErrorKind::OutOfRange.into()),
+                }
+            }
+            other => Err(Error::wrong_type(ValueKind::Float, other.kind())),
+        }
+    }
+
+    fn into_value(self) -> Value<'a> {
+        Value::Float(self)
+    }
+}
+
+impl<'a> ValueConv<'a> for f32 {
+    fn from_value(value: Value<'a>) -> Result<Self> {
+        match value {
+            // Narrowing a float only loses precision, which is expected
+            Value::Float(f) => match f as f32 {
+                g if g.is_infinite() && f.is_finite() => Err(ErrorKind::OutOfRange.into()),
+                g => Ok(g),
+            },
+            value => {
+                let f = f64::from_value(value)?;
+
+                match f as f32 {
+                    g if g as f64 == f => Ok(g),
+                    _ => Err(ErrorKind::OutOfRange.into()),
+                }
+            }
+        }
+    }
+
+    fn into_value(self) -> Value<'a> {
+        Value::Float(self as f64)
+    }
+}
+
+/// `kind` is only used for error reports.
+fn int_from_value<T>(value: Value, kind: ValueKind) -> Result<T>
+where
+    T: TryFrom<i64> + TryFrom<u64>,
+{
+    let res = match value {
+        Value::Signed(s) => T::try_from(s).ok(),
+        Value::Unsigned(u) => T::try_from(u).ok(),
+        Value::Float(f) => match f {
+            f if f.fract() != 0.0 => None,
+            f if (0.0..U64_END).contains(&f) => T::try_from(f as u64).ok(),
+            f if (-I64_END..0.0).contains(&f) => T::try_from(f as i64).ok(),
+            _ => None,
+        },
+        other => return Err(Error::wrong_type(kind, other.kind())),
+    };
+
+    res.ok_or_else(|| ErrorKind::OutOfRange.into())
+}
+
+fn conv_opt<'a, T>(value: Option<Value<'a>>) -> Result<T>
+where
+    T: ValueConv<'a>,
+{
+    value
+        .ok_or_else(|| ErrorKind::InvalidArgumentTypes.into())
+        .and_then(T::from_value)
+}
+
+/// Converts a borrowed raw value, leaving its payload untouched.
+fn conv_ref<'a, T>(raw: Option<&'a RawValue>) -> Result<T>
 where
     T: ValueConv<'a>,
 {
-    raw.ok_or(Error::InvalidArgumentTypes)
-        .and_then(|raw| unsafe { Value::from_raw(*raw).into_res() })
-        .and_then(|val| T::from_value(val))
+    conv_opt(raw.map(|raw| unsafe { raw.as_value() }))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use error::error_kind;
+    use raw::Flags;
     use MapNode;
     use std::fmt::Debug;
 
@@ -186,14 +445,82 @@ mod tests {
         check_equal(Interned::from("my_ident"));
     }
 
+    #[test]
+    fn check_narrow_numbers() {
+        check_equal(7u8);
+        check_equal(65_000u16);
+        check_equal(4_000_000_000u32);
+        check_equal(1234usize);
+        check_equal(-7i8);
+        check_equal(-32_000i16);
+        check_equal(-2_000_000_000i32);
+        check_equal(-1234isize);
+        check_equal(0.5f32);
+    }
+
+    #[test]
+    fn check_numeric_coercion() {
+        assert_eq!(u64::from_value(Value::Signed(5)), Ok(5));
+        assert_eq!(i8::from_value(Value::Unsigned(127)), Ok(127));
+        assert_eq!(u32::from_value(Value::Float(3.0)), Ok(3));
+        assert_eq!(i64::from_value(Value::Float(-3.0)), Ok(-3));
+        assert_eq!(f64::from_value(Value::Signed(-5)), Ok(-5.0));
+        assert_eq!(f32::from_value(Value::Unsigned(16)), Ok(16.0));
+        assert_eq!(f32::from_value(Value::Float(0.1)), Ok(0.1));
+
+        assert_eq!(error_kind(u64::from_value(Value::Signed(-1))), Some(ErrorKind::OutOfRange));
+        assert_eq!(error_kind(u8::from_value(Value::Unsigned(256))), Some(ErrorKind::OutOfRange));
+        assert_eq!(error_kind(i32::from_value(Value::Float(1e10))), Some(ErrorKind::OutOfRange));
+        assert_eq!(error_kind(f64::from_value(Value::Unsigned(!0))), Some(ErrorKind::OutOfRange));
+        assert_eq!(error_kind(f32::from_value(Value::Float(1e300))), Some(ErrorKind::OutOfRange));
+        assert_eq!(
+            error_kind(f32::from_value(Value::Signed(16_777_217))),
+            Some(ErrorKind::OutOfRange)
+        );
+
+        assert_eq!(error_kind(u32::from_value(Value::Float(0.5))), Some(ErrorKind::OutOfRange));
+        assert_eq!(error_kind(i8::from_value(Value::Float(-1.5))), Some(ErrorKind::OutOfRange));
+        assert_eq!(error_kind(i64::from_value(Value::Float(f64::NAN))), Some(ErrorKind::OutOfRange));
+
+        assert_eq!(error_kind(u32::from_value(Value::Bool(true))), Some(ErrorKind::WrongType));
+    }
+
     #[test]
     fn check_strings() {
         check_equal("Hello!");
-        check_equal(b"Byte slices are allowed, too (even better)" as &[u8]);
         check_equal("Bye!".to_owned());
+    }
+
+    #[test]
+    fn check_blobs() {
+        check_equal(b"Byte slices are allowed, too (even better)" as &[u8]);
         check_equal(Box::new(*b"(PS: Boxed slices also work ofc)") as Box<[u8]>);
     }
 
+    #[test]
+    fn check_shared() {
+        check_equal(SharedBuffer::from(vec![1, 2, 3]));
+
+        let shared = Value::SharedString(SharedBuffer::from("shared".to_owned()));
+        assert_eq!(String::from_value(shared), Ok("shared".to_owned()));
+    }
+
+    #[test]
+    fn check_strings_and_blobs_apart() {
+        let bytes: &[u8] = b"\x89PNG";
+
+        assert_eq!(error_kind(<&str>::from_value(bytes.into_value())), Some(ErrorKind::WrongType));
+        assert_eq!(error_kind(String::from_value(bytes.into_value())), Some(ErrorKind::WrongType));
+        assert_eq!(
+            error_kind(<&[u8]>::from_value("text".into_value())),
+            Some(ErrorKind::WrongType)
+        );
+        assert_eq!(
+            error_kind(<Box<[u8]>>::from_value("text".to_owned().into_value())),
+            Some(ErrorKind::WrongType)
+        );
+    }
+
     #[test]
     fn check_tuples() {
         check_equal((5u64, 19i64));
@@ -205,6 +532,59 @@ mod tests {
         ));
     }
 
+    #[test]
+    fn check_tuples_on_stack() {
+        let args = ("borrowed".to_owned(), 5u64);
+        let (string, number) = args.with_raw(|raw| {
+            // The array is borrowed, so it's passed without boxing
+            assert_eq!(raw.flags, Flags::VALUE_ARRAY);
+            assert!(!raw.is_owned());
+
+            <(String, u64)>::from_value(unsafe { raw.as_value() }).unwrap()
+        });
+
+        assert_eq!(string, "borrowed");
+        assert_eq!(number, 5);
+    }
+
+    #[test]
+    fn check_vecs() {
+        check_equal(Vec::<u64>::new());
+        check_equal(vec![Interned::from("a"), Interned::from("b")]);
+        check_equal(vec![vec!["nested".to_owned()], vec![]]);
+    }
+
+    #[test]
+    fn check_maps() {
+        let mut map = InternedMap::new();
+        check_equal(map.clone());
+
+        map.insert(Interned::from("width"), 1920u64);
+        map.insert(Interned::from("height"), 1080u64);
+        map.insert(Interned::from("depth"), 32u64);
+        check_equal(map.clone());
+
+        let mut nested = InternedMap::new();
+        nested.insert(Interned::from("screen"), map);
+        nested.insert(Interned::from("window"), InternedMap::new());
+        check_equal(nested);
+    }
+
+    #[test]
+    fn check_map_entries_sorted() {
+        let mut map = InternedMap::new();
+        map.insert(Interned::from("zeta"), "last");
+        map.insert(Interned::from("alpha"), "first");
+
+        match map.into_value() {
+            Value::Map(ref entries) => {
+                assert_eq!(entries.len(), 2);
+                assert!(entries[0].key < entries[1].key);
+            }
+            _ => panic!("Expected a map"),
+        }
+    }
+
     #[test]
     fn check_paths() {
         check_equal(Interned::from("simple"));
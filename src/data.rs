@@ -1,17 +1,77 @@
-use std::fmt::{Debug, Formatter, Result as FmtResult};
+use std::fmt::{Debug, Display, Formatter, Result as FmtResult};
+use std::iter::FromIterator;
+use std::marker::PhantomData;
+use std::mem::{forget, replace, ManuallyDrop};
+use std::ops::Deref;
+use std::ptr::read;
+use std::vec::IntoIter;
 
 use util::{boxed_slice_from_raw, slice_from_raw};
-use {Error, Interned, InternedPath, InternedPathBuf, NodeHandle, NodeHandleRef, Result, Vnodes};
+use {Error, ErrorKind, Interned, InternedPath, InternedPathBuf, NodeHandle, NodeHandleRef, Result,
+     SharedBuffer, Vnodes};
 
-#[repr(u8)]
-#[derive(Debug)]
+/// An `Action` as passed through the raw interface.
+///
+/// Receivers must not assume that it's a known action: codes added by newer
+/// versions are answered with `ErrorKind::ActionNotSupported`.
+pub type RawAction = u32;
+
+/// Everything a node or shared buffer can be asked to do.
+///
+/// For nodes, the actions up to `Drop` have their own `RawNodeVtable` entries.
+#[repr(u32)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
 pub enum Action {
     Call = 0x0,
     List = 0x1,
     Get = 0x10,
     Set = 0x11,
+    Remove = 0x12,
+    /// Sets a child unless it exists; the argument is a map with the entry
+    /// to set. Returns a `Bool` telling if it was set.
+    SetIfAbsent = 0x13,
     Clone = 0x20,
     Drop = 0x21,
+    CloneWeak = 0x22,
+    DropWeak = 0x23,
+    /// Tries to acquire a strong reference from a weak one; returns a `Bool`.
+    Upgrade = 0x24,
+    /// Checks if the node has the Rust type whose `TypeId` is passed as blob;
+    /// returns a `Bool`.
+    TypeId = 0x30,
+    /// Returns a `NodeMeta` describing the node.
+    Meta = 0x31,
+    /// Returns the `ABI_VERSION` the node was built against as `Unsigned`;
+    /// the argument is the version of the caller.
+    Handshake = 0x40,
+}
+
+impl Action {
+    pub fn from_raw(raw: RawAction) -> Option<Self> {
+        let action = match raw {
+            0x0 => Action::Call,
+            0x1 => Action::List,
+            0x10 => Action::Get,
+            0x11 => Action::Set,
+            0x12 => Action::Remove,
+            0x13 => Action::SetIfAbsent,
+            0x20 => Action::Clone,
+            0x21 => Action::Drop,
+            0x22 => Action::CloneWeak,
+            0x23 => Action::DropWeak,
+            0x24 => Action::Upgrade,
+            0x30 => Action::TypeId,
+            0x31 => Action::Meta,
+            0x40 => Action::Handshake,
+            _ => return None,
+        };
+
+        Some(action)
+    }
+
+    pub fn into_raw(self) -> RawAction {
+        self as RawAction
+    }
 }
 
 bitflags! {
@@ -24,7 +84,9 @@ bitflags! {
         const _FLOAT = 0x8;
         const _BOOL = 0x10;
         const _INTERNED = 0x20;
-        const _ERROR = 0x40;
+        const _MAP = 0x80;
+        // Type flags continue above the variant flags
+        const _BLOB = 0x1_0000;
         // --
 
         // -- Variant flags
@@ -32,21 +94,30 @@ bitflags! {
         const _ALLOCATED = 0x200;
         const _BOXED = 0x400;
         const _ARRAY = 0x800;
+        const _SHARED = 0x1000;
         // --
 
+        // Reserved for future types and variants; values using them are rejected
+        const _RESERVED = 0xFFFE_E040;
+
         // -- Combined flags
+        const BLOB = Self::_BLOB.bits;
+        const BLOB_BOXED = Self::_BLOB.bits | Self::_BOXED.bits;
+        const BLOB_SHARED = Self::_BLOB.bits | Self::_SHARED.bits;
         const BOOL = Self::_BOOL.bits;
-        const ERROR = Self::_ERROR.bits;
         const FLOAT = Self::_FLOAT.bits;
         const INTEGER = Self::_INTEGER.bits;
         const INTEGER_SIGNED = Self::_INTEGER.bits | Self::_SIGNED.bits;
         const INTERNED = Self::_INTERNED.bits;
         const INTERNED_PATH = Self::_INTERNED.bits | Self::_ARRAY.bits;
         const INTERNED_PATH_BUF = Self::_INTERNED.bits | Self::_ARRAY.bits | Self::_BOXED.bits;
+        const MAP = Self::_MAP.bits;
+        const MAP_BOXED = Self::_MAP.bits | Self::_BOXED.bits;
         const NODE = Self::_NODE.bits;
         const NODE_BOXED = Self::_NODE.bits | Self::_BOXED.bits;
         const STRING = Self::_STRING.bits;
         const STRING_BOXED = Self::_STRING.bits | Self::_BOXED.bits;
+        const STRING_SHARED = Self::_STRING.bits | Self::_SHARED.bits;
         const VALUE_ARRAY = Self::_ARRAY.bits;
         const VALUE_ARRAY_BOXED = Self::_ARRAY.bits | Self::_BOXED.bits;
         const VOID = 0x0;
@@ -54,9 +125,92 @@ bitflags! {
     }
 }
 
+/// The type of a value, ignoring how its payload is stored.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum ValueKind {
+    Blob,
+    Bool,
+    Float,
+    Interned,
+    InternedPath,
+    Map,
+    Node,
+    Signed,
+    String,
+    Unsigned,
+    ValueArray,
+    Void,
+}
+
+impl ValueKind {
+    /// Returns the kind encoded by the type flags of `flags`, if they're known.
+    pub fn from_flags(flags: Flags) -> Option<Self> {
+        let kind = match flags {
+            Flags::BLOB | Flags::BLOB_BOXED | Flags::BLOB_SHARED => ValueKind::Blob,
+            Flags::BOOL => ValueKind::Bool,
+            Flags::FLOAT => ValueKind::Float,
+            Flags::INTEGER => ValueKind::Unsigned,
+            Flags::INTEGER_SIGNED => ValueKind::Signed,
+            Flags::INTERNED => ValueKind::Interned,
+            Flags::INTERNED_PATH | Flags::INTERNED_PATH_BUF => ValueKind::InternedPath,
+            Flags::MAP | Flags::MAP_BOXED => ValueKind::Map,
+            Flags::NODE | Flags::NODE_BOXED => ValueKind::Node,
+            Flags::STRING | Flags::STRING_BOXED | Flags::STRING_SHARED => ValueKind::String,
+            Flags::VALUE_ARRAY | Flags::VALUE_ARRAY_BOXED => ValueKind::ValueArray,
+            Flags::VOID => ValueKind::Void,
+            _ => return None,
+        };
+
+        Some(kind)
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ValueKind::Blob => "blob",
+            ValueKind::Bool => "bool",
+            ValueKind::Float => "float",
+            ValueKind::Interned => "interned",
+            ValueKind::InternedPath => "path",
+            ValueKind::Map => "map",
+            ValueKind::Node => "node",
+            ValueKind::Signed => "signed",
+            ValueKind::String => "string",
+            ValueKind::Unsigned => "unsigned",
+            ValueKind::ValueArray => "array",
+            ValueKind::Void => "void",
+        }
+    }
+}
+
+impl Display for ValueKind {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        f.write_str(self.name())
+    }
+}
+
+/// The functions implementing a node.
+///
+/// The common operations have their own entries taking typed arguments;
+/// all other actions go through `action`.
 #[repr(C)]
-pub struct RawNodeData {
-    /// A function pointer to the `action` function of this node.
+pub struct RawNodeVtable {
+    /// Returns the child `ident`.
+    pub get: unsafe extern "C" fn(*mut RawNodeData, *mut Vnodes, Interned) -> RawResult,
+    /// Sets the child `ident`, taking ownership of the value.
+    pub set: unsafe extern "C" fn(*mut RawNodeData, *mut Vnodes, Interned, RawValue) -> RawResult,
+    /// Calls the node, taking ownership of the arguments;
+    /// multiple arguments are passed as value array.
+    pub call: unsafe extern "C" fn(*mut RawNodeData, *mut Vnodes, RawValue) -> RawResult,
+    /// Returns the identifiers of all children as array of interned values.
+    pub list: unsafe extern "C" fn(*mut RawNodeData, *mut Vnodes) -> RawResult,
+    /// Removes the child `ident`, returning its value.
+    pub remove: unsafe extern "C" fn(*mut RawNodeData, *mut Vnodes, Interned) -> RawResult,
+    /// Acquires another strong reference.
+    pub clone: unsafe extern "C" fn(*mut RawNodeData),
+    /// Releases a strong reference; the node is dropped with the last one.
+    pub drop: unsafe extern "C" fn(*mut RawNodeData),
+    /// Performs any other action; the actions above must be answered
+    /// with `ErrorKind::ActionNotSupported`.
     ///
     /// ## Parameters
     ///
@@ -64,21 +218,214 @@ pub struct RawNodeData {
     /// 2. pointer to the `vnodes` context
     /// 3. the requested action
     /// 4. argument(s)
-    pub action: unsafe extern "C" fn(*mut RawNodeData, *mut Vnodes, Action, RawValue) -> RawValue,
+    pub action: unsafe extern "C" fn(*mut RawNodeData, *mut Vnodes, RawAction, RawValue) -> RawResult,
+}
+
+#[repr(C)]
+pub struct RawNodeData {
+    /// The functions implementing this node; usually a static shared by all
+    /// nodes of the same type.
+    pub vtable: *const RawNodeVtable,
+}
+
+/// The header of a reference counted, immutable byte buffer.
+#[repr(C)]
+pub struct RawSharedData {
+    /// A function pointer to the `action` function of this buffer.
+    /// Only `Action::Clone` and `Action::Drop` are valid here.
+    ///
+    /// ## Parameters
+    ///
+    /// 1. self pointer
+    /// 2. the requested action
+    pub action: unsafe extern "C" fn(*mut RawSharedData, RawAction),
+    /// Pointer to the first byte of the buffer.
+    pub data: *const u8,
+    /// The length of the buffer in bytes.
+    pub len: usize,
 }
 
+/// The C representation of a `Value`.
+///
+/// ## Ownership
+///
+/// A raw value owns its payload if it has the `_BOXED` or the `_SHARED` flag set;
+/// owned arrays and maps also own all of their elements.
+/// All other values are either plain data or borrow their payload from somewhere else.
+///
+/// Passing a raw value by value transfers ownership of its payload.
+/// Owned payloads must be released exactly once, either by converting the raw value
+/// back using `Value::from_raw` or by calling `RawValue::drop_in_place`.
 #[repr(C)]
 #[derive(Copy, Clone)]
 pub struct RawValue {
     pub flags: Flags,
-    pub extra: u32,
+    pub extra: usize,
     pub value: RawValueInner,
 }
 
+impl RawValue {
+    /// The kind of this value, or `None` if its flags are unknown
+    /// (e.g. because it was created by a newer version).
+    pub fn kind(&self) -> Option<ValueKind> {
+        ValueKind::from_flags(self.flags)
+    }
+
+    /// Checks that this value and all values nested in arrays and maps have
+    /// known flags, which is required by `Value::from_raw`.
+    ///
+    /// Values failing the check can't be released and have to be leaked.
+    ///
+    /// # Safety
+    ///
+    /// The payloads of arrays and maps have to be valid.
+    pub unsafe fn validate(&self) -> Result<()> {
+        // Nested values are checked without recursion, the depth is up to the sender
+        let mut pending = Vec::new();
+        let mut value = *self;
+
+        loop {
+            match value.flags {
+                Flags::VALUE_ARRAY | Flags::VALUE_ARRAY_BOXED => {
+                    pending.extend_from_slice(slice_from_raw(value.value.value_array, value.extra))
+                }
+                Flags::MAP | Flags::MAP_BOXED => pending.extend(
+                    slice_from_raw(value.value.map, value.extra)
+                        .iter()
+                        .map(|entry| entry.value),
+                ),
+                flags if ValueKind::from_flags(flags).is_none() => {
+                    return Err(ErrorKind::UnknownTypeFlags.into())
+                }
+                _ => {}
+            }
+
+            match pending.pop() {
+                Some(next) => value = next,
+                None => return Ok(()),
+            }
+        }
+    }
+
+    /// Returns `true` if this value owns its payload.
+    pub fn is_owned(&self) -> bool {
+        self.flags.intersects(Flags::_BOXED | Flags::_SHARED)
+    }
+
+    /// Borrows this value without taking ownership of its payload.
+    ///
+    /// # Safety
+    ///
+    /// `self` must be a valid raw value.
+    pub unsafe fn as_value(&self) -> Value<'_> {
+        match self.flags {
+            Flags::BLOB_BOXED => Value::BlobRef(slice_from_raw(self.value.blob, self.extra)),
+            Flags::INTERNED_PATH_BUF => {
+                Value::InternedPathRef(slice_from_raw(self.value.interned_path, self.extra))
+            }
+            Flags::MAP_BOXED => Value::MapRef(slice_from_raw(self.value.map, self.extra)),
+            Flags::NODE_BOXED => Value::NodeRef(NodeHandleRef::from_raw(self.value.node_data)),
+            Flags::STRING_BOXED => Value::StringRef(slice_from_raw(self.value.string, self.extra)),
+            Flags::VALUE_ARRAY_BOXED => {
+                Value::ValueArrayRef(slice_from_raw(self.value.value_array, self.extra))
+            }
+            // Cloning a shared buffer is as cheap as borrowing it
+            flags if flags.contains(Flags::_SHARED) => Value::from_raw(self.clone_raw()),
+            _ => Value::from_raw(*self),
+        }
+    }
+
+    /// Creates a copy of this value, cloning the payload if it's owned.
+    ///
+    /// # Safety
+    ///
+    /// `self` must be a valid raw value.
+    pub unsafe fn clone_raw(&self) -> RawValue {
+        match self.is_owned() {
+            true => {
+                let value = ManuallyDrop::new(Value::from_raw(*self));
+
+                Value::clone(&value).into()
+            }
+            false => *self,
+        }
+    }
+
+    /// Releases the payload of this value (if it's owned) and resets it to void.
+    ///
+    /// # Safety
+    ///
+    /// `self` must be a valid raw value which has not been released before.
+    pub unsafe fn drop_in_place(&mut self) {
+        let raw = replace(self, RawValue::from(()));
+
+        drop(Value::from_raw(raw));
+    }
+}
+
+/// The result of a node action.
+///
+/// `status` is zero on success, in which case `value` is the returned value.
+/// Otherwise, `status` is the code of an `ErrorKind` and `value` is either void
+/// or an owned string with an error message.
+///
+/// Like `RawValue`, passing a raw result by value transfers ownership of its payload.
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct RawResult {
+    pub status: u32,
+    pub value: RawValue,
+}
+
+impl RawResult {
+    /// Takes ownership of this result.
+    ///
+    /// # Safety
+    ///
+    /// `self` must be a valid raw result which has not been released before.
+    pub unsafe fn into_result<'a>(self) -> Result<Value<'a>> {
+        // Leaking a value we can't interpret is the only safe option
+        self.value.validate()?;
+
+        let value = Value::from_raw(self.value);
+
+        match self.status {
+            0 => Ok(value),
+            code => {
+                let error = Error::new(ErrorKind::from_code(code));
+
+                Err(match value {
+                    Value::String(message) => error.with_message(String::from_utf8_lossy(&message)),
+                    _ => error,
+                })
+            }
+        }
+    }
+}
+
+impl<'a> From<Result<Value<'a>>> for RawResult {
+    fn from(res: Result<Value<'a>>) -> Self {
+        match res {
+            Ok(value) => RawResult {
+                status: 0,
+                value: value.into(),
+            },
+            Err(error) => RawResult {
+                status: error.kind().code(),
+                value: error
+                    .message()
+                    .map(|message| Value::String(message.as_bytes().into()))
+                    .unwrap_or(Value::Void)
+                    .into(),
+            },
+        }
+    }
+}
+
 impl Debug for RawValue {
     fn fmt(&self, f: &mut Formatter) -> FmtResult {
         unsafe {
-            let value = Value::from_raw(*self);
+            let value = self.as_value();
             f.debug_struct("RawValue")
                 .field("value_repr", &value)
                 .finish()
@@ -86,15 +433,30 @@ impl Debug for RawValue {
     }
 }
 
+/// A key / value pair of a `MAP` value.
+///
+/// The entries of a map are always sorted by key and each key occurs only once.
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct RawMapEntry {
+    pub key: Interned,
+    pub value: RawValue,
+}
+
+/// `float`, `signed` and `unsigned` are keywords in C, so they get a suffix there.
+///
+/// cbindgen:field-names=[blob, boolean, float_, interned, interned_path, map, node_data, shared, signed_, string, unsigned_, value_array]
 #[repr(C)]
 #[derive(Copy, Clone)]
 pub union RawValueInner {
+    pub blob: *const u8,
     pub boolean: bool,
-    pub error: Error,
     pub float: f64,
     pub interned: Interned,
     pub interned_path: *const Interned,
+    pub map: *const RawMapEntry,
     pub node_data: *mut RawNodeData,
+    pub shared: *mut RawSharedData,
     pub signed: i64,
     pub string: *const u8,
     pub unsigned: u64,
@@ -104,33 +466,48 @@ pub union RawValueInner {
 unsafe impl Send for RawValueInner {}
 unsafe impl Sync for RawValueInner {}
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Default)]
 pub enum Value<'a> {
+    Blob(Box<[u8]>),
+    BlobRef(&'a [u8]),
     Bool(bool),
-    Error(Error),
     Float(f64),
     Interned(Interned),
     InternedPathBuf(InternedPathBuf),
     InternedPathRef(&'a InternedPath),
+    Map(ValueMap<'a>),
+    MapRef(&'a [RawMapEntry]),
     Node(NodeHandle),
     NodeRef(NodeHandleRef<'a>),
+    SharedBlob(SharedBuffer),
+    SharedString(SharedBuffer),
     Signed(i64),
     String(Box<[u8]>),
     StringRef(&'a [u8]),
     Unsigned(u64),
-    ValueArray(Box<[RawValue]>),
+    ValueArray(ValueArray<'a>),
     ValueArrayRef(&'a [RawValue]),
+    #[default]
     Void,
 }
 
 impl<'a> Value<'a> {
+    /// Takes ownership of `raw`.
+    ///
+    /// # Safety
+    ///
+    /// `raw` must be a valid raw value which has not been released before and
+    /// its borrowed payload has to live for `'b`. Values received from foreign
+    /// code have to pass `RawValue::validate` first.
     pub unsafe fn from_raw<'b>(raw: RawValue) -> Value<'b>
     where
         'a: 'b,
     {
         match raw.flags {
+            Flags::BLOB => Value::BlobRef(slice_from_raw(raw.value.blob, raw.extra)),
+            Flags::BLOB_BOXED => Value::Blob(boxed_slice_from_raw(raw.value.blob, raw.extra)),
+            Flags::BLOB_SHARED => Value::SharedBlob(SharedBuffer::from_raw(raw.value.shared)),
             Flags::BOOL => Value::Bool(raw.value.boolean),
-            Flags::ERROR => Value::Error(raw.value.error),
             Flags::FLOAT => Value::Float(raw.value.float),
             Flags::INTEGER => Value::Unsigned(raw.value.unsigned),
             Flags::INTEGER_SIGNED => Value::Signed(raw.value.signed),
@@ -141,59 +518,78 @@ impl<'a> Value<'a> {
             Flags::INTERNED_PATH_BUF => Value::InternedPathBuf(InternedPathBuf::from(
                 boxed_slice_from_raw(raw.value.interned_path, raw.extra),
             )),
+            Flags::MAP => Value::MapRef(slice_from_raw(raw.value.map, raw.extra)),
+            Flags::MAP_BOXED => Value::Map(ValueMap::from_raw(raw.value.map, raw.extra)),
             Flags::NODE => Value::NodeRef(NodeHandleRef::from_raw(raw.value.node_data)),
             Flags::NODE_BOXED => Value::Node(NodeHandle::from_raw(raw.value.node_data)),
             Flags::STRING => Value::StringRef(slice_from_raw(raw.value.string, raw.extra)),
             Flags::STRING_BOXED => Value::String(boxed_slice_from_raw(raw.value.string, raw.extra)),
+            Flags::STRING_SHARED => Value::SharedString(SharedBuffer::from_raw(raw.value.shared)),
             Flags::VALUE_ARRAY => {
                 Value::ValueArrayRef(slice_from_raw(raw.value.value_array, raw.extra))
             }
             Flags::VALUE_ARRAY_BOXED => {
-                Value::ValueArray(boxed_slice_from_raw(raw.value.value_array, raw.extra))
+                Value::ValueArray(ValueArray::from_raw(raw.value.value_array, raw.extra))
             }
             Flags::VOID => Value::Void,
             flags => unimplemented!("Unimplemented flags: {:?}", flags),
         }
     }
 
-    pub fn from_res(res: Result<Self>) -> Self {
-        match res {
-            Ok(x) => x,
-            Err(e) => Value::Error(e),
-        }
-    }
-
-    pub fn into_res(self) -> Result<Self> {
-        match self {
-            Value::Error(e) => Err(e),
-            x => Ok(x),
-        }
-    }
-
     pub fn as_node_handle(&self) -> Result<NodeHandleRef> {
         match *self {
             Value::Node(ref node) => Ok(node.handle_ref()),
             Value::NodeRef(node) => Ok(node),
-            _ => Err(Error::WrongType),
+            ref other => Err(Error::wrong_type(ValueKind::Node, other.kind())),
+        }
+    }
+
+    pub fn kind(&self) -> ValueKind {
+        match *self {
+            Value::Blob(_) | Value::BlobRef(_) | Value::SharedBlob(_) => ValueKind::Blob,
+            Value::Bool(_) => ValueKind::Bool,
+            Value::Float(_) => ValueKind::Float,
+            Value::Interned(_) => ValueKind::Interned,
+            Value::InternedPathBuf(_) | Value::InternedPathRef(_) => ValueKind::InternedPath,
+            Value::Map(_) | Value::MapRef(_) => ValueKind::Map,
+            Value::Node(_) | Value::NodeRef(_) => ValueKind::Node,
+            Value::Signed(_) => ValueKind::Signed,
+            Value::String(_) | Value::StringRef(_) | Value::SharedString(_) => ValueKind::String,
+            Value::Unsigned(_) => ValueKind::Unsigned,
+            Value::ValueArray(_) | Value::ValueArrayRef(_) => ValueKind::ValueArray,
+            Value::Void => ValueKind::Void,
         }
     }
 
     pub fn make_owned(self) -> Value<'static> {
         match self {
+            Value::Blob(b) => Value::Blob(b),
+            Value::BlobRef(b) => Value::Blob(Vec::from(b).into_boxed_slice()),
             Value::Bool(b) => Value::Bool(b),
-            Value::Error(e) => Value::Error(e),
             Value::Float(f) => Value::Float(f),
             Value::Interned(i) => Value::Interned(i),
             Value::InternedPathBuf(b) => Value::InternedPathBuf(b),
             Value::InternedPathRef(r) => Value::InternedPathBuf(InternedPathBuf::from(r)),
+            Value::Map(m) => Value::Map(m.into_owned()),
+            Value::MapRef(m) => Value::Map(
+                m.iter()
+                    .map(|entry| (entry.key, unsafe { entry.value.as_value().make_owned() }))
+                    .collect(),
+            ),
             Value::Node(n) => Value::Node(n),
             Value::NodeRef(n) => Value::Node(n.to_handle()),
+            Value::SharedBlob(b) => Value::SharedBlob(b),
+            Value::SharedString(s) => Value::SharedString(s),
             Value::Signed(s) => Value::Signed(s),
             Value::String(s) => Value::String(s),
             Value::StringRef(s) => Value::String(Vec::from(s).into_boxed_slice()),
             Value::Unsigned(u) => Value::Unsigned(u),
-            Value::ValueArray(a) => Value::ValueArray(a),
-            Value::ValueArrayRef(a) => Value::ValueArray(Vec::from(a).into_boxed_slice()),
+            Value::ValueArray(a) => Value::ValueArray(a.into_owned()),
+            Value::ValueArrayRef(a) => Value::ValueArray(
+                a.iter()
+                    .map(|raw| unsafe { raw.as_value().make_owned() })
+                    .collect(),
+            ),
             Value::Void => Value::Void,
         }
     }
@@ -208,16 +604,23 @@ impl<'a> From<()> for Value<'a> {
 impl<'a> From<Value<'a>> for RawValue {
     fn from(value: Value) -> Self {
         match value {
+            Value::Blob(b) => RawValue {
+                flags: Flags::BLOB_BOXED,
+                extra: b.len(),
+                value: RawValueInner {
+                    blob: Box::into_raw(b) as *const u8,
+                },
+            },
+            Value::BlobRef(b) => RawValue {
+                flags: Flags::BLOB,
+                extra: b.len(),
+                value: RawValueInner { blob: b.as_ptr() },
+            },
             Value::Bool(boolean) => RawValue {
                 flags: Flags::BOOL,
                 extra: 0,
                 value: RawValueInner { boolean },
             },
-            Value::Error(error) => RawValue {
-                flags: Flags::ERROR,
-                extra: 0,
-                value: RawValueInner { error },
-            },
             Value::Float(float) => RawValue {
                 flags: Flags::FLOAT,
                 extra: 0,
@@ -232,7 +635,7 @@ impl<'a> From<Value<'a>> for RawValue {
                 let boxed = b.into_boxed_slice();
                 RawValue {
                     flags: Flags::INTERNED_PATH_BUF,
-                    extra: boxed.len() as u32,
+                    extra: boxed.len(),
                     value: RawValueInner {
                         interned_path: Box::into_raw(boxed) as *const Interned,
                     },
@@ -242,10 +645,24 @@ impl<'a> From<Value<'a>> for RawValue {
                 let ptr = r.as_ptr();
                 RawValue {
                     flags: Flags::INTERNED_PATH,
-                    extra: r.len() as u32,
+                    extra: r.len(),
                     value: RawValueInner { interned_path: ptr },
                 }
             }
+            Value::Map(map) => {
+                let (map, len) = ValueMap::into_raw(map);
+
+                RawValue {
+                    flags: Flags::MAP_BOXED,
+                    extra: len,
+                    value: RawValueInner { map },
+                }
+            }
+            Value::MapRef(map) => RawValue {
+                flags: Flags::MAP,
+                extra: map.len(),
+                value: RawValueInner { map: map.as_ptr() },
+            },
             Value::Node(node) => RawValue {
                 flags: Flags::NODE_BOXED,
                 extra: 0,
@@ -260,6 +677,20 @@ impl<'a> From<Value<'a>> for RawValue {
                     node_data: node.raw(),
                 },
             },
+            Value::SharedBlob(b) => RawValue {
+                flags: Flags::BLOB_SHARED,
+                extra: 0,
+                value: RawValueInner {
+                    shared: SharedBuffer::into_raw(b),
+                },
+            },
+            Value::SharedString(s) => RawValue {
+                flags: Flags::STRING_SHARED,
+                extra: 0,
+                value: RawValueInner {
+                    shared: SharedBuffer::into_raw(s),
+                },
+            },
             Value::Signed(signed) => RawValue {
                 flags: Flags::INTEGER_SIGNED,
                 extra: 0,
@@ -267,14 +698,14 @@ impl<'a> From<Value<'a>> for RawValue {
             },
             Value::String(s) => RawValue {
                 flags: Flags::STRING_BOXED,
-                extra: s.len() as u32,
+                extra: s.len(),
                 value: RawValueInner {
                     string: Box::into_raw(s) as *const u8,
                 },
             },
             Value::StringRef(s) => RawValue {
                 flags: Flags::STRING,
-                extra: s.len() as u32,
+                extra: s.len(),
                 value: RawValueInner { string: s.as_ptr() },
             },
             Value::Unsigned(unsigned) => RawValue {
@@ -282,16 +713,18 @@ impl<'a> From<Value<'a>> for RawValue {
                 extra: 0,
                 value: RawValueInner { unsigned },
             },
-            Value::ValueArray(array) => RawValue {
-                flags: Flags::VALUE_ARRAY_BOXED,
-                extra: array.len() as u32,
-                value: RawValueInner {
-                    value_array: Box::into_raw(array) as *const RawValue,
-                },
-            },
+            Value::ValueArray(array) => {
+                let (value_array, len) = ValueArray::into_raw(array);
+
+                RawValue {
+                    flags: Flags::VALUE_ARRAY_BOXED,
+                    extra: len,
+                    value: RawValueInner { value_array },
+                }
+            }
             Value::ValueArrayRef(array) => RawValue {
                 flags: Flags::VALUE_ARRAY,
-                extra: array.len() as u32,
+                extra: array.len(),
                 value: RawValueInner {
                     value_array: array.as_ptr(),
                 },
@@ -311,6 +744,285 @@ impl From<()> for RawValue {
     }
 }
 
+/// An owned array of values.
+///
+/// Dropping the array releases the owned payloads of all its elements.
+pub struct ValueArray<'a> {
+    values: Box<[RawValue]>,
+    marker: PhantomData<Value<'a>>,
+}
+
+impl<'a> ValueArray<'a> {
+    /// Takes ownership of a boxed slice of `len` raw values.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must come from `ValueArray::into_raw` and must not be used afterwards.
+    pub unsafe fn from_raw(ptr: *const RawValue, len: usize) -> Self {
+        ValueArray {
+            values: boxed_slice_from_raw(ptr, len),
+            marker: PhantomData,
+        }
+    }
+
+    /// Gives up ownership of the array, returning the pointer to its elements
+    /// and their number.
+    pub fn into_raw(this: Self) -> (*const RawValue, usize) {
+        let values = unsafe { read(&this.values) };
+        forget(this);
+
+        let len = values.len();
+
+        (Box::into_raw(values) as *const RawValue, len)
+    }
+
+    pub fn into_owned(self) -> ValueArray<'static> {
+        self.into_iter().map(Value::make_owned).collect()
+    }
+}
+
+impl<'a> Clone for ValueArray<'a> {
+    fn clone(&self) -> Self {
+        let values: Vec<RawValue> = self.iter().map(|raw| unsafe { raw.clone_raw() }).collect();
+
+        ValueArray {
+            values: values.into_boxed_slice(),
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<'a> Debug for ValueArray<'a> {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        f.debug_list()
+            .entries(self.iter().map(|raw| unsafe { raw.as_value() }))
+            .finish()
+    }
+}
+
+impl<'a> Deref for ValueArray<'a> {
+    type Target = [RawValue];
+
+    fn deref(&self) -> &[RawValue] {
+        &self.values
+    }
+}
+
+impl<'a> Drop for ValueArray<'a> {
+    fn drop(&mut self) {
+        for raw in self.values.iter_mut() {
+            unsafe {
+                raw.drop_in_place();
+            }
+        }
+    }
+}
+
+impl<'a> FromIterator<Value<'a>> for ValueArray<'a> {
+    fn from_iter<I>(iter: I) -> Self
+    where
+        I: IntoIterator<Item = Value<'a>>,
+    {
+        let values: Vec<RawValue> = iter.into_iter().map(RawValue::from).collect();
+
+        ValueArray {
+            values: values.into_boxed_slice(),
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<'a> IntoIterator for ValueArray<'a> {
+    type Item = Value<'a>;
+    type IntoIter = ValueArrayIntoIter<'a>;
+
+    fn into_iter(self) -> ValueArrayIntoIter<'a> {
+        let values = unsafe { read(&self.values) };
+        forget(self);
+
+        ValueArrayIntoIter {
+            iter: values.into_vec().into_iter(),
+            marker: PhantomData,
+        }
+    }
+}
+
+/// Moves the elements out of a `ValueArray`.
+pub struct ValueArrayIntoIter<'a> {
+    iter: IntoIter<RawValue>,
+    marker: PhantomData<Value<'a>>,
+}
+
+impl<'a> Iterator for ValueArrayIntoIter<'a> {
+    type Item = Value<'a>;
+
+    fn next(&mut self) -> Option<Value<'a>> {
+        self.iter.next().map(|raw| unsafe { Value::from_raw(raw) })
+    }
+}
+
+impl<'a> Drop for ValueArrayIntoIter<'a> {
+    fn drop(&mut self) {
+        for _ in self {}
+    }
+}
+
+/// An owned map of values, sorted by key.
+///
+/// Dropping the map releases the owned payloads of all its values.
+pub struct ValueMap<'a> {
+    entries: Box<[RawMapEntry]>,
+    marker: PhantomData<Value<'a>>,
+}
+
+impl<'a> ValueMap<'a> {
+    /// Takes ownership of a boxed slice of `len` map entries.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must come from `ValueMap::into_raw` and must not be used afterwards.
+    pub unsafe fn from_raw(ptr: *const RawMapEntry, len: usize) -> Self {
+        ValueMap {
+            entries: boxed_slice_from_raw(ptr, len),
+            marker: PhantomData,
+        }
+    }
+
+    /// Gives up ownership of the map, returning the pointer to its entries
+    /// and their number.
+    pub fn into_raw(this: Self) -> (*const RawMapEntry, usize) {
+        let entries = unsafe { read(&this.entries) };
+        forget(this);
+
+        let len = entries.len();
+
+        (Box::into_raw(entries) as *const RawMapEntry, len)
+    }
+
+    pub fn into_owned(self) -> ValueMap<'static> {
+        self.into_iter()
+            .map(|(key, value)| (key, value.make_owned()))
+            .collect()
+    }
+}
+
+impl<'a> Clone for ValueMap<'a> {
+    fn clone(&self) -> Self {
+        let entries: Vec<RawMapEntry> = self.iter()
+            .map(|entry| RawMapEntry {
+                key: entry.key,
+                value: unsafe { entry.value.clone_raw() },
+            })
+            .collect();
+
+        ValueMap {
+            entries: entries.into_boxed_slice(),
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<'a> Debug for ValueMap<'a> {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        f.debug_map()
+            .entries(
+                self.iter()
+                    .map(|entry| (entry.key, unsafe { entry.value.as_value() })),
+            )
+            .finish()
+    }
+}
+
+impl<'a> Deref for ValueMap<'a> {
+    type Target = [RawMapEntry];
+
+    fn deref(&self) -> &[RawMapEntry] {
+        &self.entries
+    }
+}
+
+impl<'a> Drop for ValueMap<'a> {
+    fn drop(&mut self) {
+        for entry in self.entries.iter_mut() {
+            unsafe {
+                entry.value.drop_in_place();
+            }
+        }
+    }
+}
+
+/// Collects the entries into a map, sorting them by key.
+/// If a key occurs multiple times, the last value wins.
+impl<'a> FromIterator<(Interned, Value<'a>)> for ValueMap<'a> {
+    fn from_iter<I>(iter: I) -> Self
+    where
+        I: IntoIterator<Item = (Interned, Value<'a>)>,
+    {
+        let mut entries: Vec<RawMapEntry> = iter.into_iter()
+            .map(|(key, value)| RawMapEntry {
+                key,
+                value: value.into(),
+            })
+            .collect();
+
+        // Stable, so duplicates keep their insertion order
+        entries.sort_by_key(|entry| entry.key);
+
+        let mut deduped: Vec<RawMapEntry> = Vec::with_capacity(entries.len());
+        for entry in entries {
+            match deduped.last_mut() {
+                Some(ref mut last) if last.key == entry.key => unsafe {
+                    last.value.drop_in_place();
+                    last.value = entry.value;
+                },
+                _ => deduped.push(entry),
+            }
+        }
+
+        ValueMap {
+            entries: deduped.into_boxed_slice(),
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<'a> IntoIterator for ValueMap<'a> {
+    type Item = (Interned, Value<'a>);
+    type IntoIter = ValueMapIntoIter<'a>;
+
+    fn into_iter(self) -> ValueMapIntoIter<'a> {
+        let entries = unsafe { read(&self.entries) };
+        forget(self);
+
+        ValueMapIntoIter {
+            iter: entries.into_vec().into_iter(),
+            marker: PhantomData,
+        }
+    }
+}
+
+/// Moves the entries out of a `ValueMap`.
+pub struct ValueMapIntoIter<'a> {
+    iter: IntoIter<RawMapEntry>,
+    marker: PhantomData<Value<'a>>,
+}
+
+impl<'a> Iterator for ValueMapIntoIter<'a> {
+    type Item = (Interned, Value<'a>);
+
+    fn next(&mut self) -> Option<(Interned, Value<'a>)> {
+        self.iter
+            .next()
+            .map(|entry| (entry.key, unsafe { Value::from_raw(entry.value) }))
+    }
+}
+
+impl<'a> Drop for ValueMapIntoIter<'a> {
+    fn drop(&mut self) {
+        for _ in self {}
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -319,8 +1031,60 @@ mod tests {
     #[test]
     fn check_size() {
         assert_eq!(size_of::<Flags>(), 4);
-        assert_eq!(size_of::<u32>(), 4);
         assert_eq!(size_of::<RawValueInner>(), 8);
-        assert_eq!(size_of::<RawValue>(), 16);
+        assert_eq!(size_of::<RawValue>(), 2 * size_of::<usize>() + 8);
+        assert_eq!(size_of::<RawMapEntry>(), 2 * size_of::<usize>() + 16);
+    }
+
+    #[test]
+    fn check_raw_actions() {
+        for raw in 0..0x100 {
+            if let Some(action) = Action::from_raw(raw) {
+                assert_eq!(action.into_raw(), raw);
+            }
+        }
+        assert_eq!(Action::from_raw(0x40), Some(Action::Handshake));
+        assert_eq!(Action::from_raw(0x41), None);
+    }
+
+    #[test]
+    fn check_unknown_flags() {
+        let mut value: RawValue = Value::Unsigned(5).into();
+        assert_eq!(value.kind(), Some(ValueKind::Unsigned));
+
+        value.flags |= Flags::_RESERVED;
+        assert_eq!(value.kind(), None);
+
+        let res = RawResult { status: 0, value };
+        assert_eq!(
+            unsafe { res.into_result() }.map_err(|e| e.kind()).err(),
+            Some(ErrorKind::UnknownTypeFlags)
+        );
+    }
+
+    #[test]
+    fn check_nested_unknown_flags() {
+        let mut inner: RawValue = Value::Unsigned(5).into();
+        inner.flags |= Flags::_RESERVED;
+        let entries = [RawMapEntry {
+            key: Interned::from("a"),
+            value: inner,
+        }];
+        let values = [Value::Bool(true).into(), Value::MapRef(&entries).into()];
+        let value: RawValue = Value::ValueArrayRef(&values).into();
+
+        assert_eq!(value.kind(), Some(ValueKind::ValueArray));
+        assert_eq!(
+            unsafe { value.validate() }.map_err(|e| e.kind()),
+            Err(ErrorKind::UnknownTypeFlags)
+        );
+        let res = RawResult { status: 0, value };
+        assert_eq!(
+            unsafe { res.into_result() }.map_err(|e| e.kind()).err(),
+            Some(ErrorKind::UnknownTypeFlags)
+        );
+
+        let values = [Value::Bool(true).into()];
+        assert_eq!(unsafe { RawValue::from(Value::ValueArrayRef(&values)).validate() }, Ok(()));
     }
 }
@@ -1,23 +1,370 @@
-#[derive(Clone, Copy, Debug, Fail, PartialEq)]
+use std::fmt::{Display, Formatter, Result as FmtResult};
+
+use {Interned, InternedMap, InternedPathBuf, Value, ValueConv, ValueKind};
+
+/// The compact error code; this is what crosses the raw ABI.
+///
+/// Codes below `ErrorKind::CUSTOM_BASE` are reserved for vnodes itself;
+/// nodes can report their own errors using `ErrorKind::Custom`.
+#[derive(Clone, Copy, Debug, Eq, Fail, Hash, PartialEq)]
 #[fail(display = "Vnodes error")]
-#[repr(u8)]
-pub enum Error {
+pub enum ErrorKind {
     #[fail(display = "Action not supported")]
-    ActionNotSupported = 0x1,
+    ActionNotSupported,
     #[fail(display = "Expected node")]
-    ExpectedNode = 0x2,
+    ExpectedNode,
     #[fail(display = "Invalid argument types")]
-    InvalidArgumentTypes = 0x3,
+    InvalidArgumentTypes,
     #[fail(display = "No such entry")]
-    NoSuchEntry = 0x4,
-    #[fail(display = "No such entry")]
-    PathEmpty = 0x5,
-    #[fail(display = "Unknown type")]
-    UnknownTypeFlags = 0x6,
+    NoSuchEntry,
+    #[fail(display = "Path is empty")]
+    PathEmpty,
     #[fail(display = "Unknown type")]
-    Utf8Error = 0x7,
+    UnknownTypeFlags,
+    #[fail(display = "Invalid UTF-8")]
+    Utf8Error,
     #[fail(display = "Wrong type")]
-    WrongType = 0x8, // TODO: `ExpectedNode` and `WrongType` intersect
+    WrongType, // TODO: `ExpectedNode` and `WrongType` intersect
+    #[fail(display = "Value out of range")]
+    OutOfRange,
+    #[fail(display = "Node panicked")]
+    Panicked,
+    #[fail(display = "Failed to load library")]
+    LibraryLoad,
+    #[fail(display = "Incompatible ABI version")]
+    AbiMismatch,
+    #[fail(display = "Invalid syntax")]
+    InvalidSyntax,
+    #[fail(display = "I/O error")]
+    Io,
+    /// An error defined by a node; the meaning of the code is up to the node.
+    #[fail(display = "Custom error {}", _0)]
+    Custom(u16),
+    /// A code this version of vnodes doesn't know about.
+    #[fail(display = "Unknown error code {:#x}", _0)]
+    Unknown(u32),
+}
+
+impl ErrorKind {
+    /// The first code of the range reserved for `ErrorKind::Custom`.
+    pub const CUSTOM_BASE: u32 = 0x1_0000;
+
+    pub fn code(self) -> u32 {
+        match self {
+            ErrorKind::ActionNotSupported => 0x1,
+            ErrorKind::ExpectedNode => 0x2,
+            ErrorKind::InvalidArgumentTypes => 0x3,
+            ErrorKind::NoSuchEntry => 0x4,
+            ErrorKind::PathEmpty => 0x5,
+            ErrorKind::UnknownTypeFlags => 0x6,
+            ErrorKind::Utf8Error => 0x7,
+            ErrorKind::WrongType => 0x8,
+            ErrorKind::OutOfRange => 0x9,
+            ErrorKind::Panicked => 0xA,
+            ErrorKind::LibraryLoad => 0xB,
+            ErrorKind::AbiMismatch => 0xC,
+            ErrorKind::InvalidSyntax => 0xD,
+            ErrorKind::Io => 0xE,
+            ErrorKind::Custom(code) => ErrorKind::CUSTOM_BASE + code as u32,
+            ErrorKind::Unknown(code) => code,
+        }
+    }
+
+    pub fn from_code(code: u32) -> Self {
+        match code {
+            0x1 => ErrorKind::ActionNotSupported,
+            0x2 => ErrorKind::ExpectedNode,
+            0x3 => ErrorKind::InvalidArgumentTypes,
+            0x4 => ErrorKind::NoSuchEntry,
+            0x5 => ErrorKind::PathEmpty,
+            0x6 => ErrorKind::UnknownTypeFlags,
+            0x7 => ErrorKind::Utf8Error,
+            0x8 => ErrorKind::WrongType,
+            0x9 => ErrorKind::OutOfRange,
+            0xA => ErrorKind::Panicked,
+            0xB => ErrorKind::LibraryLoad,
+            0xC => ErrorKind::AbiMismatch,
+            0xD => ErrorKind::InvalidSyntax,
+            0xE => ErrorKind::Io,
+            code if code >= ErrorKind::CUSTOM_BASE && code - ErrorKind::CUSTOM_BASE <= 0xFFFF => {
+                ErrorKind::Custom((code - ErrorKind::CUSTOM_BASE) as u16)
+            }
+            code => ErrorKind::Unknown(code),
+        }
+    }
+}
+
+/// An error report: the `ErrorKind` plus whatever context is known about where
+/// and why it happened.
+///
+/// Only the kind survives a trip through the raw ABI.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Error {
+    kind: ErrorKind,
+    // Boxed so `Result`s stay small on the happy path
+    context: Option<Box<ErrorContext>>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+struct ErrorContext {
+    path: Option<InternedPathBuf>,
+    component: Option<Interned>,
+    expected: Option<ValueKind>,
+    actual: Option<ValueKind>,
+    message: Option<String>,
+}
+
+impl Error {
+    pub fn new(kind: ErrorKind) -> Self {
+        Error {
+            kind,
+            context: None,
+        }
+    }
+
+    /// Creates an error with a node defined code and a message.
+    pub fn custom<M>(code: u16, message: M) -> Self
+    where
+        M: Into<String>,
+    {
+        Error::new(ErrorKind::Custom(code)).with_message(message)
+    }
+
+    /// Creates a `WrongType` error for a value of kind `actual`.
+    pub fn wrong_type(expected: ValueKind, actual: ValueKind) -> Self {
+        let mut error = Error::new(ErrorKind::WrongType);
+        {
+            let context = error.context_mut();
+            context.expected = Some(expected);
+            context.actual = Some(actual);
+        }
+
+        error
+    }
+
+    pub fn kind(&self) -> ErrorKind {
+        self.kind
+    }
+
+    /// The path that was accessed when the error occurred.
+    pub fn path(&self) -> Option<&InternedPathBuf> {
+        self.context.as_ref().and_then(|c| c.path.as_ref())
+    }
+
+    /// The path component at which resolving the path failed.
+    pub fn component(&self) -> Option<Interned> {
+        self.context.as_ref().and_then(|c| c.component)
+    }
+
+    pub fn expected(&self) -> Option<ValueKind> {
+        self.context.as_ref().and_then(|c| c.expected)
+    }
+
+    pub fn actual(&self) -> Option<ValueKind> {
+        self.context.as_ref().and_then(|c| c.actual)
+    }
+
+    pub fn message(&self) -> Option<&str> {
+        self.context
+            .as_ref()
+            .and_then(|c| c.message.as_ref())
+            .map(String::as_str)
+    }
+
+    /// Records `path` unless a (more specific) path has been recorded already.
+    pub fn with_path<P>(mut self, path: P) -> Self
+    where
+        P: Into<InternedPathBuf>,
+    {
+        if self.path().is_none() {
+            self.context_mut().path = Some(path.into());
+        }
+
+        self
+    }
+
+    /// Records `component` unless one has been recorded already.
+    pub fn with_component(mut self, component: Interned) -> Self {
+        if self.component().is_none() {
+            self.context_mut().component = Some(component);
+        }
+
+        self
+    }
+
+    pub fn with_message<M>(mut self, message: M) -> Self
+    where
+        M: Into<String>,
+    {
+        self.context_mut().message = Some(message.into());
+
+        self
+    }
+
+    fn context_mut(&mut self) -> &mut ErrorContext {
+        self.context.get_or_insert_with(Default::default)
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        write!(f, "{}", self.kind)?;
+
+        if let Some(path) = self.path() {
+            write!(f, " at `{}`", path)?;
+        }
+        if let Some(component) = self.component() {
+            write!(f, " (component `{}`)", component)?;
+        }
+        match (self.expected(), self.actual()) {
+            (Some(expected), Some(actual)) => write!(f, ": expected {}, got {}", expected, actual)?,
+            (Some(expected), None) => write!(f, ": expected {}", expected)?,
+            (None, Some(actual)) => write!(f, ": got {}", actual)?,
+            (None, None) => {}
+        }
+        if let Some(message) = self.message() {
+            write!(f, ": {}", message)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl ::failure::Fail for Error {}
+
+impl From<ErrorKind> for Error {
+    fn from(kind: ErrorKind) -> Self {
+        Error::new(kind)
+    }
+}
+
+/// Errors are stored as maps with a `code` and optionally a `message` and a `path`,
+/// so they can be kept in the tree like any other data.
+impl<'a> ValueConv<'a> for Error {
+    fn from_value(value: Value<'a>) -> Result<Self> {
+        let mut map: InternedMap<Value> = ValueConv::from_value(value)?;
+
+        let code = Interned::from("code");
+        let code = map
+            .remove(code)
+            .ok_or_else(|| Error::new(ErrorKind::NoSuchEntry).with_component(code))
+            .and_then(u32::from_value)?;
+        let mut error = Error::new(ErrorKind::from_code(code));
+
+        if let Some(message) = map.remove(Interned::from("message")) {
+            error = error.with_message(String::from_value(message)?);
+        }
+        if let Some(path) = map.remove(Interned::from("path")) {
+            error = error.with_path(InternedPathBuf::from_value(path)?);
+        }
+
+        Ok(error)
+    }
+
+    fn into_value(self) -> Value<'a> {
+        let mut map = InternedMap::new();
+        map.insert(Interned::from("code"), Value::Unsigned(self.kind.code() as u64));
+        if let Some(message) = self.message() {
+            map.insert(Interned::from("message"), message.to_owned().into_value());
+        }
+        if let Some(path) = self.path() {
+            map.insert(Interned::from("path"), path.clone().into_value());
+        }
+
+        map.into_value()
+    }
 }
 
 pub type Result<T> = ::std::result::Result<T, Error>;
+
+/// Strips the context from an error, so tests can compare kinds only.
+#[cfg(test)]
+pub fn error_kind<T>(res: Result<T>) -> Option<ErrorKind> {
+    res.err().map(|e| e.kind())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::mem::size_of;
+    use {MapNode, Vnodes};
+
+    #[test]
+    fn check_small() {
+        assert_eq!(size_of::<Error>(), 2 * size_of::<usize>());
+    }
+
+    #[test]
+    fn check_display() {
+        let error = Error::wrong_type(ValueKind::Unsigned, ValueKind::String)
+            .with_path("/a/b")
+            .with_component(Interned::from("b"))
+            .with_message("while loading");
+
+        assert_eq!(
+            error.to_string(),
+            "Wrong type at `/a/b` (component `b`): expected unsigned, got string: while loading"
+        );
+        assert_eq!(Error::from(ErrorKind::PathEmpty).to_string(), "Path is empty");
+    }
+
+    #[test]
+    fn check_codes() {
+        let kinds = [
+            ErrorKind::ActionNotSupported,
+            ErrorKind::Panicked,
+            ErrorKind::AbiMismatch,
+            ErrorKind::InvalidSyntax,
+            ErrorKind::Io,
+            ErrorKind::Custom(0),
+            ErrorKind::Custom(0xFFFF),
+            ErrorKind::Unknown(0x42),
+            ErrorKind::Unknown(0x2_0000),
+        ];
+
+        for &kind in &kinds {
+            assert_eq!(ErrorKind::from_code(kind.code()), kind);
+        }
+        assert_eq!(ErrorKind::Custom(3).code(), ErrorKind::CUSTOM_BASE + 3);
+    }
+
+    #[test]
+    fn check_stored_errors() {
+        let nodes = Vnodes::new();
+        let error = Error::custom(4, "asset not loaded").with_path("/assets/cube");
+        nodes.insert("/last_error", error.clone()).unwrap();
+
+        // A stored error is data, reading it succeeds
+        assert_eq!(nodes.get("/last_error"), Ok(error));
+        assert!(nodes.get::<_, Value>("/last_error").is_ok());
+        // A failed read is an `Err`
+        assert_eq!(
+            error_kind(nodes.get::<_, Error>("/no_error")),
+            Some(ErrorKind::NoSuchEntry)
+        );
+    }
+
+    #[test]
+    fn check_path_context() {
+        let nodes = Vnodes::new();
+        nodes.insert("/a", MapNode::new_node()).unwrap();
+        nodes.insert("/a/b", MapNode::new_node()).unwrap();
+        nodes.insert("/a/b/c", 1u64).unwrap();
+
+        let error = nodes.get::<_, u64>("/a/x/c").unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::NoSuchEntry);
+        assert_eq!(error.path(), Some(&InternedPathBuf::from("/a/x/c")));
+        assert_eq!(error.component(), Some(Interned::from("x")));
+
+        let error = nodes.get::<_, String>("/a/b/c").unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::WrongType);
+        assert_eq!(error.path(), Some(&InternedPathBuf::from("/a/b/c")));
+        assert_eq!(error.expected(), Some(ValueKind::String));
+        assert_eq!(error.actual(), Some(ValueKind::Unsigned));
+
+        let error = nodes.get::<_, u64>("/a/b/c/d").unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::WrongType);
+        assert_eq!(error.component(), Some(Interned::from("c")));
+        assert_eq!(error.actual(), Some(ValueKind::Unsigned));
+    }
+}
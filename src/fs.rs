@@ -1,9 +1,21 @@
-#![allow(unused)]
-
+use std::collections::HashMap;
+use std::fs;
+use std::io::{Error as IoError, ErrorKind as IoErrorKind};
 use std::path::PathBuf;
 
-use {Interned, Node, NodeHandle, Result, Value, Vnodes};
+use intern::is_internable;
+use {Error, ErrorKind, Interned, Node, NodeHandle, NodeMeta, Result, Value, ValueConv, Vnodes};
 
+/// Exposes a directory of the file system as a read-only node.
+///
+/// Subdirectories become nodes and files become values, which are read on
+/// every access: a string if the file is valid UTF-8 and a blob otherwise.
+/// Entries whose names can't be interned are skipped.
+///
+/// Interning folds uppercase letters to lowercase and `-` to `_`, so `Sub`
+/// is found as `sub` and `a-b` as `a_b`. Entries whose folded names collide,
+/// like `Sub` and `sub`, are skipped as well, since neither can be told
+/// apart from the other.
 pub struct FsNode {
     path: PathBuf,
 }
@@ -15,18 +27,124 @@ impl FsNode {
     {
         NodeHandle::new(FsNode { path: path.into() })
     }
+
+    fn entries(&self) -> Result<Vec<(Interned, PathBuf)>> {
+        // Colliding names map to `None`
+        let mut entries = HashMap::new();
+        for entry in fs::read_dir(&self.path).map_err(io_error)? {
+            let entry = entry.map_err(io_error)?;
+            if let Some(name) = entry.file_name().to_str() {
+                if is_internable(name) {
+                    entries
+                        .entry(Interned::from(name))
+                        .and_modify(|path| *path = None)
+                        .or_insert_with(|| Some(entry.path()));
+                }
+            }
+        }
+
+        Ok(entries
+            .into_iter()
+            .filter_map(|(name, path)| path.map(|path| (name, path)))
+            .collect())
+    }
 }
 
 impl Node for FsNode {
-    fn call(&self, context: &Vnodes, args: &[Value]) -> Result<Value> {
-        unimplemented!()
+    fn get(&self, _: &Vnodes, ident: Interned) -> Result<Value<'_>> {
+        let (_, path) = self
+            .entries()?
+            .into_iter()
+            .find(|&(name, _)| name == ident)
+            .ok_or(ErrorKind::NoSuchEntry)?;
+
+        if path.is_dir() {
+            return Ok(Value::Node(FsNode::new_node(path)));
+        }
+
+        let bytes = fs::read(&path).map_err(io_error)?;
+
+        Ok(match String::from_utf8(bytes) {
+            Ok(string) => string.into_value(),
+            Err(e) => Value::Blob(e.into_bytes().into_boxed_slice()),
+        })
+    }
+
+    fn list(&self, _: &Vnodes) -> Result<Vec<Interned>> {
+        Ok(self.entries()?.into_iter().map(|(name, _)| name).collect())
     }
 
-    fn get(&self, context: &Vnodes, ident: Interned) -> Result<Value> {
-        unimplemented!()
+    fn meta(&self, _: &Vnodes) -> NodeMeta {
+        NodeMeta {
+            list: true,
+            read_only: true,
+            ..NodeMeta::new::<Self>()
+        }
     }
+}
+
+fn io_error(e: IoError) -> Error {
+    let kind = match e.kind() {
+        IoErrorKind::NotFound => ErrorKind::NoSuchEntry,
+        _ => ErrorKind::Io,
+    };
+
+    Error::new(kind).with_message(e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::process;
+
+    #[test]
+    fn check_mount() {
+        let dir = env::temp_dir().join(format!("vnodes-fs-{}", process::id()));
+        fs::create_dir_all(dir.join("Sub")).unwrap();
+        fs::write(dir.join("conf.ron"), "(vsync: true)").unwrap();
+        fs::write(dir.join("Sub/data.bin"), [0xFFu8, 0]).unwrap();
+        fs::write(dir.join("not internable"), "").unwrap();
+        fs::write(dir.join("a-b"), "dash").unwrap();
+        fs::write(dir.join("x-y"), "").unwrap();
+        fs::write(dir.join("X_y"), "").unwrap();
+
+        let nodes = Vnodes::new();
+        nodes.insert("/fs", FsNode::new_node(&dir)).unwrap();
+
+        let mut names = nodes.list("/fs").unwrap();
+        names.sort_by_key(|i| i.into_inner());
+        let mut expected = vec![
+            Interned::from("a_b"),
+            Interned::from("conf.ron"),
+            Interned::from("sub"),
+        ];
+        expected.sort_by_key(|i| i.into_inner());
+        assert_eq!(names, expected);
+
+        assert_eq!(
+            nodes.get::<_, String>("/fs/conf.ron").unwrap(),
+            "(vsync: true)"
+        );
+        assert_eq!(
+            &*nodes.get::<_, Box<[u8]>>("/fs/sub/data.bin").unwrap(),
+            &[0xFF, 0]
+        );
+        assert_eq!(nodes.get::<_, String>("/fs/a-b").unwrap(), "dash");
+        assert_eq!(
+            nodes.get::<_, Value>("/fs/x_y").unwrap_err().kind(),
+            ErrorKind::NoSuchEntry
+        );
+        assert!(nodes.meta("/fs/sub").unwrap().read_only);
+        assert_eq!(
+            nodes.insert("/fs/new", 1u64).unwrap_err().kind(),
+            ErrorKind::ActionNotSupported
+        );
 
-    fn set(&self, context: &Vnodes, ident: Interned, value: Value) -> Result<()> {
-        unimplemented!()
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(
+            nodes.list("/fs").unwrap_err().kind(),
+            ErrorKind::NoSuchEntry
+        );
     }
 }
@@ -1,3 +1,4 @@
+use std::fmt::{Display, Formatter, Result as FmtResult};
 use std::str::from_utf8_unchecked;
 
 #[derive(Debug)]
@@ -22,6 +23,11 @@ impl Interned {
         unsafe { from_utf8_unchecked(&buf[..num]) }
     }
 
+    /// Writes the name to `buf`, which must hold at least 10 bytes, and
+    /// returns its length.
+    ///
+    /// `Interned` can hold any `u64`; codes which don't stand for a character
+    /// are written as `?`.
     pub fn un_intern_raw(&self, buf: &mut [u8]) -> usize {
         assert!(buf.len() >= 10);
 
@@ -61,7 +67,7 @@ impl Interned {
                 continue;
             }
 
-            buf[buf_ind] = array[code as usize];
+            buf[buf_ind] = array.get(code as usize).cloned().unwrap_or(b'?');
             buf_ind += 1;
         }
     }
@@ -71,6 +77,14 @@ impl Interned {
     }
 }
 
+impl Display for Interned {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        let mut buf = [0; 10];
+
+        f.write_str(self.un_intern(&mut buf))
+    }
+}
+
 impl<'a> From<&'a Interned> for Interned {
     fn from(x: &'a Interned) -> Self {
         *x
@@ -101,6 +115,15 @@ impl<'a> From<&'a str> for Interned {
     }
 }
 
+/// Checks if `name` can be interned; `Interned::from` panics on unsupported
+/// characters and names longer than 10 characters don't fit.
+pub fn is_internable(name: &str) -> bool {
+    name.len() <= 10
+        && name
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_' || b == b'.')
+}
+
 fn intern_byte(b: u8) -> u8 {
     match b {
         b'a'...b'z' => 1 + b - b'a',
@@ -166,6 +189,20 @@ impl InternedPathBuf {
     }
 }
 
+impl Display for InternedPathBuf {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        for (i, component) in self.0.iter().enumerate() {
+            if i != 0 {
+                f.write_str("/")?;
+            }
+
+            write!(f, "{}", component)?;
+        }
+
+        Ok(())
+    }
+}
+
 impl From<Box<[Interned]>> for InternedPathBuf {
     fn from(slice: Box<[Interned]>) -> Self {
         InternedPathBuf(slice.into_vec())
@@ -211,6 +248,13 @@ mod tests {
         assert!(path.is_absolute());
     }
 
+    #[test]
+    fn display() {
+        let path = InternedPathBuf::from("/this/is/absolute");
+        assert_eq!(path.to_string(), "/this/is/absolute");
+        assert_eq!(InternedPathBuf::from("a/b").to_string(), "a/b");
+    }
+
     fn check_same(s: &str) {
         let interned = Interned::from(s);
         let mut un_interned = [0; 10];
@@ -236,4 +280,21 @@ mod tests {
         check_exact_same("my.world");
         check_exact_same("007");
     }
+
+    #[test]
+    fn invalid_codes() {
+        assert_eq!(Interned(0x3F).to_string(), "?");
+        assert_eq!(Interned((1 << 6) | 39).to_string(), "a?");
+        assert_eq!(Interned(!0).to_string(), "??????????");
+    }
+
+    #[test]
+    fn internable() {
+        assert!(is_internable("my-world"));
+        assert!(is_internable("0123456789"));
+        assert!(is_internable(""));
+        assert!(!is_internable("01234567890"));
+        assert!(!is_internable("a b"));
+        assert!(!is_internable("größe"));
+    }
 }
@@ -7,19 +7,35 @@ extern crate derivative;
 #[macro_use]
 extern crate failure;
 extern crate fxhash;
+#[cfg(feature = "plugin")]
+extern crate libloading;
 #[macro_use]
 extern crate log;
+#[cfg(feature = "lua")]
+extern crate mlua;
 extern crate parking_lot;
+#[cfg(feature = "wasm")]
+extern crate wasmi;
+#[cfg(all(test, feature = "wasm"))]
+extern crate wat;
 
 pub use conv::ValueConv;
-pub use data::Value;
-pub use error::{Error, Result};
-pub use intern::{Interned, InternedPath, InternedPathBuf};
+pub use data::{Value, ValueArray, ValueArrayIntoIter, ValueKind, ValueMap, ValueMapIntoIter};
+pub use error::{Error, ErrorKind, Result};
+pub use fs::FsNode;
+pub use intern::{is_internable, Interned, InternedPath, InternedPathBuf};
+#[cfg(feature = "lua")]
+pub use lua::LuaNode;
 pub use map::{InternedMap, MapNode};
-pub use node::{Node, NodeData, NodeHandle, NodeHandleRef, NodeMut};
+pub use node::{Node, NodeData, NodeHandle, NodeHandleRef, NodeMeta, NodeMut, WeakNodeHandle};
+pub use shared::SharedBuffer;
+#[cfg(feature = "wasm")]
+pub use wasm::{WasmLimits, WasmNode};
 
+#[macro_use]
 mod macros;
 
+pub mod console;
 pub mod raw;
 
 mod conv;
@@ -27,9 +43,16 @@ mod data;
 mod error;
 mod fs;
 mod intern;
+#[cfg(feature = "lua")]
+mod lua;
 mod map;
 mod node;
+#[cfg(feature = "plugin")]
+mod plugin;
+mod shared;
 mod util;
+#[cfg(feature = "wasm")]
+mod wasm;
 
 pub struct Vnodes {
     current: NodeHandle,
@@ -54,13 +77,302 @@ impl Vnodes {
         self.get_no_alloc(path, |val| R::from_value(val.make_owned()))
     }
 
+    /// Like `get`, but returns `Ok(None)` if there's no entry at `path`.
+    ///
+    /// Other errors, e.g. a value of the wrong type, are still reported.
+    pub fn get_opt<I, R>(&self, path: I) -> Result<Option<R>>
+    where
+        I: Into<InternedPathBuf>,
+        R: ValueConv<'static>,
+    {
+        let path_buf = path.into();
+        let value = match self.walk(path_buf.path(), |value| Ok(value.make_owned())) {
+            Ok(value) => value,
+            Err(ref e) if e.kind() == ErrorKind::NoSuchEntry => return Ok(None),
+            Err(e) => return Err(e.with_path(path_buf)),
+        };
+
+        R::from_value(value)
+            .map(Some)
+            .map_err(|e| e.with_path(path_buf))
+    }
+
+    /// Returns the value at `path` or `default` if there's no entry.
+    pub fn get_or<I, R>(&self, path: I, default: R) -> Result<R>
+    where
+        I: Into<InternedPathBuf>,
+        R: ValueConv<'static>,
+    {
+        self.get_opt(path).map(|value| value.unwrap_or(default))
+    }
+
+    /// Returns the value at `path`, inserting the result of `f` first if there's no entry.
+    pub fn get_or_insert_with<F, I, R>(&self, path: I, f: F) -> Result<R>
+    where
+        F: FnOnce() -> R,
+        I: Into<InternedPathBuf>,
+        R: ValueConv<'static>,
+    {
+        let path_buf = path.into();
+
+        match self.get_opt(path_buf.clone())? {
+            Some(value) => Ok(value),
+            None => {
+                self.insert_if_absent(path_buf.clone(), f())?;
+
+                // Read it back, the node may have transformed the value or
+                // another thread may have inserted one first
+                self.get(path_buf)
+            }
+        }
+    }
+
+    /// Returns `true` if `path` resolves to a value.
+    pub fn exists<I>(&self, path: I) -> bool
+    where
+        I: Into<InternedPathBuf>,
+    {
+        self.get_no_alloc(path, |_| Ok(())).is_ok()
+    }
+
+    /// Returns the kind of the value at `path` or `None` if there's no entry.
+    pub fn type_of<I>(&self, path: I) -> Result<Option<ValueKind>>
+    where
+        I: Into<InternedPathBuf>,
+    {
+        let path_buf = path.into();
+
+        match self.walk(path_buf.path(), |value| Ok(value.kind())) {
+            Ok(kind) => Ok(Some(kind)),
+            Err(ref e) if e.kind() == ErrorKind::NoSuchEntry => Ok(None),
+            Err(e) => Err(e.with_path(path_buf)),
+        }
+    }
+
     pub fn get_no_alloc<F, I, R>(&self, path: I, f: F) -> Result<R>
     where
         F: FnOnce(Value) -> Result<R>,
         I: Into<InternedPathBuf>,
     {
         let path_buf = path.into();
-        let mut path = path_buf.path();
+        let res = self.walk(path_buf.path(), f);
+
+        res.map_err(|e| e.with_path(path_buf))
+    }
+
+    /// Calls the node at `path`; tuples are passed as multiple arguments.
+    pub fn call<I, A, R>(&self, path: I, args: A) -> Result<R>
+    where
+        I: Into<InternedPathBuf>,
+        A: ValueConv<'static>,
+        R: ValueConv<'static>,
+    {
+        self.get_no_alloc(path, |node| {
+            let node = node.as_node_handle()?;
+            let ret = args.with_raw(|args| unsafe { node.call_raw(self, args) })?;
+
+            R::from_value(ret.make_owned())
+        })
+    }
+
+    /// Lists the children of the node at `path`.
+    pub fn list<I>(&self, path: I) -> Result<Vec<Interned>>
+    where
+        I: Into<InternedPathBuf>,
+    {
+        self.get_no_alloc(path, |node| node.as_node_handle()?.list(self))
+    }
+
+    /// Removes the entry at `path`, returning its value.
+    pub fn remove<I, R>(&self, path: I) -> Result<R>
+    where
+        I: Into<InternedPathBuf>,
+        R: ValueConv<'static>,
+    {
+        let path_buf = path.into();
+        let res = {
+            let (&target, parent) = path_buf.path().split_last().ok_or(ErrorKind::PathEmpty)?;
+
+            self.walk(parent, |node| {
+                let removed = node.as_node_handle()?.remove(self, target)?;
+
+                R::from_value(removed)
+            })
+        };
+
+        res.map_err(|e| e.with_path(path_buf))
+    }
+
+    /// Describes the node at `path`.
+    pub fn meta<I>(&self, path: I) -> Result<NodeMeta>
+    where
+        I: Into<InternedPathBuf>,
+    {
+        self.get_no_alloc(path, |value| value.as_node_handle()?.meta(self))
+    }
+
+    pub fn insert<I, V>(&self, path: I, value: V) -> Result<()>
+    where
+        I: Into<InternedPathBuf>,
+        V: ValueConv<'static>,
+    {
+        let path_buf = path.into();
+        let res = {
+            let (&target, parent) = path_buf.path().split_last().ok_or(ErrorKind::PathEmpty)?;
+            let value = value.into_value();
+
+            self.walk(parent, |node| {
+                node.as_node_handle()
+                    .and_then(move |node| node.insert(self, target, value))
+            })
+        };
+
+        res.map_err(|e| e.with_path(path_buf))
+    }
+
+    /// Inserts `value` at `path` unless there's an entry; returns `true` if it was inserted.
+    ///
+    /// Nodes which don't support `Action::SetIfAbsent` are checked for the
+    /// entry first, so other threads may modify them in between.
+    pub fn insert_if_absent<I, V>(&self, path: I, value: V) -> Result<bool>
+    where
+        I: Into<InternedPathBuf>,
+        V: ValueConv<'static>,
+    {
+        let path_buf = path.into();
+        let res = {
+            let (&target, parent) = path_buf.path().split_last().ok_or(ErrorKind::PathEmpty)?;
+            let value = value.into_value();
+
+            self.walk(parent, |node| {
+                let node = node.as_node_handle()?;
+                match node.set_if_absent(self, target, value.clone()) {
+                    Err(ref e) if e.kind() == ErrorKind::ActionNotSupported => {}
+                    res => return res,
+                }
+
+                let exists = match node.get(self, target) {
+                    Ok(_) => true,
+                    Err(ref e) if e.kind() == ErrorKind::NoSuchEntry => false,
+                    Err(e) => return Err(e),
+                };
+
+                match exists {
+                    true => Ok(false),
+                    false => node.insert(self, target, value).map(|_| true),
+                }
+            })
+        };
+
+        res.map_err(|e| e.with_path(path_buf))
+    }
+
+    /// Inserts `value` at `path`, creating missing intermediate nodes as `MapNode`s.
+    pub fn insert_all<I, V>(&self, path: I, value: V) -> Result<()>
+    where
+        I: Into<InternedPathBuf>,
+        V: ValueConv<'static>,
+    {
+        self.insert_all_with(path, value, MapNode::new_node)
+    }
+
+    /// Inserts `value` at `path`, creating missing intermediate nodes with `factory`.
+    pub fn insert_all_with<F, I, V>(&self, path: I, value: V, factory: F) -> Result<()>
+    where
+        F: FnMut() -> NodeHandle,
+        I: Into<InternedPathBuf>,
+        V: ValueConv<'static>,
+    {
+        let path_buf = path.into();
+        {
+            let (_, parent) = path_buf.path().split_last().ok_or(ErrorKind::PathEmpty)?;
+            self.create_dirs_with(parent, factory)?;
+        }
+
+        self.insert(path_buf, value)
+    }
+
+    /// Creates all missing nodes along `path` as `MapNode`s, like `mkdir -p`.
+    pub fn create_dirs<I>(&self, path: I) -> Result<()>
+    where
+        I: Into<InternedPathBuf>,
+    {
+        self.create_dirs_with(path, MapNode::new_node)
+    }
+
+    /// Creates all missing nodes along `path` with `factory`.
+    ///
+    /// Fails if a component exists, but isn't a node.
+    pub fn create_dirs_with<F, I>(&self, path: I, mut factory: F) -> Result<()>
+    where
+        F: FnMut() -> NodeHandle,
+        I: Into<InternedPathBuf>,
+    {
+        let path_buf = path.into();
+        let path = path_buf.path();
+        // The root of an absolute path always exists
+        let start = match path.first().cloned() {
+            Some(Interned(0)) => 1,
+            _ => 0,
+        };
+
+        for end in start + 1..path.len() + 1 {
+            let prefix = &path[..end];
+
+            // Another thread may create the same node, so it's only
+            // inserted if it's still missing
+            let kind = match self.type_of(prefix)? {
+                None => {
+                    self.insert_if_absent(prefix, factory())?;
+                    self.type_of(prefix)?
+                }
+                kind => kind,
+            };
+
+            match kind {
+                Some(ValueKind::Node) => {}
+                Some(kind) => {
+                    return Err(Error::wrong_type(ValueKind::Node, kind)
+                        .with_component(path[end - 1])
+                        .with_path(path_buf.clone()))
+                }
+                None => {
+                    return Err(Error::new(ErrorKind::NoSuchEntry)
+                        .with_component(path[end - 1])
+                        .with_path(path_buf.clone()))
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Loads the plugin `library` (a shared library built with `export_plugin!`)
+    /// and mounts its root node at `path`.
+    ///
+    /// The library is unloaded once all handles to its nodes are dropped,
+    /// including the nodes the plugin inserted through its context.
+    ///
+    /// # Safety
+    ///
+    /// Loading a library runs its initialization code, and the plugin has to
+    /// be built against the same version of vnodes; only the ABI version is checked.
+    #[cfg(feature = "plugin")]
+    pub unsafe fn load_plugin<L, I>(&self, library: L, path: I) -> Result<()>
+    where
+        L: AsRef<::std::ffi::OsStr>,
+        I: Into<InternedPathBuf>,
+    {
+        let node = plugin::load(self, library.as_ref())?;
+
+        self.insert(path, node)
+    }
+
+    /// Resolves `path` and passes the value to `f`; errors don't record the path yet.
+    fn walk<F, R>(&self, mut path: &InternedPath, f: F) -> Result<R>
+    where
+        F: FnOnce(Value) -> Result<R>,
+    {
         let start = match path.get(0).cloned() {
             Some(Interned(0)) => {
                 path = &path[1..];
@@ -75,38 +387,27 @@ impl Vnodes {
             _ => walk_node(self, start, path, f),
         }
     }
-
-    pub fn insert<I, V>(&self, path: I, value: V) -> Result<()>
-    where
-        I: Into<InternedPathBuf>,
-        V: ValueConv<'static>,
-    {
-        let mut path_buf = path.into();
-        let target = path_buf.pop().ok_or(Error::PathEmpty)?;
-
-        let value = value.into_value();
-
-        self.get_no_alloc(path_buf, |node| {
-            node.as_node_handle()
-                .map(move |node| node.insert(self, target, value))
-        })
-    }
 }
 
 fn walk_node<F, R>(context: &Vnodes, handle: NodeHandleRef, path: &InternedPath, f: F) -> Result<R>
 where
     F: FnOnce(Value) -> Result<R>,
 {
-    // TODO: let node methods return Result
-    // TODO: and: should the Rust `Value` even store errors?
-    let value = handle.get(context, path[0]).into_res()?;
+    let component = path[0];
+    let value = handle
+        .get(context, component)
+        .map_err(|e| e.with_component(component))?;
 
     match path.get(1).cloned() {
         None => f(value),
         Some(Interned(0)) => {
             match value {
                 Value::Node(_) | Value::NodeRef(_) => {}
-                _ => return Err(Error::ExpectedNode),
+                ref other => {
+                    return Err(Error::from(ErrorKind::ExpectedNode)
+                        .with_component(component)
+                        .with_message(format!("got {}", other.kind())))
+                }
             }
 
             f(value)
@@ -115,10 +416,151 @@ where
             let handle_ref = match value {
                 Value::Node(ref node) => node.handle_ref(),
                 Value::NodeRef(node) => node,
-                _ => return Err(Error::WrongType),
+                ref other => {
+                    return Err(Error::wrong_type(ValueKind::Node, other.kind())
+                        .with_component(component))
+                }
             };
 
             walk_node(context, handle_ref, &path[1..], f)
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use error::error_kind;
+
+    fn config() -> Vnodes {
+        let nodes = Vnodes::new();
+        nodes.insert("/graphics", MapNode::new_node()).unwrap();
+        nodes.insert("/graphics/quality", 2u64).unwrap();
+        nodes.insert("/graphics/name", "high".to_owned()).unwrap();
+
+        nodes
+    }
+
+    #[test]
+    fn check_get_opt() {
+        let nodes = config();
+
+        assert_eq!(nodes.get_opt("/graphics/quality"), Ok(Some(2u64)));
+        assert_eq!(nodes.get_opt::<_, u64>("/graphics/shadows"), Ok(None));
+        assert_eq!(nodes.get_opt::<_, u64>("/audio/volume"), Ok(None));
+        assert_eq!(
+            error_kind(nodes.get_opt::<_, u64>("/graphics/name")),
+            Some(ErrorKind::WrongType)
+        );
+    }
+
+    #[test]
+    fn check_get_or() {
+        let nodes = config();
+
+        assert_eq!(nodes.get_or("/graphics/quality", 0u64), Ok(2));
+        assert_eq!(nodes.get_or("/graphics/shadows", 1u64), Ok(1));
+        assert_eq!(
+            error_kind(nodes.get_or("/graphics/name", 0u64)),
+            Some(ErrorKind::WrongType)
+        );
+    }
+
+    #[test]
+    fn check_get_or_insert_with() {
+        let nodes = config();
+
+        assert_eq!(nodes.get_or_insert_with("/graphics/quality", || 0u64), Ok(2));
+        assert_eq!(nodes.get_or_insert_with("/graphics/shadows", || 3u64), Ok(3));
+        assert_eq!(nodes.get("/graphics/shadows"), Ok(3u64));
+        assert_eq!(
+            error_kind(nodes.get_or_insert_with("/audio/volume", || 1u64)),
+            Some(ErrorKind::NoSuchEntry)
+        );
+    }
+
+    #[test]
+    fn check_insert_all() {
+        let nodes = config();
+
+        nodes.insert_all("/graphics/shadows/quality", 2u64).unwrap();
+        nodes.insert_all("audio/volume", 0.5f64).unwrap();
+        nodes.insert_all("/graphics/quality", 3u64).unwrap();
+
+        assert_eq!(nodes.get("/graphics/shadows/quality"), Ok(2u64));
+        assert_eq!(nodes.get("/audio/volume"), Ok(0.5f64));
+        assert_eq!(nodes.get("/graphics/quality"), Ok(3u64));
+        assert_eq!(nodes.type_of("/graphics/shadows"), Ok(Some(ValueKind::Node)));
+
+        let error = nodes.insert_all("/graphics/name/x", 1u64).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::WrongType);
+        assert_eq!(error.component(), Some(Interned::from("name")));
+    }
+
+    #[test]
+    fn check_create_dirs_with() {
+        let nodes = config();
+        let mut created = 0;
+
+        nodes
+            .create_dirs_with("/graphics/a/b/c", || {
+                created += 1;
+
+                MapNode::new_node()
+            })
+            .unwrap();
+        assert_eq!(created, 3);
+
+        // Existing nodes are kept
+        nodes.insert("/graphics/a/b/c/d", 1u64).unwrap();
+        nodes.create_dirs("/graphics/a/b/c").unwrap();
+        assert_eq!(nodes.get("/graphics/a/b/c/d"), Ok(1u64));
+    }
+
+    /// Stores a single value for any name, without `set_if_absent`.
+    struct Slot(parking_lot::Mutex<Option<u64>>);
+
+    impl Node for Slot {
+        fn get(&self, _: &Vnodes, _: Interned) -> Result<Value<'_>> {
+            self.0.lock().map(Value::Unsigned).ok_or_else(|| ErrorKind::NoSuchEntry.into())
+        }
+
+        fn set(&self, _: &Vnodes, _: Interned, value: Value<'static>) -> Result<()> {
+            *self.0.lock() = Some(u64::from_value(value)?);
+
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn check_insert_if_absent() {
+        let nodes = config();
+
+        assert_eq!(nodes.insert_if_absent("/graphics/quality", 5u64), Ok(false));
+        assert_eq!(nodes.get("/graphics/quality"), Ok(2u64));
+        assert_eq!(nodes.insert_if_absent("/graphics/vsync", true), Ok(true));
+        assert_eq!(nodes.get("/graphics/vsync"), Ok(true));
+
+        nodes
+            .insert("/slot", NodeHandle::new(Slot(parking_lot::Mutex::new(None))))
+            .unwrap();
+        assert_eq!(nodes.insert_if_absent("/slot/x", 1u64), Ok(true));
+        assert_eq!(nodes.insert_if_absent("/slot/x", 2u64), Ok(false));
+        assert_eq!(nodes.get("/slot/x"), Ok(1u64));
+    }
+
+    #[test]
+    fn check_exists_and_type_of() {
+        let nodes = config();
+
+        assert!(nodes.exists("/graphics"));
+        assert!(nodes.exists("/graphics/quality"));
+        assert!(!nodes.exists("/graphics/shadows"));
+        assert!(!nodes.exists("/graphics/quality/x"));
+
+        assert_eq!(nodes.type_of("/graphics"), Ok(Some(ValueKind::Node)));
+        assert_eq!(nodes.type_of("/graphics/quality"), Ok(Some(ValueKind::Unsigned)));
+        assert_eq!(nodes.type_of("/graphics/name"), Ok(Some(ValueKind::String)));
+        assert_eq!(nodes.type_of("/graphics/shadows"), Ok(None));
+    }
+}
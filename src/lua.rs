@@ -0,0 +1,678 @@
+//! Nodes scripted in Lua.
+
+use std::error::Error as StdError;
+use std::fmt::{Display, Formatter, Result as FmtResult};
+use std::str::from_utf8;
+use std::sync::Arc;
+
+use mlua::{self, FromLua, Function, IntoLua, Lua, MultiValue, Scope, Table, UserData, Variadic};
+use parking_lot::ReentrantMutex;
+
+use intern::is_internable;
+use {
+    Error, ErrorKind, Interned, InternedPathBuf, Node, NodeHandle, NodeMeta, Result, Value,
+    ValueMap, Vnodes,
+};
+
+type LuaValue = mlua::Value;
+
+/// The maximum nesting of tables converted to values, which also stops
+/// at tables containing themselves.
+const MAX_DEPTH: usize = 64;
+
+/// A node running a Lua chunk.
+///
+/// The functions defined by the chunk become children which can be called;
+/// its other globals can be read and written like map entries. Names are
+/// interned, so they have to be lowercase to be found.
+///
+/// While Lua code runs, the `vnodes` global gives access to the calling
+/// context with `get(path)`, `set(path, value)`, `list(path)`,
+/// `call(path, ...)`, `remove(path)` and `exists(path)`.
+pub struct LuaNode {
+    script: Arc<Script>,
+}
+
+impl LuaNode {
+    /// Runs `chunk`; `context` is available as `vnodes` while it runs.
+    pub fn new(context: &Vnodes, chunk: &str) -> Result<Self> {
+        let lua = Lua::new();
+        let env = new_env(&lua).map_err(from_lua_err)?;
+        let script = Script {
+            lua,
+            env,
+            lock: ReentrantMutex::new(()),
+        };
+
+        script.enter(context, || {
+            script
+                .lua
+                .load(chunk)
+                .set_environment(script.env.clone())
+                .exec()
+        })?;
+
+        Ok(LuaNode {
+            script: Arc::new(script),
+        })
+    }
+
+    pub fn new_node(context: &Vnodes, chunk: &str) -> Result<NodeHandle> {
+        LuaNode::new(context, chunk).map(NodeHandle::new)
+    }
+}
+
+impl Node for LuaNode {
+    fn get(&self, _: &Vnodes, ident: Interned) -> Result<Value<'_>> {
+        let value: LuaValue = self
+            .script
+            .env
+            .raw_get(ident.to_string())
+            .map_err(from_lua_err)?;
+
+        match value {
+            LuaValue::Nil => Err(ErrorKind::NoSuchEntry.into()),
+            LuaValue::Function(function) => Ok(Value::Node(NodeHandle::new(LuaFunction {
+                script: self.script.clone(),
+                function,
+            }))),
+            value => Value::from_lua(value, &self.script.lua).map_err(from_lua_err),
+        }
+    }
+
+    fn list(&self, _: &Vnodes) -> Result<Vec<Interned>> {
+        let mut names = Vec::new();
+        for pair in self.script.env.pairs::<LuaValue, LuaValue>() {
+            // Names which can't be interned aren't reachable, so they're skipped
+            if let (LuaValue::String(name), _) = pair.map_err(from_lua_err)? {
+                match name.to_str() {
+                    Ok(ref name) if is_internable(name) => names.push(Interned::from(&**name)),
+                    _ => {}
+                }
+            }
+        }
+
+        Ok(names)
+    }
+
+    fn remove(&self, context: &Vnodes, ident: Interned) -> Result<Value<'static>> {
+        let value = self.get(context, ident)?.make_owned();
+        self.script
+            .env
+            .raw_set(ident.to_string(), LuaValue::Nil)
+            .map_err(from_lua_err)?;
+
+        Ok(value)
+    }
+
+    fn set(&self, _: &Vnodes, ident: Interned, value: Value<'static>) -> Result<()> {
+        value
+            .into_lua(&self.script.lua)
+            .and_then(|value| self.script.env.raw_set(ident.to_string(), value))
+            .map_err(from_lua_err)
+    }
+
+    fn meta(&self, _: &Vnodes) -> NodeMeta {
+        NodeMeta {
+            list: true,
+            remove: true,
+            set: true,
+            ..NodeMeta::new::<Self>()
+        }
+    }
+}
+
+/// A function defined by a `LuaNode`, called with the arguments of the call.
+struct LuaFunction {
+    script: Arc<Script>,
+    function: Function,
+}
+
+impl Node for LuaFunction {
+    fn call(&self, context: &Vnodes, args: &[Value]) -> Result<Value<'_>> {
+        self.script.enter(context, || {
+            let args = args
+                .iter()
+                .map(|arg| arg.clone().into_lua(&self.script.lua))
+                .collect::<mlua::Result<MultiValue>>()?;
+
+            self.function.call(args)
+        })
+    }
+
+    fn get(&self, _: &Vnodes, _: Interned) -> Result<Value<'_>> {
+        Err(ErrorKind::NoSuchEntry.into())
+    }
+
+    fn meta(&self, _: &Vnodes) -> NodeMeta {
+        NodeMeta {
+            call: true,
+            ..NodeMeta::new::<Self>()
+        }
+    }
+}
+
+struct Script {
+    lua: Lua,
+    // The globals of the chunk, falling back to the standard library
+    env: Table,
+    // Held while the `vnodes` global is set, so calls from other threads
+    // can't see the context of another call; calls may still be nested
+    lock: ReentrantMutex<()>,
+}
+
+impl Script {
+    /// Runs `f` with the `vnodes` global referring to `context`.
+    fn enter<F, R>(&self, context: &Vnodes, f: F) -> Result<R>
+    where
+        F: FnOnce() -> mlua::Result<R>,
+    {
+        let _guard = self.lock.lock();
+
+        self.lua
+            .scope(|scope| {
+                let api = new_api(&self.lua, scope, context)?;
+                // Restored afterwards, since the calls may be nested
+                let previous: LuaValue = self.env.raw_get("vnodes")?;
+                self.env.raw_set("vnodes", api)?;
+                let res = f();
+                self.env.raw_set("vnodes", previous)?;
+
+                res
+            })
+            .map_err(from_lua_err)
+    }
+}
+
+fn new_env(lua: &Lua) -> mlua::Result<Table> {
+    let env = lua.create_table()?;
+    let meta = lua.create_table()?;
+    meta.set("__index", lua.globals())?;
+    env.set_metatable(Some(meta));
+
+    Ok(env)
+}
+
+fn new_api<'scope, 'env>(
+    lua: &Lua,
+    scope: &'scope Scope<'scope, 'env>,
+    context: &'env Vnodes,
+) -> mlua::Result<Table> {
+    let api = lua.create_table()?;
+
+    api.set(
+        "get",
+        scope.create_function(move |_, path: mlua::String| {
+            context
+                .get::<_, Value>(lua_path(&path)?)
+                .map_err(to_lua_err)
+        })?,
+    )?;
+    api.set(
+        "set",
+        scope.create_function(move |_, (path, value): (mlua::String, Value<'static>)| {
+            context.insert(lua_path(&path)?, value).map_err(to_lua_err)
+        })?,
+    )?;
+    api.set(
+        "list",
+        scope.create_function(move |_, path: mlua::String| {
+            let names = context.list(lua_path(&path)?).map_err(to_lua_err)?;
+
+            Ok(names.iter().map(ToString::to_string).collect::<Vec<_>>())
+        })?,
+    )?;
+    api.set(
+        "call",
+        scope.create_function(
+            move |_, (path, args): (mlua::String, Variadic<Value<'static>>)| {
+                let args = match args.len() {
+                    0 => Value::Void,
+                    _ => Value::ValueArray(args.into_iter().collect()),
+                };
+
+                context
+                    .call::<_, _, Value>(lua_path(&path)?, args)
+                    .map_err(to_lua_err)
+            },
+        )?,
+    )?;
+    api.set(
+        "remove",
+        scope.create_function(move |_, path: mlua::String| {
+            context
+                .remove::<_, Value>(lua_path(&path)?)
+                .map_err(to_lua_err)
+        })?,
+    )?;
+    api.set(
+        "exists",
+        scope.create_function(move |_, path: mlua::String| Ok(context.exists(lua_path(&path)?)))?,
+    )?;
+
+    Ok(api)
+}
+
+/// Parses a path given by Lua code, which must not be able to panic.
+fn lua_path(path: &mlua::String) -> mlua::Result<InternedPathBuf> {
+    let path = path.to_str()?;
+
+    match path.split('/').all(is_internable) {
+        true => Ok(InternedPathBuf::from(&*path)),
+        false => Err(mlua::Error::runtime(format!("invalid path `{}`", &*path))),
+    }
+}
+
+/// Lets errors of the context pass through Lua code unchanged.
+#[derive(Debug)]
+struct NodeError(Error);
+
+impl Display for NodeError {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl StdError for NodeError {}
+
+fn to_lua_err(error: Error) -> mlua::Error {
+    mlua::Error::external(NodeError(error))
+}
+
+fn from_lua_err(error: mlua::Error) -> Error {
+    fn node_error(error: &mlua::Error) -> Option<&Error> {
+        match *error {
+            mlua::Error::CallbackError { ref cause, .. } => node_error(cause),
+            mlua::Error::ExternalError(ref error) => error.downcast_ref().map(|e: &NodeError| &e.0),
+            _ => None,
+        }
+    }
+
+    match node_error(&error) {
+        Some(e) => e.clone(),
+        None => {
+            let kind = match error {
+                mlua::Error::FromLuaConversionError { .. } => ErrorKind::WrongType,
+                mlua::Error::SyntaxError { .. } => ErrorKind::InvalidSyntax,
+                _ => ErrorKind::Panicked,
+            };
+
+            Error::new(kind).with_message(error.to_string())
+        }
+    }
+}
+
+/// Nodes other than Lua functions are passed to Lua as opaque handles.
+struct LuaHandle(NodeHandle);
+
+impl UserData for LuaHandle {}
+
+/// Maps and value arrays become tables, strings and blobs become strings,
+/// interned strings and paths become strings and nodes become userdata.
+impl<'a> IntoLua for Value<'a> {
+    fn into_lua(self, lua: &Lua) -> mlua::Result<LuaValue> {
+        Ok(match self.make_owned() {
+            Value::Blob(bytes) | Value::String(bytes) => {
+                LuaValue::String(lua.create_string(&bytes)?)
+            }
+            Value::SharedBlob(bytes) | Value::SharedString(bytes) => {
+                LuaValue::String(lua.create_string(bytes.as_slice())?)
+            }
+            Value::Bool(b) => LuaValue::Boolean(b),
+            Value::Float(f) => LuaValue::Number(f),
+            Value::Signed(i) => LuaValue::Integer(i),
+            Value::Unsigned(u) if u <= i64::MAX as u64 => LuaValue::Integer(u as i64),
+            Value::Unsigned(u) => LuaValue::Number(u as f64),
+            Value::Interned(interned) => LuaValue::String(lua.create_string(interned.to_string())?),
+            Value::InternedPathBuf(path) => LuaValue::String(lua.create_string(path.to_string())?),
+            Value::Map(map) => LuaValue::Table(
+                lua.create_table_from(map.into_iter().map(|(k, v)| (k.to_string(), v)))?,
+            ),
+            Value::ValueArray(array) => LuaValue::Table(lua.create_sequence_from(array)?),
+            Value::Node(node) => LuaValue::UserData(lua.create_userdata(LuaHandle(node))?),
+            Value::Void => LuaValue::Nil,
+            value => {
+                return Err(mlua::Error::ToLuaConversionError {
+                    from: value.kind().to_string(),
+                    to: "value",
+                    message: None,
+                })
+            }
+        })
+    }
+}
+
+/// Integers become `Signed` if they're negative and `Unsigned` otherwise.
+/// Tables which are sequences become value arrays, other tables become maps
+/// and need keys which can be interned.
+impl FromLua for Value<'static> {
+    fn from_lua(value: LuaValue, _: &Lua) -> mlua::Result<Self> {
+        value_from_lua(value, 0)
+    }
+}
+
+fn value_from_lua(value: LuaValue, depth: usize) -> mlua::Result<Value<'static>> {
+    Ok(match value {
+        LuaValue::Nil => Value::Void,
+        LuaValue::Boolean(b) => Value::Bool(b),
+        LuaValue::Integer(i) if i < 0 => Value::Signed(i),
+        LuaValue::Integer(i) => Value::Unsigned(i as u64),
+        LuaValue::Number(f) => Value::Float(f),
+        LuaValue::String(s) => {
+            let bytes: Box<[u8]> = (*s.as_bytes()).into();
+
+            match from_utf8(&bytes).is_ok() {
+                true => Value::String(bytes),
+                false => Value::Blob(bytes),
+            }
+        }
+        LuaValue::Table(_) if depth == MAX_DEPTH => {
+            return Err(mlua::Error::FromLuaConversionError {
+                from: "table",
+                to: "Value".to_owned(),
+                message: Some("table nested too deeply or containing itself".to_owned()),
+            })
+        }
+        LuaValue::Table(table) => {
+            let len = table.raw_len();
+            match table.pairs::<LuaValue, LuaValue>().count() == len {
+                true => Value::ValueArray(
+                    table
+                        .sequence_values::<LuaValue>()
+                        .map(|value| value_from_lua(value?, depth + 1))
+                        .collect::<mlua::Result<_>>()?,
+                ),
+                false => Value::Map(
+                    table
+                        .pairs::<mlua::String, LuaValue>()
+                        .map(|pair| {
+                            let (key, value) = pair?;
+                            let key = key.to_str()?;
+                            match is_internable(&key) {
+                                true => {
+                                    Ok((Interned::from(&*key), value_from_lua(value, depth + 1)?))
+                                }
+                                false => Err(mlua::Error::runtime(format!(
+                                    "`{}` can't be interned",
+                                    &*key
+                                ))),
+                            }
+                        })
+                        .collect::<mlua::Result<ValueMap>>()?,
+                ),
+            }
+        }
+        LuaValue::UserData(data) => Value::Node(data.borrow::<LuaHandle>()?.0.clone()),
+        value => {
+            return Err(mlua::Error::FromLuaConversionError {
+                from: value.type_name(),
+                to: "Value".to_owned(),
+                message: None,
+            })
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use error::error_kind;
+    use std::thread;
+    use {InternedMap, MapNode, ValueConv};
+
+    fn load(nodes: &Vnodes, chunk: &str) {
+        nodes
+            .insert("/script", LuaNode::new_node(nodes, chunk).unwrap())
+            .unwrap();
+    }
+
+    #[test]
+    fn check_functions() {
+        let nodes = Vnodes::new();
+        load(
+            &nodes,
+            r#"
+            speed = 5
+
+            function add(a, b)
+                return a + b
+            end
+
+            function greet(name)
+                return "Hello, " .. (name or "world") .. "!"
+            end
+            "#,
+        );
+
+        assert_eq!(nodes.call("/script/add", (1u64, 2u64)), Ok(3u64));
+        assert_eq!(nodes.call("/script/add", (0.5f64, 1u64)), Ok(1.5f64));
+
+        let meta = nodes.meta("/script/add").unwrap();
+        assert!(meta.call);
+        assert!(!meta.list && !meta.set && !meta.remove);
+        let meta = nodes.meta("/script").unwrap();
+        assert!(meta.list && meta.set && meta.remove);
+        assert!(!meta.call);
+        assert_eq!(
+            nodes.call::<_, _, String>("/script/greet", ()).unwrap(),
+            "Hello, world!"
+        );
+        assert_eq!(
+            nodes.call::<_, _, String>("/script/greet", "lua").unwrap(),
+            "Hello, lua!"
+        );
+
+        assert_eq!(nodes.get("/script/speed"), Ok(5u64));
+        nodes.insert("/script/speed", -1i64).unwrap();
+        assert_eq!(nodes.get("/script/speed"), Ok(-1i64));
+
+        let mut names = nodes.list("/script").unwrap();
+        names.sort_by_key(|i| i.into_inner());
+        let mut expected = vec![
+            Interned::from("add"),
+            Interned::from("greet"),
+            Interned::from("speed"),
+        ];
+        expected.sort_by_key(|i| i.into_inner());
+        assert_eq!(names, expected);
+
+        assert_eq!(nodes.remove("/script/speed"), Ok(-1i64));
+        assert!(!nodes.exists("/script/speed"));
+        // Globals of the standard library aren't children
+        assert!(!nodes.exists("/script/print"));
+    }
+
+    #[test]
+    fn check_context() {
+        let nodes = Vnodes::new();
+        nodes.insert("/world", MapNode::new_node()).unwrap();
+        load(
+            &nodes,
+            r#"
+            vnodes.set("/world/loaded", true)
+
+            function spawn(kind, count)
+                vnodes.set("/world/" .. kind, count)
+                return vnodes.call("/script/total")
+            end
+
+            function total()
+                local sum = 0
+                for _, name in ipairs(vnodes.list("/world")) do
+                    local value = vnodes.get("/world/" .. name)
+                    if math.type(value) == "integer" then
+                        sum = sum + value
+                    end
+                end
+                return sum
+            end
+            "#,
+        );
+
+        assert_eq!(nodes.get("/world/loaded"), Ok(true));
+        assert_eq!(nodes.call("/script/spawn", ("orc", 3u64)), Ok(3u64));
+        assert_eq!(nodes.call("/script/spawn", ("elf", 2u64)), Ok(5u64));
+        assert_eq!(nodes.get("/world/orc"), Ok(3u64));
+        // `vnodes` is only available while Lua code runs
+        assert!(!nodes.exists("/script/vnodes"));
+    }
+
+    #[test]
+    fn check_tables() {
+        let nodes = Vnodes::new();
+        load(
+            &nodes,
+            r#"
+            function echo(value)
+                return value
+            end
+
+            function config()
+                return { width = 800, title = "demo", tags = { "a", "b" } }
+            end
+            "#,
+        );
+
+        let mut config: InternedMap<Value> = nodes.call("/script/config", ()).unwrap();
+        let width = config.remove(Interned::from("width")).unwrap();
+        assert_eq!(u64::from_value(width), Ok(800));
+        let tags = config.remove(Interned::from("tags")).unwrap();
+        assert_eq!(Vec::<String>::from_value(tags).unwrap(), vec!["a", "b"]);
+
+        // A single argument, so the array isn't spread into several arguments
+        let echo = |value: Value<'static>| {
+            let args = Value::ValueArray(vec![value].into_iter().collect());
+
+            nodes.call::<_, _, Value>("/script/echo", args).unwrap()
+        };
+
+        let array = vec![1u64, 2, 3];
+        assert_eq!(Vec::from_value(echo(array.clone().into_value())), Ok(array));
+
+        let mut map = InternedMap::new();
+        map.insert(Interned::from("x"), 1.5f64);
+        assert_eq!(
+            InternedMap::from_value(echo(map.clone().into_value())),
+            Ok(map)
+        );
+
+        let node = MapNode::new_node();
+        let echoed = NodeHandle::from_value(echo(node.clone().into_value())).unwrap();
+        assert_eq!(echoed.as_raw(), node.as_raw());
+    }
+
+    #[test]
+    fn check_cyclic_tables() {
+        let nodes = Vnodes::new();
+        load(
+            &nodes,
+            r#"
+            function cycle()
+                local t = {}
+                t.t = t
+                return t
+            end
+
+            function deep()
+                local t = {}
+                for i = 1, 100 do
+                    t = { t }
+                end
+                return t
+            end
+            "#,
+        );
+
+        assert_eq!(
+            error_kind(nodes.call::<_, _, Value>("/script/cycle", ())),
+            Some(ErrorKind::WrongType)
+        );
+        assert_eq!(
+            error_kind(nodes.call::<_, _, Value>("/script/deep", ())),
+            Some(ErrorKind::WrongType)
+        );
+    }
+
+    #[test]
+    fn check_threads() {
+        let node = LuaNode::new_node(
+            &Vnodes::new(),
+            r#"
+            function id()
+                local id = vnodes.get("/id")
+                for i = 1, 100 do
+                    if vnodes.get("/id") ~= id then
+                        error("saw another context")
+                    end
+                end
+                return id
+            end
+            "#,
+        )
+        .unwrap();
+
+        let threads: Vec<_> = (0..2u64)
+            .map(|id| {
+                let node = node.clone();
+                thread::spawn(move || {
+                    let nodes = Vnodes::new();
+                    nodes.insert("/id", id).unwrap();
+                    nodes.insert("/script", node).unwrap();
+
+                    for _ in 0..100 {
+                        assert_eq!(nodes.call("/script/id", ()), Ok(id));
+                    }
+                })
+            })
+            .collect();
+
+        for thread in threads {
+            thread.join().unwrap();
+        }
+    }
+
+    #[test]
+    fn check_errors() {
+        let nodes = Vnodes::new();
+        load(
+            &nodes,
+            r#"
+            function fail()
+                error("boom")
+            end
+
+            function missing()
+                return vnodes.get("/missing")
+            end
+
+            function catch()
+                return pcall(vnodes.get, "/invalid path")
+            end
+
+            function closure()
+                return function() end
+            end
+            "#,
+        );
+
+        let error = nodes.call::<_, _, Value>("/script/fail", ()).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::Panicked);
+        assert!(error.message().unwrap().contains("boom"));
+
+        assert_eq!(
+            error_kind(nodes.call::<_, _, Value>("/script/missing", ())),
+            Some(ErrorKind::NoSuchEntry)
+        );
+        assert_eq!(nodes.call("/script/catch", ()), Ok(false));
+        assert_eq!(
+            error_kind(nodes.call::<_, _, Value>("/script/closure", ())),
+            Some(ErrorKind::WrongType)
+        );
+
+        let error = LuaNode::new(&nodes, "this is not lua").err().unwrap();
+        assert_eq!(error.kind(), ErrorKind::InvalidSyntax);
+
+        let error = LuaNode::new(&nodes, "error('boom')").err().unwrap();
+        assert_eq!(error.kind(), ErrorKind::Panicked);
+    }
+}
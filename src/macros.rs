@@ -1 +1,43 @@
+/// Declares the entry point of a plugin, which can then be loaded with `Vnodes::load_plugin`.
+///
+/// `$init` is a `fn(&Vnodes) -> Result<NodeHandle>` creating the root node of the plugin;
+/// the crate has to be built as `cdylib`.
+///
+/// ```ignore
+/// fn init(_: &Vnodes) -> Result<NodeHandle> {
+///     Ok(MapNode::new_node())
+/// }
+///
+/// export_plugin!(init);
+/// ```
+#[macro_export]
+macro_rules! export_plugin {
+    ($init:path) => {
+        #[no_mangle]
+        pub static VNODES_PLUGIN: $crate::raw::PluginDeclaration = {
+            unsafe extern "C" fn __vnodes_plugin_init(
+                context: $crate::raw::RawContextPtr,
+            ) -> $crate::raw::RawResult {
+                $crate::raw::plugin_init(context, $init)
+            }
 
+            $crate::raw::PluginDeclaration {
+                abi_version: $crate::raw::ABI_VERSION,
+                init: __vnodes_plugin_init,
+            }
+        };
+    };
+}
+
+/// Fails to compile unless `$ty` has the given size, alignment and field offsets.
+macro_rules! assert_layout {
+    ($ty:ty, size = $size:expr, align = $align:expr $(, $field:ident = $offset:expr)*) => {
+        const _: () = {
+            assert!(::std::mem::size_of::<$ty>() == $size, "unexpected size");
+            assert!(::std::mem::align_of::<$ty>() == $align, "unexpected alignment");
+            $(
+                assert!(::std::mem::offset_of!($ty, $field) == $offset, "unexpected offset");
+            )*
+        };
+    };
+}
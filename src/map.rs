@@ -1,10 +1,12 @@
+use std::iter::{Map, Zip};
 use std::mem::swap;
+use std::vec::IntoIter;
 
 use parking_lot::RwLock;
 
-use {Error, Interned, NodeHandle, NodeMut, Result, Value, Vnodes};
+use {ErrorKind, Interned, NodeHandle, NodeMeta, NodeMut, Result, Value, Vnodes};
 
-#[derive(Derivative)]
+#[derive(Clone, Debug, Derivative, PartialEq)]
 #[derivative(Default(bound = ""))]
 pub struct InternedMap<T> {
     keys: Vec<u64>,
@@ -16,6 +18,21 @@ impl<T> InternedMap<T> {
         Default::default()
     }
 
+    pub fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    /// Iterates over all entries, sorted by key.
+    pub fn iter<'a>(&'a self) -> impl Iterator<Item = (Interned, &'a T)> + 'a {
+        let keys = self.keys.iter().map(|&key| Interned(key));
+
+        keys.zip(self.values.iter())
+    }
+
     #[inline]
     pub fn get(&self, key: Interned) -> Option<&T> {
         search(key.0, &self.keys).map(|i| &self.values[i])
@@ -27,6 +44,14 @@ impl<T> InternedMap<T> {
         search(key.0, &self.keys).map(move |i| &mut values[i])
     }
 
+    pub fn remove(&mut self, key: Interned) -> Option<T> {
+        search(key.0, &self.keys).map(|i| {
+            self.keys.remove(i);
+
+            self.values.remove(i)
+        })
+    }
+
     pub fn insert(&mut self, key: Interned, mut value: T) -> Option<T> {
         let key = key.0;
 
@@ -61,6 +86,17 @@ impl<T> InternedMap<T> {
     }
 }
 
+impl<T> IntoIterator for InternedMap<T> {
+    type Item = (Interned, T);
+    type IntoIter = Zip<Map<IntoIter<u64>, fn(u64) -> Interned>, IntoIter<T>>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        let keys = self.keys.into_iter().map(Interned as fn(u64) -> Interned);
+
+        keys.zip(self.values)
+    }
+}
+
 #[derive(Default)]
 pub struct MapNode {
     map: InternedMap<Value<'static>>,
@@ -73,12 +109,16 @@ impl MapNode {
 }
 
 impl NodeMut for MapNode {
-    fn call(&self, _: &Vnodes, _: &[Value]) -> Result<Value<'static>> {
-        unimplemented!()
+    fn get(&self, _: &Vnodes, ident: Interned) -> Result<Value<'static>> {
+        self.map.get(ident).cloned().ok_or_else(|| ErrorKind::NoSuchEntry.into())
     }
 
-    fn get(&self, _: &Vnodes, ident: Interned) -> Result<Value<'static>> {
-        self.map.get(ident).cloned().ok_or(Error::NoSuchEntry)
+    fn list(&self, _: &Vnodes) -> Result<Vec<Interned>> {
+        Ok(self.map.iter().map(|(key, _)| key).collect())
+    }
+
+    fn remove(&mut self, _: &Vnodes, ident: Interned) -> Result<Value<'static>> {
+        self.map.remove(ident).ok_or_else(|| ErrorKind::NoSuchEntry.into())
     }
 
     fn set(&mut self, _: &Vnodes, ident: Interned, value: Value<'static>) -> Result<()> {
@@ -86,6 +126,33 @@ impl NodeMut for MapNode {
 
         Ok(())
     }
+
+    fn set_if_absent(
+        &mut self,
+        _: &Vnodes,
+        ident: Interned,
+        value: Value<'static>,
+    ) -> Result<bool> {
+        match self.map.get(ident) {
+            Some(_) => Ok(false),
+            None => {
+                self.map.insert(ident, value);
+
+                Ok(true)
+            }
+        }
+    }
+
+    fn meta(&self, _: &Vnodes) -> NodeMeta {
+        NodeMeta {
+            list: true,
+            remove: true,
+            set: true,
+            children: Some(self.map.len() as u64),
+            doc: Some("Stores arbitrary values by identifier.".to_owned()),
+            ..NodeMeta::new::<Self>()
+        }
+    }
 }
 
 unsafe fn search_n(key: u64, elements: &[u64]) -> usize {
@@ -160,8 +227,8 @@ fn search(key: u64, elements: &[u64]) -> Option<usize> {
         }
     };
 
-    match guess {
-        guess if elements[guess] == key => Some(guess),
+    match elements.get(guess) {
+        Some(&element) if element == key => Some(guess),
         _ => None,
     }
 }
@@ -195,4 +262,17 @@ mod tests {
             assert_eq!(map.get(key), Some(&value));
         }
     }
+
+    #[test]
+    fn check_remove() {
+        let mut map = InternedMap::new();
+        for (i, &key) in ["a", "b", "c", "d", "e", "f"].iter().enumerate() {
+            map.insert(Interned::from(key), i);
+        }
+
+        assert_eq!(map.remove(Interned::from("c")), Some(2));
+        assert_eq!(map.remove(Interned::from("c")), None);
+        assert_eq!(map.get(Interned::from("d")), Some(&3));
+        assert_eq!(map.len(), 5);
+    }
 }
@@ -1,116 +1,541 @@
+use std::any::TypeId;
+use std::mem::{size_of, ManuallyDrop};
+use std::panic::{catch_unwind, AssertUnwindSafe};
 use std::process::abort;
-use std::sync::atomic::{AtomicUsize, Ordering};
+use std::slice;
+use std::ptr::{self, drop_in_place};
+use std::sync::atomic::{fence, AtomicUsize, Ordering};
+use std::sync::PoisonError;
+
+use fxhash::FxHashMap;
 
 use super::*;
 
+/// The data of a node; freed once there are no strong and no weak handles left.
+///
+/// The node itself is dropped as soon as the last strong handle is gone.
 #[repr(C)]
 pub struct NodeData<T> {
     pub _raw: RawNodeData,
-    pub node: T,
+    pub node: ManuallyDrop<T>,
     strong: AtomicUsize,
-    //weak: AtomicUsize,
+    /// The number of weak handles, plus one for all strong handles together.
+    weak: AtomicUsize,
 }
 
 impl<T> NodeData<T>
 where
     T: Node + 'static,
 {
+    const VTABLE: RawNodeVtable = RawNodeVtable {
+        get: raw_get::<T>,
+        set: raw_set::<T>,
+        call: raw_call::<T>,
+        list: raw_list::<T>,
+        remove: raw_remove::<T>,
+        clone: raw_clone::<T>,
+        drop: raw_drop::<T>,
+        action: raw_action::<T>,
+    };
+
     pub fn new(node: T) -> Box<Self> {
         let data = NodeData {
             _raw: RawNodeData {
-                action: raw_action_node_data::<T>,
+                vtable: vtable::<T>(),
             },
-            node,
+            node: ManuallyDrop::new(node),
             strong: AtomicUsize::new(1),
-            //weak: AtomicUsize::new(0),
+            weak: AtomicUsize::new(1),
         };
 
         Box::new(data)
     }
 }
 
-unsafe extern "C" fn raw_action_node_data<T>(
-    node_data: *mut RawNodeData,
-    context: *mut Vnodes,
-    action: Action,
-    arg: RawValue,
-) -> RawValue
+/// The vtables of `NodeData`, allocated once per node type.
+static VTABLES: ::std::sync::RwLock<Option<FxHashMap<TypeId, &'static RawNodeVtable>>> =
+    ::std::sync::RwLock::new(None);
+
+/// Returns the vtable of `NodeData<T>`, whose address identifies `T`.
+///
+/// Addresses of functions and constants aren't guaranteed to be unique, so
+/// the vtable is leaked once per type instead.
+fn vtable<T>() -> &'static RawNodeVtable
 where
     T: Node + 'static,
 {
-    let arg = Value::from_raw(arg);
+    let id = TypeId::of::<T>();
+    let vtables = VTABLES.read().unwrap_or_else(PoisonError::into_inner);
+    if let Some(&vtable) = vtables.as_ref().and_then(|vtables| vtables.get(&id)) {
+        return vtable;
+    }
+    drop(vtables);
 
-    trace!(
-        "action function called with args {{ node: {:x}, context: {:x}, action: {:?}, arg: {:?} }}",
-        node_data as usize,
-        context as usize,
-        action,
-        arg,
-    );
+    let mut vtables = VTABLES.write().unwrap_or_else(PoisonError::into_inner);
+    vtables
+        .get_or_insert_with(FxHashMap::default)
+        .entry(id)
+        .or_insert_with(|| Box::leak(Box::new(NodeData::<T>::VTABLE)))
+}
+
+/// Checks if `node_data` was created by `NodeData::<T>::new`.
+///
+/// Unlike `Action::TypeId`, this can't be faked by foreign nodes: it compares
+/// the vtable, which is allocated once per `T`. Nodes of the same type created
+/// by another copy of this crate, e.g. in a plugin, only make the check fail.
+///
+/// # Safety
+///
+/// `node_data` must be a valid node.
+pub unsafe fn is_node_data<T>(node_data: *mut RawNodeData) -> bool
+where
+    T: Node + 'static,
+{
+    ptr::eq((*node_data).vtable, vtable::<T>())
+}
+
+unsafe fn node<'a, T>(node_data: *mut RawNodeData) -> &'a T {
+    &(*(node_data as *const NodeData<T>)).node
+}
 
-    let this = node_data as *const NodeData<T>;
-    let this = &*this;
+/// Runs `f` for the vtable entry `entry`.
+///
+/// Unwinding out of an `extern "C"` function is undefined behavior,
+/// so panics are turned into `ErrorKind::Panicked`.
+fn guard<'a, F>(node_data: *mut RawNodeData, entry: &str, f: F) -> RawResult
+where
+    F: FnOnce() -> Result<Value<'a>>,
+{
+    trace!("{} called on node {:x}", entry, node_data as usize);
+
+    match catch_unwind(AssertUnwindSafe(f)) {
+        Ok(res) => res.into(),
+        Err(_) => {
+            error!("node {:x} panicked during {}", node_data as usize, entry);
 
-    Value::from_res(action_node_data(this, context, action, arg)).into()
+            Err(ErrorKind::Panicked.into()).into()
+        }
+    }
 }
 
-unsafe fn action_node_data<'a, T>(
-    this: &'a NodeData<T>,
+unsafe extern "C" fn raw_get<T>(
+    node_data: *mut RawNodeData,
+    context: RawContextPtr,
+    ident: Interned,
+) -> RawResult
+where
+    T: Node + 'static,
+{
+    guard(node_data, "get", || node::<T>(node_data).get(&*context, ident))
+}
+
+unsafe extern "C" fn raw_set<T>(
+    node_data: *mut RawNodeData,
+    context: RawContextPtr,
+    ident: Interned,
+    value: RawValue,
+) -> RawResult
+where
+    T: Node + 'static,
+{
+    guard(node_data, "set", || {
+        value.validate()?;
+        let value = Value::from_raw(value).make_owned();
+        node::<T>(node_data).set(&*context, ident, value)?;
+
+        Ok(Value::Void)
+    })
+}
+
+unsafe extern "C" fn raw_call<T>(
+    node_data: *mut RawNodeData,
     context: RawContextPtr,
-    action: Action,
-    arg: Value,
-) -> Result<Value<'a>>
+    args: RawValue,
+) -> RawResult
 where
     T: Node + 'static,
 {
-    match action {
-        Action::Call => unimplemented!(),
-        Action::Get => {
-            let context = &*context;
-            let ident: Interned = ValueConv::from_value(arg)?;
+    guard(node_data, "call", || {
+        args.validate()?;
+        with_args(Value::from_raw(args), |args| {
+            node::<T>(node_data)
+                .call(&*context, args)
+                .map(Value::make_owned)
+        })
+    })
+}
+
+/// Passes the values of an argument array to `f` as slice, without
+/// allocating for up to `INLINE_ARGS` arguments.
+fn with_args<'a, F, R>(args: Value<'a>, f: F) -> R
+where
+    F: FnOnce(&[Value<'a>]) -> R,
+{
+    const INLINE_ARGS: usize = 8;
+
+    fn fill<'a, I>(slots: &mut [Value<'a>], values: I) -> usize
+    where
+        I: Iterator<Item = Value<'a>>,
+    {
+        slots.iter_mut().zip(values).map(|(slot, value)| *slot = value).count()
+    }
 
-            this.node.get(context, ident)
+    let mut inline: [Value; INLINE_ARGS] = Default::default();
+
+    match args {
+        Value::Void => f(&[]),
+        Value::ValueArrayRef(array) if array.len() <= INLINE_ARGS => {
+            let len = fill(&mut inline, array.iter().map(|raw| unsafe { raw.as_value() }));
+
+            f(&inline[..len])
         }
-        Action::Set => {
-            let (ident, value): (Interned, Value) = ValueConv::from_value(arg)?;
-            let value = value.make_owned();
+        Value::ValueArrayRef(array) => {
+            let args: Vec<Value> = array.iter().map(|raw| unsafe { raw.as_value() }).collect();
 
-            this.node.set(&*context, ident, value)?;
+            f(&args)
+        }
+        Value::ValueArray(array) if array.len() <= INLINE_ARGS => {
+            let len = fill(&mut inline, array.into_iter());
 
-            Ok(Value::Void)
+            f(&inline[..len])
         }
-        Action::Clone => {
-            let old = this.strong.fetch_add(1, Ordering::Relaxed);
+        Value::ValueArray(array) => f(&array.into_iter().collect::<Vec<_>>()),
+        arg => f(slice::from_ref(&arg)),
+    }
+}
+
+unsafe extern "C" fn raw_list<T>(node_data: *mut RawNodeData, context: RawContextPtr) -> RawResult
+where
+    T: Node + 'static,
+{
+    guard(node_data, "list", || {
+        node::<T>(node_data)
+            .list(&*context)
+            .map(ValueConv::into_value)
+    })
+}
+
+unsafe extern "C" fn raw_remove<T>(
+    node_data: *mut RawNodeData,
+    context: RawContextPtr,
+    ident: Interned,
+) -> RawResult
+where
+    T: Node + 'static,
+{
+    guard(node_data, "remove", || node::<T>(node_data).remove(&*context, ident))
+}
+
+/// Handles the remaining actions, see `RawNodeVtable::action`.
+unsafe extern "C" fn raw_action<T>(
+    node_data: *mut RawNodeData,
+    context: RawContextPtr,
+    action: RawAction,
+    arg: RawValue,
+) -> RawResult
+where
+    T: Node + 'static,
+{
+    if let Err(e) = arg.validate() {
+        return Err(e).into();
+    }
+    let arg = Value::from_raw(arg);
+
+    trace!(
+        "action function called with args {{ node: {:x}, context: {:x}, action: {:#x}, arg: {:?} }}",
+        node_data as usize,
+        context as usize,
+        action,
+        arg,
+    );
+
+    let this = node_data as *mut NodeData<T>;
+
+    guard(node_data, "action", || match Action::from_raw(action) {
+        Some(Action::CloneWeak) => {
+            let old = (*this).weak.fetch_add(1, Ordering::Relaxed);
 
-            // Refcount is way too high, we got serious memory leaks.
             if old > (!0 >> 1) {
                 abort();
             }
 
             Ok(Value::Void)
         }
-        Action::Drop => {
-            let old = this.strong.fetch_sub(1, Ordering::Relaxed);
-
-            match old {
-                1 => {
-                    // Synchronize with other threads to make sure we have unique ownership
-                    assert_eq!(this.strong.load(Ordering::Acquire), 0);
-
-                    // Don't rely on type inference here
-                    let b: Box<NodeData<T>> =
-                        Box::from_raw(this as *const NodeData<T> as *mut NodeData<T>);
-                    drop(b);
+        Some(Action::DropWeak) => {
+            release_weak(this);
+
+            Ok(Value::Void)
+        }
+        Some(Action::Upgrade) => Ok(Value::Bool(upgrade(this))),
+        Some(Action::Meta) => Ok((*this).node.meta(&*context).into_value()),
+        Some(Action::SetIfAbsent) => match arg.make_owned() {
+            Value::Map(map) => {
+                let mut entries = map.into_iter();
+                match (entries.next(), entries.next()) {
+                    (Some((ident, value)), None) => (*this)
+                        .node
+                        .set_if_absent(&*context, ident, value)
+                        .map(Value::Bool),
+                    _ => Err(ErrorKind::InvalidArgumentTypes.into()),
                 }
-                _ => {}
             }
+            _ => Err(ErrorKind::InvalidArgumentTypes.into()),
+        },
+        Some(Action::TypeId) => match arg {
+            Value::BlobRef(bytes) if bytes.len() == size_of::<TypeId>() => {
+                let id = (bytes.as_ptr() as *const TypeId).read_unaligned();
 
-            Ok(Value::Void)
+                Ok(Value::Bool(id == TypeId::of::<T>()))
+            }
+            _ => Err(ErrorKind::InvalidArgumentTypes.into()),
+        },
+        Some(Action::Handshake) => Ok(Value::Unsigned(ABI_VERSION as u64)),
+        // The other actions have their own vtable entries
+        _ => Err(ErrorKind::ActionNotSupported.into()),
+    })
+}
+
+// The reference counting follows `std::sync::Arc`, including the orderings.
+
+unsafe extern "C" fn raw_clone<T>(node_data: *mut RawNodeData)
+where
+    T: Node + 'static,
+{
+    let this = node_data as *mut NodeData<T>;
+
+    // A new handle can only be created from an existing one,
+    // so there's nothing to synchronize with here.
+    let old = (*this).strong.fetch_add(1, Ordering::Relaxed);
+
+    // Refcount is way too high, we got serious memory leaks.
+    if old > (!0 >> 1) {
+        abort();
+    }
+}
+
+unsafe extern "C" fn raw_drop<T>(node_data: *mut RawNodeData)
+where
+    T: Node + 'static,
+{
+    let this = node_data as *mut NodeData<T>;
+
+    // Release: all uses of the node through this handle happen before the decrement
+    if (*this).strong.fetch_sub(1, Ordering::Release) != 1 {
+        return;
+    }
+
+    // Acquire: synchronize with the decrements of all other handles,
+    // so their uses of the node happen before it's dropped.
+    fence(Ordering::Acquire);
+
+    let res = catch_unwind(AssertUnwindSafe(|| drop_in_place(&mut *(*this).node)));
+    if res.is_err() {
+        error!("node {:x} panicked while being dropped", node_data as usize);
+    }
+
+    // Release the weak reference all strong handles shared
+    release_weak(this);
+}
+
+/// Tries to acquire a strong reference; fails if the node has been dropped already.
+unsafe fn upgrade<T>(this: *mut NodeData<T>) -> bool {
+    let strong = &(*this).strong;
+    let mut old = strong.load(Ordering::Relaxed);
+
+    loop {
+        if old == 0 {
+            return false;
+        }
+
+        if old > (!0 >> 1) {
+            abort();
+        }
+
+        // Acquire on success, pairing with the release in `raw_drop`
+        match strong.compare_exchange_weak(old, old + 1, Ordering::Acquire, Ordering::Relaxed) {
+            Ok(_) => return true,
+            Err(current) => old = current,
         }
-        Action::List => unimplemented!(),
     }
 }
 
-fn drop_box<T>(b: Box<NodeData<T>>) {
+unsafe fn release_weak<T>(this: *mut NodeData<T>) {
+    if (*this).weak.fetch_sub(1, Ordering::Release) != 1 {
+        return;
+    }
+
+    fence(Ordering::Acquire);
+
+    // The node has been dropped already, only the memory is left.
+    // Don't rely on type inference here
+    let b: Box<NodeData<T>> = Box::from_raw(this);
     drop(b);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use error::error_kind;
+    use {Error, MapNode, NodeHandle};
+
+    #[test]
+    fn check_vtables() {
+        let map = MapNode::new_node();
+        let adder = NodeHandle::new(Adder);
+
+        let first = vtable::<RwLock<MapNode>>() as *const _;
+        assert_eq!(first, vtable::<RwLock<MapNode>>() as *const _);
+        assert_ne!(vtable::<Adder>() as *const _, vtable::<Assets>() as *const _);
+        unsafe {
+            assert!(is_node_data::<Adder>(adder.as_raw()));
+            assert!(!is_node_data::<Assets>(adder.as_raw()));
+            assert!(!is_node_data::<Adder>(map.as_raw()));
+        }
+    }
+
+    struct Panicking;
+
+    impl Node for Panicking {
+        fn get(&self, _: &Vnodes, _: Interned) -> Result<Value<'_>> {
+            panic!("this node is broken")
+        }
+    }
+
+    /// Reports domain specific errors.
+    struct Assets;
+
+    impl Node for Assets {
+        fn call(&self, _: &Vnodes, _: &[Value]) -> Result<Value<'_>> {
+            Err(Error::custom(13, "permission denied"))
+        }
+
+        fn get(&self, _: &Vnodes, _: Interned) -> Result<Value<'_>> {
+            Err(ErrorKind::Custom(7).into())
+        }
+    }
+
+    struct Adder;
+
+    impl Node for Adder {
+        fn call(&self, _: &Vnodes, args: &[Value]) -> Result<Value<'_>> {
+            let sum = args.iter()
+                .map(|arg| u64::from_value(arg.clone()))
+                .sum::<Result<u64>>()?;
+
+            Ok(Value::Unsigned(sum))
+        }
+
+        fn get(&self, _: &Vnodes, _: Interned) -> Result<Value<'_>> {
+            Err(ErrorKind::NoSuchEntry.into())
+        }
+    }
+
+    #[test]
+    fn check_panic_is_caught() {
+        let nodes = Vnodes::new();
+        nodes.insert("/broken", NodeHandle::new(Panicking)).unwrap();
+
+        assert_eq!(error_kind(nodes.get::<_, u64>("/broken/x")), Some(ErrorKind::Panicked));
+        // The node is still usable afterwards
+        assert_eq!(error_kind(nodes.get::<_, u64>("/broken/y")), Some(ErrorKind::Panicked));
+    }
+
+    #[test]
+    fn check_not_supported() {
+        let nodes = Vnodes::new();
+        nodes.insert("/broken", NodeHandle::new(Panicking)).unwrap();
+        nodes.insert("/map", MapNode::new_node()).unwrap();
+
+        let unsupported = Some(ErrorKind::ActionNotSupported);
+        assert_eq!(error_kind(nodes.insert("/broken/x", 1u64)), unsupported);
+        assert_eq!(error_kind(nodes.list("/broken")), unsupported);
+        assert_eq!(error_kind(nodes.remove::<_, ()>("/broken/x")), unsupported);
+        assert_eq!(error_kind(nodes.call::<_, _, ()>("/map", ())), unsupported);
+    }
+
+    #[test]
+    fn check_custom_errors() {
+        let nodes = Vnodes::new();
+        nodes.insert("/assets", NodeHandle::new(Assets)).unwrap();
+
+        let error = nodes.get::<_, u64>("/assets/cube").unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::Custom(7));
+        assert_eq!(error.message(), None);
+        assert_eq!(error.component(), Some(Interned::from("cube")));
+
+        let error = nodes.call::<_, _, ()>("/assets", ()).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::Custom(13));
+        assert_eq!(error.message(), Some("permission denied"));
+    }
+
+    #[test]
+    fn check_call() {
+        let nodes = Vnodes::new();
+        nodes.insert("/add", NodeHandle::new(Adder)).unwrap();
+
+        assert_eq!(nodes.call("/add", (1u64, 2u64, 3u64)), Ok(6u64));
+        assert_eq!(nodes.call("/add", 5u64), Ok(5u64));
+        assert_eq!(nodes.call("/add", ()), Ok(0u64));
+        // More arguments than fit on the stack
+        assert_eq!(nodes.call("/add", vec![1u64; 20]), Ok(20u64));
+        assert_eq!(error_kind(nodes.call::<_, _, u64>("/add", true)), Some(ErrorKind::WrongType));
+    }
+
+    #[test]
+    fn check_list_remove() {
+        let nodes = Vnodes::new();
+        nodes.insert("/map", MapNode::new_node()).unwrap();
+        assert_eq!(nodes.list("/map"), Ok(vec![]));
+
+        nodes.insert("/map/a", 1u64).unwrap();
+        nodes.insert("/map/b", 2u64).unwrap();
+        assert_eq!(
+            nodes.list("/map"),
+            Ok(vec![Interned::from("a"), Interned::from("b")])
+        );
+
+        assert_eq!(nodes.remove("/map/a"), Ok(1u64));
+        assert_eq!(error_kind(nodes.remove::<_, u64>("/map/a")), Some(ErrorKind::NoSuchEntry));
+        assert_eq!(nodes.list("/map"), Ok(vec![Interned::from("b")]));
+    }
+
+    #[test]
+    fn check_handshake() {
+        let node = MapNode::new_node();
+        assert_eq!(node.handshake(), Ok(ABI_VERSION));
+    }
+
+    #[test]
+    fn check_unknown_raw_input() {
+        let nodes = Vnodes::new();
+        let node = MapNode::new_node();
+        let raw = node.handle_ref().raw();
+        let context = &nodes as *const Vnodes as RawContextPtr;
+
+        let vtable = unsafe { &*(*raw).vtable };
+
+        let res = unsafe { (vtable.action)(raw, context, 0xFF, Value::Void.into()).into_result() };
+        assert_eq!(error_kind(res), Some(ErrorKind::ActionNotSupported));
+        // Actions with their own entry aren't taken by the fallback
+        let arg = Value::Interned(Interned::from("x")).into();
+        let res = unsafe { (vtable.action)(raw, context, Action::Get.into_raw(), arg).into_result() };
+        assert_eq!(error_kind(res), Some(ErrorKind::ActionNotSupported));
+
+        let mut arg: RawValue = Value::Void.into();
+        arg.flags = Flags::_RESERVED;
+        let res = unsafe { (vtable.call)(raw, context, arg).into_result() };
+        assert_eq!(error_kind(res), Some(ErrorKind::UnknownTypeFlags));
+
+        // Nested values are checked as well
+        let mut nested: RawValue = Value::Unsigned(1).into();
+        nested.flags |= Flags::_RESERVED;
+        let values = [Value::Bool(true).into(), nested];
+        let arg = Value::ValueArrayRef(&values).into();
+        let ident = Interned::from("x");
+        let res = unsafe { (vtable.set)(raw, context, ident, arg).into_result() };
+        assert_eq!(error_kind(res), Some(ErrorKind::UnknownTypeFlags));
+        let arg = Value::ValueArrayRef(&values).into();
+        let res = unsafe { (vtable.call)(raw, context, arg).into_result() };
+        assert_eq!(error_kind(res), Some(ErrorKind::UnknownTypeFlags));
+    }
+}
@@ -0,0 +1,142 @@
+use std::any::type_name;
+
+use {Error, Interned, Result, Value, ValueConv, ValueKind};
+
+/// Describes a node and the actions it supports.
+///
+/// Returned by `Action::Meta`, encoded as a map value.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct NodeMeta {
+    /// The name of the node's type, for diagnostics only.
+    pub type_name: String,
+    /// Whether the node can be called.
+    pub call: bool,
+    /// Whether the children of the node can be listed.
+    pub list: bool,
+    /// Whether children can be set.
+    pub set: bool,
+    /// Whether children can be removed.
+    pub remove: bool,
+    /// Whether the node rejects all modifications at the moment.
+    pub read_only: bool,
+    /// The number of children, if known.
+    pub children: Option<u64>,
+    /// Documentation, e.g. the usage of a callable node.
+    pub doc: Option<String>,
+}
+
+impl NodeMeta {
+    /// Creates a descriptor for a node of type `T` which supports no actions
+    /// besides `get`.
+    pub fn new<T: ?Sized>() -> Self {
+        NodeMeta {
+            type_name: type_name::<T>().to_owned(),
+            ..Default::default()
+        }
+    }
+
+    fn read_entry(&mut self, key: Interned, value: Value) -> Result<()> {
+        match key {
+            k if k == Interned::from("type_name") => self.type_name = String::from_value(value)?,
+            k if k == Interned::from("call") => self.call = bool::from_value(value)?,
+            k if k == Interned::from("list") => self.list = bool::from_value(value)?,
+            k if k == Interned::from("set") => self.set = bool::from_value(value)?,
+            k if k == Interned::from("remove") => self.remove = bool::from_value(value)?,
+            k if k == Interned::from("read_only") => self.read_only = bool::from_value(value)?,
+            k if k == Interned::from("children") => {
+                self.children = match value {
+                    Value::Void => None,
+                    value => Some(u64::from_value(value)?),
+                }
+            }
+            k if k == Interned::from("doc") => {
+                self.doc = match value {
+                    Value::Void => None,
+                    value => Some(String::from_value(value)?),
+                }
+            }
+            // Unknown entries are allowed for forward compatibility
+            _ => {}
+        }
+
+        Ok(())
+    }
+}
+
+impl<'a> ValueConv<'a> for NodeMeta {
+    fn from_value(value: Value<'a>) -> Result<Self> {
+        let mut meta = NodeMeta::default();
+
+        match value {
+            Value::Map(map) => for (key, value) in map {
+                meta.read_entry(key, value)?;
+            },
+            Value::MapRef(map) => for entry in map {
+                meta.read_entry(entry.key, unsafe { entry.value.as_value() })?;
+            },
+            other => return Err(Error::wrong_type(ValueKind::Map, other.kind())),
+        }
+
+        Ok(meta)
+    }
+
+    fn into_value(self) -> Value<'a> {
+        let entries = vec![
+            (Interned::from("type_name"), self.type_name.into_value()),
+            (Interned::from("call"), Value::Bool(self.call)),
+            (Interned::from("list"), Value::Bool(self.list)),
+            (Interned::from("set"), Value::Bool(self.set)),
+            (Interned::from("remove"), Value::Bool(self.remove)),
+            (Interned::from("read_only"), Value::Bool(self.read_only)),
+            (
+                Interned::from("children"),
+                self.children.map(Value::Unsigned).unwrap_or(Value::Void),
+            ),
+            (
+                Interned::from("doc"),
+                self.doc.map(ValueConv::into_value).unwrap_or(Value::Void),
+            ),
+        ];
+
+        Value::Map(entries.into_iter().collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use error::error_kind;
+    use {ErrorKind, MapNode, Vnodes};
+
+    #[test]
+    fn check_conv() {
+        let meta = NodeMeta {
+            type_name: "Console".to_owned(),
+            call: true,
+            read_only: true,
+            children: Some(3),
+            doc: Some("Usage: console <line>".to_owned()),
+            ..Default::default()
+        };
+
+        assert_eq!(NodeMeta::from_value(meta.clone().into_value()), Ok(meta));
+    }
+
+    #[test]
+    fn check_map_node() {
+        let nodes = Vnodes::new();
+        nodes.insert("/a", 1u64).unwrap();
+        nodes.insert("/b", MapNode::new_node()).unwrap();
+        nodes.insert("/b/x", 1u64).unwrap();
+        nodes.insert("/b/y", 2u64).unwrap();
+
+        let meta = nodes.meta("/b").unwrap();
+        assert!(meta.type_name.ends_with("MapNode"));
+        assert!(meta.set);
+        assert!(!meta.call);
+        assert!(!meta.read_only);
+        assert_eq!(meta.children, Some(2));
+
+        assert_eq!(error_kind(nodes.meta("/a")), Some(ErrorKind::WrongType));
+    }
+}
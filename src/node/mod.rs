@@ -1,28 +1,93 @@
 pub use self::data::NodeData;
-pub use self::ptr::{NodeHandle, NodeHandleRef};
+pub use self::meta::NodeMeta;
+pub use self::ptr::{NodeHandle, NodeHandleRef, WeakNodeHandle};
 
 use parking_lot::RwLock;
 
 use raw::*;
-use {Interned, Result, Value, ValueConv, Vnodes};
+use {ErrorKind, Interned, Result, Value, ValueConv, Vnodes};
 
 mod data;
+mod meta;
 mod ptr;
 
+/// A node in the tree.
+///
+/// Only `get` is mandatory; all other actions are reported as
+/// `ErrorKind::ActionNotSupported` unless they're implemented.
 pub trait Node: Send + Sync {
-    fn call(&self, context: &Vnodes, args: &[Value]) -> Result<Value>;
+    fn call(&self, _context: &Vnodes, _args: &[Value]) -> Result<Value> {
+        Err(ErrorKind::ActionNotSupported.into())
+    }
 
     fn get(&self, context: &Vnodes, ident: Interned) -> Result<Value>;
 
-    fn set(&self, context: &Vnodes, ident: Interned, value: Value<'static>) -> Result<()>;
+    fn list(&self, _context: &Vnodes) -> Result<Vec<Interned>> {
+        Err(ErrorKind::ActionNotSupported.into())
+    }
+
+    fn remove(&self, _context: &Vnodes, _ident: Interned) -> Result<Value<'static>> {
+        Err(ErrorKind::ActionNotSupported.into())
+    }
+
+    fn set(&self, _context: &Vnodes, _ident: Interned, _value: Value<'static>) -> Result<()> {
+        Err(ErrorKind::ActionNotSupported.into())
+    }
+
+    /// Sets `ident` to `value` unless it exists, without other modifications
+    /// in between; returns `true` if it was set.
+    fn set_if_absent(
+        &self,
+        _context: &Vnodes,
+        _ident: Interned,
+        _value: Value<'static>,
+    ) -> Result<bool> {
+        Err(ErrorKind::ActionNotSupported.into())
+    }
+
+    /// Describes this node; by default only the type name is filled in, so
+    /// nodes implementing actions other than `get` should report them here.
+    fn meta(&self, _context: &Vnodes) -> NodeMeta {
+        NodeMeta::new::<Self>()
+    }
 }
 
+/// A node which needs mutable access for modifications, see `Node`.
 pub trait NodeMut: Send + Sync {
-    fn call(&self, context: &Vnodes, args: &[Value]) -> Result<Value<'static>>;
+    fn call(&self, _context: &Vnodes, _args: &[Value]) -> Result<Value<'static>> {
+        Err(ErrorKind::ActionNotSupported.into())
+    }
 
     fn get(&self, context: &Vnodes, ident: Interned) -> Result<Value<'static>>;
 
-    fn set(&mut self, context: &Vnodes, ident: Interned, value: Value<'static>) -> Result<()>;
+    fn list(&self, _context: &Vnodes) -> Result<Vec<Interned>> {
+        Err(ErrorKind::ActionNotSupported.into())
+    }
+
+    fn remove(&mut self, _context: &Vnodes, _ident: Interned) -> Result<Value<'static>> {
+        Err(ErrorKind::ActionNotSupported.into())
+    }
+
+    fn set(&mut self, _context: &Vnodes, _ident: Interned, _value: Value<'static>) -> Result<()> {
+        Err(ErrorKind::ActionNotSupported.into())
+    }
+
+    /// Sets `ident` to `value` unless it exists, without other modifications
+    /// in between; returns `true` if it was set.
+    fn set_if_absent(
+        &mut self,
+        _context: &Vnodes,
+        _ident: Interned,
+        _value: Value<'static>,
+    ) -> Result<bool> {
+        Err(ErrorKind::ActionNotSupported.into())
+    }
+
+    /// Describes this node; by default only the type name is filled in, so
+    /// nodes implementing actions other than `get` should report them here.
+    fn meta(&self, _context: &Vnodes) -> NodeMeta {
+        NodeMeta::new::<Self>()
+    }
 }
 
 impl<T> Node for RwLock<T>
@@ -37,7 +102,28 @@ where
         self.read().get(context, ident)
     }
 
+    fn list(&self, context: &Vnodes) -> Result<Vec<Interned>> {
+        self.read().list(context)
+    }
+
+    fn remove(&self, context: &Vnodes, ident: Interned) -> Result<Value<'static>> {
+        self.write().remove(context, ident)
+    }
+
     fn set(&self, context: &Vnodes, ident: Interned, value: Value<'static>) -> Result<()> {
         self.write().set(context, ident, value)
     }
+
+    fn set_if_absent(
+        &self,
+        context: &Vnodes,
+        ident: Interned,
+        value: Value<'static>,
+    ) -> Result<bool> {
+        self.write().set_if_absent(context, ident, value)
+    }
+
+    fn meta(&self, context: &Vnodes) -> NodeMeta {
+        self.read().meta(context)
+    }
 }
@@ -2,6 +2,7 @@ use std::marker::PhantomData;
 use std::mem::forget;
 use std::ptr::null_mut;
 
+use super::data::is_node_data;
 use super::*;
 
 #[derive(Debug, PartialEq)]
@@ -24,18 +25,52 @@ impl NodeHandle {
         unsafe { NodeHandle::from_raw(Box::into_raw(boxed) as *mut RawNodeData) }
     }
 
+    /// Takes ownership of one strong reference of `inner`.
+    ///
+    /// # Safety
+    ///
+    /// `inner` must be a valid node whose strong count includes this handle.
     pub unsafe fn from_raw(inner: *mut RawNodeData) -> Self {
         NodeHandle {
             data: NodeHandleRef::from_raw(inner),
         }
     }
 
-    pub fn get(&self, context: &Vnodes, ident: Interned) -> Value {
+    pub fn get(&self, context: &Vnodes, ident: Interned) -> Result<Value<'_>> {
         self.data.get(context, ident)
     }
 
-    pub fn insert(&self, context: &Vnodes, ident: Interned, value: Value<'static>) {
-        self.data.insert(context, ident, value);
+    pub fn insert(&self, context: &Vnodes, ident: Interned, value: Value<'static>) -> Result<()> {
+        self.data.insert(context, ident, value)
+    }
+
+    pub fn set_if_absent(
+        &self,
+        context: &Vnodes,
+        ident: Interned,
+        value: Value<'static>,
+    ) -> Result<bool> {
+        self.data.set_if_absent(context, ident, value)
+    }
+
+    pub fn call(&self, context: &Vnodes, args: Value) -> Result<Value<'_>> {
+        self.data.call(context, args)
+    }
+
+    pub fn list(&self, context: &Vnodes) -> Result<Vec<Interned>> {
+        self.data.list(context)
+    }
+
+    pub fn remove(&self, context: &Vnodes, ident: Interned) -> Result<Value<'static>> {
+        self.data.remove(context, ident)
+    }
+
+    pub fn meta(&self, context: &Vnodes) -> Result<NodeMeta> {
+        self.data.meta(context)
+    }
+
+    pub fn handshake(&self) -> Result<u32> {
+        self.data.handshake()
     }
 
     pub fn into_raw(this: Self) -> *mut RawNodeData {
@@ -53,6 +88,31 @@ impl NodeHandle {
     pub fn handle_ref<'a>(&'a self) -> NodeHandleRef<'a> {
         unsafe { NodeHandleRef::from_raw(self.as_raw()) }
     }
+
+    /// Returns a reference to the node if it has type `T`.
+    pub fn downcast_ref<T>(&self) -> Option<&T>
+    where
+        T: Node + 'static,
+    {
+        self.data.downcast_ref()
+    }
+
+    /// Creates a weak handle to this node, which doesn't keep the node alive.
+    ///
+    /// Returns `None` if the node doesn't support weak handles, e.g. because
+    /// it's implemented in another language and doesn't answer `Action::CloneWeak`.
+    pub fn downgrade(&self) -> Option<WeakNodeHandle> {
+        unsafe {
+            let res = NodeHandleRef::action(
+                &self.data,
+                null_mut(),
+                Action::CloneWeak,
+                Value::Void.into(),
+            );
+
+            res.ok().map(|_| WeakNodeHandle::from_raw(self.as_raw()))
+        }
+    }
 }
 
 impl Clone for NodeHandle {
@@ -69,6 +129,77 @@ impl Drop for NodeHandle {
     }
 }
 
+/// A handle which doesn't keep its node alive.
+///
+/// Use these for back-references (e.g. to a parent node) to avoid reference cycles.
+#[derive(Debug, PartialEq)]
+pub struct WeakNodeHandle {
+    inner: *mut RawNodeData,
+}
+
+impl WeakNodeHandle {
+    /// Takes ownership of one weak reference of `inner`.
+    ///
+    /// # Safety
+    ///
+    /// `inner` must point to node data whose weak count includes this handle.
+    pub unsafe fn from_raw(inner: *mut RawNodeData) -> Self {
+        WeakNodeHandle { inner }
+    }
+
+    pub fn into_raw(this: Self) -> *mut RawNodeData {
+        let raw = this.inner;
+
+        forget(this);
+
+        raw
+    }
+
+    /// Returns a strong handle if the node is still alive.
+    pub fn upgrade(&self) -> Option<NodeHandle> {
+        unsafe {
+            let this = NodeHandleRef::from_raw(self.inner);
+            let upgraded =
+                match NodeHandleRef::action(&this, null_mut(), Action::Upgrade, Value::Void.into()) {
+                    Ok(Value::Bool(b)) => b,
+                    _ => false,
+                };
+
+            match upgraded {
+                true => Some(NodeHandle::from_raw(self.inner)),
+                false => None,
+            }
+        }
+    }
+
+    unsafe fn weak_action(&self, action: Action) {
+        let this = NodeHandleRef::from_raw(self.inner);
+
+        let _ = NodeHandleRef::action(&this, null_mut(), action, Value::Void.into());
+    }
+}
+
+impl Clone for WeakNodeHandle {
+    fn clone(&self) -> Self {
+        unsafe {
+            self.weak_action(Action::CloneWeak);
+
+            WeakNodeHandle::from_raw(self.inner)
+        }
+    }
+}
+
+impl Drop for WeakNodeHandle {
+    fn drop(&mut self) {
+        unsafe {
+            self.weak_action(Action::DropWeak);
+        }
+    }
+}
+
+unsafe impl Send for WeakNodeHandle {}
+unsafe impl Sync for WeakNodeHandle {}
+
 #[derive(Copy, Clone, Debug, PartialEq)]
 pub struct NodeHandleRef<'a> {
     inner: *mut RawNodeData,
@@ -76,6 +207,11 @@ pub struct NodeHandleRef<'a> {
 }
 
 impl<'a> NodeHandleRef<'a> {
+    /// Borrows the node `inner` without taking a reference.
+    ///
+    /// # Safety
+    ///
+    /// `inner` must be a valid node which is kept alive for `'a`.
     pub unsafe fn from_raw(inner: *mut RawNodeData) -> Self {
         NodeHandleRef {
             inner,
@@ -83,34 +219,116 @@ impl<'a> NodeHandleRef<'a> {
         }
     }
 
-    pub fn get<'b>(&'b self, context: &Vnodes, ident: Interned) -> Value<'b> {
-        let ident: RawValue = Value::Interned(ident).into();
+    pub fn get<'b>(&'b self, context: &Vnodes, ident: Interned) -> Result<Value<'b>> {
+        unsafe { (self.vtable().get)(self.inner, context_ptr(context), ident).into_result() }
+    }
 
-        unsafe {
+    pub fn insert(&self, context: &Vnodes, ident: Interned, value: Value<'static>) -> Result<()> {
+        let res = unsafe {
+            (self.vtable().set)(self.inner, context_ptr(context), ident, value.into()).into_result()
+        };
+
+        res.map(|_| ())
+    }
+
+    /// Sets the child `ident` unless it exists, returning `true` if it was set.
+    ///
+    /// Nodes which can't do this atomically report `ErrorKind::ActionNotSupported`.
+    pub fn set_if_absent(
+        &self,
+        context: &Vnodes,
+        ident: Interned,
+        value: Value<'static>,
+    ) -> Result<bool> {
+        let entry = Value::Map(vec![(ident, value)].into_iter().collect());
+        let set = unsafe {
             Self::action(
                 self,
-                context as *const Vnodes as RawContextPtr,
-                Action::Get,
-                ident,
+                context_ptr(context),
+                Action::SetIfAbsent,
+                entry.into(),
             )
-        }
+        };
+
+        set.and_then(ValueConv::from_value)
     }
 
-    pub fn insert(&self, context: &Vnodes, ident: Interned, value: Value<'static>) {
-        unsafe {
+    /// Calls the node; multiple arguments are passed as value array.
+    pub fn call<'b>(&'b self, context: &Vnodes, args: Value) -> Result<Value<'b>> {
+        unsafe { self.call_raw(context, args.into()) }
+    }
+
+    /// Calls the node with raw arguments, see `ValueConv::with_raw`.
+    ///
+    /// # Safety
+    ///
+    /// `args` must be a valid raw value.
+    pub unsafe fn call_raw<'b>(&'b self, context: &Vnodes, args: RawValue) -> Result<Value<'b>> {
+        (self.vtable().call)(self.inner, context_ptr(context), args).into_result()
+    }
+
+    pub fn list(&self, context: &Vnodes) -> Result<Vec<Interned>> {
+        let list = unsafe { (self.vtable().list)(self.inner, context_ptr(context)).into_result() };
+
+        list.and_then(ValueConv::from_value)
+    }
+
+    /// Removes the child `ident`, returning its value.
+    pub fn remove(&self, context: &Vnodes, ident: Interned) -> Result<Value<'static>> {
+        let removed =
+            unsafe { (self.vtable().remove)(self.inner, context_ptr(context), ident).into_result() };
+
+        removed.map(Value::make_owned)
+    }
+
+    /// Asks the node to describe itself.
+    pub fn meta(&self, context: &Vnodes) -> Result<NodeMeta> {
+        let meta = unsafe {
             Self::action(
                 self,
-                context as *const Vnodes as RawContextPtr,
-                Action::Set,
-                (ident, value).into_value().into(),
-            );
-        }
+                context_ptr(context),
+                Action::Meta,
+                Value::Void.into(),
+            )
+        };
+
+        meta.and_then(NodeMeta::from_value)
+    }
+
+    /// Returns the `ABI_VERSION` the node was built against.
+    ///
+    /// Nodes which predate `Action::Handshake` report `ErrorKind::ActionNotSupported`.
+    pub fn handshake(&self) -> Result<u32> {
+        let version = unsafe {
+            Self::action(
+                self,
+                null_mut(),
+                Action::Handshake,
+                Value::Unsigned(ABI_VERSION as u64).into(),
+            )
+        };
+
+        version.and_then(ValueConv::from_value)
     }
 
     pub fn raw(&self) -> *mut RawNodeData {
         self.inner
     }
 
+    /// Returns a reference to the node if it has type `T`.
+    ///
+    /// Only nodes created by `NodeHandle::new` in this library match, the
+    /// answer of foreign nodes to `Action::TypeId` isn't trusted.
+    pub fn downcast_ref<T>(&self) -> Option<&'a T>
+    where
+        T: Node + 'static,
+    {
+        match unsafe { is_node_data::<T>(self.inner) } {
+            true => unsafe { Some(&*(*(self.inner as *const NodeData<T>)).node) },
+            false => None,
+        }
+    }
+
     pub fn to_handle(&self) -> NodeHandle {
         unsafe {
             Self::clone(self);
@@ -119,25 +337,231 @@ impl<'a> NodeHandleRef<'a> {
         }
     }
 
+    /// Performs an action without a vtable entry of its own.
+    ///
+    /// # Safety
+    ///
+    /// `context` must be null or a valid context, `arg` must be a valid raw
+    /// value and the action must not break the reference counting, e.g. by
+    /// dropping a weak reference which isn't owned.
     pub unsafe fn action<'b>(
         this: &'b Self,
         context: *mut Vnodes,
         action: Action,
         arg: RawValue,
-    ) -> Value<'b> {
-        let raw = &*this.inner;
-
-        Value::from_raw((raw.action)(this.inner, context, action, arg))
+    ) -> Result<Value<'b>> {
+        (this.vtable().action)(this.inner, context, action.into_raw(), arg).into_result()
     }
 
+    /// Acquires another strong reference.
+    ///
+    /// # Safety
+    ///
+    /// The reference has to be released by `drop` or a `NodeHandle` owning it.
     pub unsafe fn clone(this: &Self) {
-        Self::action(this, null_mut(), Action::Clone, Value::Void.into());
+        (this.vtable().clone)(this.inner)
     }
 
+    /// Releases a strong reference.
+    ///
+    /// # Safety
+    ///
+    /// The caller must own the reference and not use it afterwards.
     pub unsafe fn drop(this: &mut Self) {
-        Self::action(this, null_mut(), Action::Drop, Value::Void.into());
+        (this.vtable().drop)(this.inner)
+    }
+
+    fn vtable(&self) -> &RawNodeVtable {
+        unsafe { &*(*self.inner).vtable }
     }
 }
 
 unsafe impl<'a> Send for NodeHandleRef<'a> {}
 unsafe impl<'a> Sync for NodeHandleRef<'a> {}
+
+fn context_ptr(context: &Vnodes) -> RawContextPtr {
+    context as *const Vnodes as RawContextPtr
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::any::TypeId;
+    use std::mem::size_of;
+    use std::slice::from_raw_parts;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use {ErrorKind, MapNode};
+
+    /// Counts how often it has been dropped.
+    struct DropCounter {
+        drops: Arc<AtomicUsize>,
+        parent: Option<WeakNodeHandle>,
+    }
+
+    impl Node for DropCounter {
+        fn call(&self, _: &Vnodes, _: &[Value]) -> Result<Value<'_>> {
+            Err(ErrorKind::ActionNotSupported.into())
+        }
+
+        fn get(&self, _: &Vnodes, _: Interned) -> Result<Value<'_>> {
+            Err(ErrorKind::NoSuchEntry.into())
+        }
+
+        fn set(&self, _: &Vnodes, _: Interned, _: Value<'static>) -> Result<()> {
+            Err(ErrorKind::ActionNotSupported.into())
+        }
+    }
+
+    impl Drop for DropCounter {
+        fn drop(&mut self) {
+            self.drops.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    fn counter(parent: Option<WeakNodeHandle>) -> (NodeHandle, Arc<AtomicUsize>) {
+        let drops = Arc::new(AtomicUsize::new(0));
+        let node = NodeHandle::new(DropCounter {
+            drops: drops.clone(),
+            parent,
+        });
+
+        (node, drops)
+    }
+
+    #[test]
+    fn check_downcast() {
+        let (node, _) = counter(None);
+        assert!(node.downcast_ref::<DropCounter>().is_some());
+        assert!(node.downcast_ref::<RwLock<MapNode>>().is_none());
+
+        let map = MapNode::new_node();
+        let context = Vnodes::new();
+        map.insert(&context, Interned::from("answer"), Value::Unsigned(42))
+            .unwrap();
+
+        let inner = map.handle_ref().downcast_ref::<RwLock<MapNode>>().unwrap();
+        let value = inner.read().get(&context, Interned::from("answer"));
+        assert_eq!(value.and_then(u64::from_value), Ok(42));
+    }
+
+    #[test]
+    fn check_upgrade() {
+        let (node, drops) = counter(None);
+        let weak = node.downgrade().unwrap();
+
+        assert_eq!(weak.upgrade(), Some(node.clone()));
+
+        drop(node);
+        assert_eq!(drops.load(Ordering::SeqCst), 1);
+        assert_eq!(weak.upgrade(), None);
+        assert_eq!(weak.clone().upgrade(), None);
+    }
+
+    #[test]
+    fn check_parent_link() {
+        let context = Vnodes::new();
+        let parent = MapNode::new_node();
+        let (child, drops) = counter(parent.downgrade());
+
+        parent
+            .insert(&context, Interned::from("child"), Value::Node(child.clone()))
+            .unwrap();
+        drop(child);
+        assert_eq!(drops.load(Ordering::SeqCst), 0);
+
+        // The child only holds a weak reference, so there's no cycle
+        drop(parent);
+        assert_eq!(drops.load(Ordering::SeqCst), 1);
+    }
+
+    static FOREIGN_VTABLE: RawNodeVtable = RawNodeVtable {
+        get: foreign_get,
+        set: foreign_set,
+        call: foreign_call,
+        list: foreign_list,
+        remove: foreign_get,
+        clone: foreign_clone,
+        drop: foreign_clone,
+        action: foreign_action,
+    };
+
+    unsafe extern "C" fn foreign_get(_: *mut RawNodeData, _: *mut Vnodes, _: Interned) -> RawResult {
+        Err(ErrorKind::NoSuchEntry.into()).into()
+    }
+
+    unsafe extern "C" fn foreign_set(
+        _: *mut RawNodeData,
+        _: *mut Vnodes,
+        _: Interned,
+        _: RawValue,
+    ) -> RawResult {
+        Err(ErrorKind::ActionNotSupported.into()).into()
+    }
+
+    unsafe extern "C" fn foreign_call(_: *mut RawNodeData, _: *mut Vnodes, _: RawValue) -> RawResult {
+        Err(ErrorKind::ActionNotSupported.into()).into()
+    }
+
+    unsafe extern "C" fn foreign_list(_: *mut RawNodeData, _: *mut Vnodes) -> RawResult {
+        Ok(Value::Void).into()
+    }
+
+    unsafe extern "C" fn foreign_clone(_: *mut RawNodeData) {}
+
+    /// Claims to be of every type, but doesn't support weak handles.
+    unsafe extern "C" fn foreign_action(
+        _: *mut RawNodeData,
+        _: *mut Vnodes,
+        action: RawAction,
+        _: RawValue,
+    ) -> RawResult {
+        match Action::from_raw(action) {
+            Some(Action::TypeId) => Ok(Value::Bool(true)).into(),
+            _ => Err(ErrorKind::ActionNotSupported.into()).into(),
+        }
+    }
+
+    /// A node as it could be implemented in C; it lives forever.
+    fn foreign() -> NodeHandle {
+        let raw = Box::leak(Box::new(RawNodeData {
+            vtable: &FOREIGN_VTABLE,
+        }));
+
+        unsafe { NodeHandle::from_raw(raw) }
+    }
+
+    #[test]
+    fn check_foreign_without_weak() {
+        let node = foreign();
+
+        assert!(node.downgrade().is_none());
+    }
+
+    #[test]
+    fn check_foreign_downcast() {
+        let node = foreign();
+        let id = TypeId::of::<DropCounter>();
+        let bytes = unsafe { from_raw_parts(&id as *const TypeId as *const u8, size_of::<TypeId>()) };
+        let handle = node.handle_ref();
+        let arg = Value::BlobRef(bytes).into();
+        let answer = unsafe { NodeHandleRef::action(&handle, null_mut(), Action::TypeId, arg) };
+
+        // The node claims to be a `DropCounter`, but isn't one
+        assert_eq!(answer.and_then(bool::from_value), Ok(true));
+        assert!(node.downcast_ref::<DropCounter>().is_none());
+    }
+
+    #[test]
+    fn check_weak_outlives_node() {
+        let (node, drops) = counter(None);
+        let weak = node.downgrade().unwrap();
+        let other = weak.clone();
+
+        drop(node);
+        assert_eq!(drops.load(Ordering::SeqCst), 1);
+
+        drop(weak);
+        assert_eq!(other.upgrade(), None);
+    }
+}
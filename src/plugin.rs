@@ -0,0 +1,250 @@
+//! Loading nodes from shared libraries.
+
+use std::ffi::OsStr;
+use std::sync::Arc;
+
+use libloading::Library;
+
+use raw::{PluginDeclaration, RawContextPtr, ABI_VERSION};
+use {Error, ErrorKind, Interned, Node, NodeHandle, NodeMeta, Result, Value, ValueConv, Vnodes};
+
+/// Loads the plugin at `path` and returns its root node.
+///
+/// See `Vnodes::load_plugin` for the safety requirements.
+pub unsafe fn load(context: &Vnodes, path: &OsStr) -> Result<NodeHandle> {
+    let library = Arc::new(Library::new(path).map_err(load_error)?);
+
+    let node = {
+        let declaration = library
+            .get::<*const PluginDeclaration>(b"VNODES_PLUGIN\0")
+            .map_err(load_error)?;
+        let declaration = &**declaration;
+
+        if declaration.abi_version != ABI_VERSION {
+            return Err(abi_mismatch(declaration.abi_version));
+        }
+
+        let context = guest_context(context, &library);
+        let raw = (declaration.init)(&context as *const Vnodes as RawContextPtr);
+
+        NodeHandle::from_value(raw.into_result()?)?
+    };
+
+    // The declaration may be written by hand, so ask the node as well
+    match node.handshake() {
+        Ok(ABI_VERSION) => {}
+        Ok(version) => return Err(abi_mismatch(version)),
+        Err(ref e) if e.kind() == ErrorKind::ActionNotSupported => {
+            return Err(Error::new(ErrorKind::AbiMismatch)
+                .with_message("plugin node doesn't support the handshake"))
+        }
+        Err(e) => return Err(e),
+    }
+
+    Ok(PluginNode::new_node(node, library))
+}
+
+fn abi_mismatch(version: u32) -> Error {
+    Error::new(ErrorKind::AbiMismatch).with_message(format!(
+        "plugin uses version {}, expected {}",
+        version, ABI_VERSION
+    ))
+}
+
+fn load_error(e: ::libloading::Error) -> Error {
+    Error::new(ErrorKind::LibraryLoad).with_message(e.to_string())
+}
+
+/// Forwards to a node implemented by a plugin, keeping the library loaded.
+///
+/// Everything the plugin hands out is wrapped or copied, so no code or
+/// data of the library can outlive it. Everything handed to the plugin is
+/// wrapped in a `HostNode`, so the plugin can't pass its own values to the
+/// host without going through `adopt`.
+struct PluginNode {
+    // Declared before `library`, so it's dropped first
+    node: NodeHandle,
+    library: Arc<Library>,
+}
+
+impl PluginNode {
+    fn new_node(node: NodeHandle, library: Arc<Library>) -> NodeHandle {
+        // Nodes of the host which went through the plugin are unwrapped
+        if let Some(host) = node.downcast_ref::<HostNode>() {
+            return host.node.clone();
+        }
+
+        NodeHandle::new(PluginNode { node, library })
+    }
+}
+
+impl Node for PluginNode {
+    fn call(&self, context: &Vnodes, args: &[Value]) -> Result<Value<'_>> {
+        let args = match args.len() {
+            0 => Value::Void,
+            _ => Value::ValueArray(
+                args.iter()
+                    .map(|arg| lend(&self.library, arg.clone()))
+                    .collect(),
+            ),
+        };
+
+        let context = guest_context(context, &self.library);
+        self.node
+            .call(&context, args)
+            .map(|v| adopt(&self.library, v))
+    }
+
+    fn get(&self, context: &Vnodes, ident: Interned) -> Result<Value<'_>> {
+        let context = guest_context(context, &self.library);
+        self.node
+            .get(&context, ident)
+            .map(|v| adopt(&self.library, v))
+    }
+
+    fn list(&self, context: &Vnodes) -> Result<Vec<Interned>> {
+        self.node.list(&guest_context(context, &self.library))
+    }
+
+    fn remove(&self, context: &Vnodes, ident: Interned) -> Result<Value<'static>> {
+        let context = guest_context(context, &self.library);
+        self.node
+            .remove(&context, ident)
+            .map(|v| adopt(&self.library, v))
+    }
+
+    fn set(&self, context: &Vnodes, ident: Interned, value: Value<'static>) -> Result<()> {
+        let context = guest_context(context, &self.library);
+        self.node
+            .insert(&context, ident, lend(&self.library, value))
+    }
+
+    fn set_if_absent(
+        &self,
+        context: &Vnodes,
+        ident: Interned,
+        value: Value<'static>,
+    ) -> Result<bool> {
+        let context = guest_context(context, &self.library);
+        self.node
+            .set_if_absent(&context, ident, lend(&self.library, value))
+    }
+
+    fn meta(&self, context: &Vnodes) -> NodeMeta {
+        self.node
+            .meta(&guest_context(context, &self.library))
+            .unwrap_or_else(|_| NodeMeta::new::<Self>())
+    }
+}
+
+/// Forwards to a node of the host which was handed to a plugin.
+///
+/// Values the plugin passes to it are adopted, so the library stays loaded
+/// as long as the host holds on to them.
+struct HostNode {
+    node: NodeHandle,
+    library: Arc<Library>,
+}
+
+impl HostNode {
+    fn new_node(node: NodeHandle, library: Arc<Library>) -> NodeHandle {
+        // Nodes of the plugin which went through the host are unwrapped
+        if let Some(plugin) = node.downcast_ref::<PluginNode>() {
+            return plugin.node.clone();
+        }
+
+        NodeHandle::new(HostNode { node, library })
+    }
+}
+
+impl Node for HostNode {
+    fn call(&self, context: &Vnodes, args: &[Value]) -> Result<Value<'_>> {
+        let args = match args.len() {
+            0 => Value::Void,
+            _ => Value::ValueArray(
+                args.iter()
+                    .map(|arg| adopt(&self.library, arg.clone()))
+                    .collect(),
+            ),
+        };
+
+        self.node
+            .call(context, args)
+            .map(|v| lend(&self.library, v))
+    }
+
+    fn get(&self, context: &Vnodes, ident: Interned) -> Result<Value<'_>> {
+        self.node
+            .get(context, ident)
+            .map(|v| lend(&self.library, v))
+    }
+
+    fn list(&self, context: &Vnodes) -> Result<Vec<Interned>> {
+        self.node.list(context)
+    }
+
+    fn remove(&self, context: &Vnodes, ident: Interned) -> Result<Value<'static>> {
+        self.node
+            .remove(context, ident)
+            .map(|v| lend(&self.library, v))
+    }
+
+    fn set(&self, context: &Vnodes, ident: Interned, value: Value<'static>) -> Result<()> {
+        self.node
+            .insert(context, ident, adopt(&self.library, value))
+    }
+
+    fn set_if_absent(
+        &self,
+        context: &Vnodes,
+        ident: Interned,
+        value: Value<'static>,
+    ) -> Result<bool> {
+        self.node
+            .set_if_absent(context, ident, adopt(&self.library, value))
+    }
+
+    fn meta(&self, context: &Vnodes) -> NodeMeta {
+        self.node
+            .meta(context)
+            .unwrap_or_else(|_| NodeMeta::new::<Self>())
+    }
+}
+
+/// Takes a value from the plugin, wrapping its nodes and copying its buffers.
+fn adopt(library: &Arc<Library>, value: Value) -> Value<'static> {
+    match value.make_owned() {
+        Value::Map(map) => {
+            Value::Map(map.into_iter().map(|(k, v)| (k, adopt(library, v))).collect())
+        }
+        Value::Node(node) => Value::Node(PluginNode::new_node(node, library.clone())),
+        Value::SharedBlob(blob) => Value::Blob(blob.as_slice().into()),
+        Value::SharedString(string) => Value::String(string.as_slice().into()),
+        Value::ValueArray(array) => {
+            Value::ValueArray(array.into_iter().map(|v| adopt(library, v)).collect())
+        }
+        value => value,
+    }
+}
+
+/// Hands a value to the plugin, wrapping the nodes of the host.
+fn lend(library: &Arc<Library>, value: Value) -> Value<'static> {
+    match value.make_owned() {
+        Value::Map(map) => {
+            Value::Map(map.into_iter().map(|(k, v)| (k, lend(library, v))).collect())
+        }
+        Value::Node(node) => Value::Node(HostNode::new_node(node, library.clone())),
+        Value::ValueArray(array) => {
+            Value::ValueArray(array.into_iter().map(|v| lend(library, v)).collect())
+        }
+        value => value,
+    }
+}
+
+/// The context passed to the plugin, which only reaches the host through `HostNode`s.
+fn guest_context(context: &Vnodes, library: &Arc<Library>) -> Vnodes {
+    Vnodes {
+        current: HostNode::new_node(context.current.clone(), library.clone()),
+        root: HostNode::new_node(context.root.clone(), library.clone()),
+    }
+}
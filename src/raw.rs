@@ -1,27 +1,483 @@
-#![no_mangle]
-#![allow(unused_variables)]
+//! The raw representation of values and nodes, and the C interface built on it.
+//!
+//! The C declarations are in `include/vnodes.h`.
 
-pub use data::{Action, Flags, RawNodeData, RawValue, RawValueInner};
+pub use data::{Action, Flags, RawAction, RawMapEntry, RawNodeData, RawNodeVtable, RawResult,
+               RawSharedData, RawValue, RawValueInner};
 pub use {Interned, Vnodes};
 
 pub use self::export::*;
 
+use std::mem::{align_of, size_of};
+
 pub type RawContextPtr = *mut Vnodes;
 pub type RawNodePtr = *mut RawNodeData;
+pub type RawSharedPtr = *mut RawSharedData;
 pub type RawValueList = *const RawValue;
 
+/// The version of the raw interface; plugins built against another version are rejected.
+///
+/// Has to be bumped whenever the layout of one of the types below, the meaning of
+/// an action or flag or the `Vnodes` context changes. Adding actions or using
+/// reserved flags is compatible, since unknown ones are rejected by the receiver.
+pub const ABI_VERSION: u32 = 3;
+
+/// The entry point of a plugin, exported as static `VNODES_PLUGIN`.
+///
+/// Rust plugins declare it with `export_plugin!`.
+#[repr(C)]
+pub struct PluginDeclaration {
+    /// Must be `ABI_VERSION`.
+    pub abi_version: u32,
+    /// Creates the root node of the plugin and returns it as owned node value.
+    pub init: unsafe extern "C" fn(context: RawContextPtr) -> RawResult,
+}
+
+// The layout of everything that's shared with C and plugins
+assert_layout!(Action, size = 4, align = 4);
+assert_layout!(Flags, size = 4, align = 4);
+assert_layout!(Interned, size = 8, align = align_of::<u64>());
+assert_layout!(RawValueInner, size = 8, align = align_of::<u64>());
+assert_layout!(
+    RawValue,
+    size = 2 * size_of::<usize>() + 8,
+    align = align_of::<u64>(),
+    flags = 0,
+    extra = size_of::<usize>(),
+    value = 2 * size_of::<usize>()
+);
+assert_layout!(
+    RawMapEntry,
+    size = 2 * size_of::<usize>() + 16,
+    align = align_of::<u64>(),
+    key = 0,
+    value = 8
+);
+assert_layout!(
+    RawResult,
+    size = align_of::<u64>() + size_of::<RawValue>(),
+    align = align_of::<u64>(),
+    status = 0,
+    value = align_of::<u64>()
+);
+assert_layout!(RawNodeData, size = size_of::<usize>(), align = align_of::<usize>(), vtable = 0);
+assert_layout!(
+    RawNodeVtable,
+    size = size_of::<[usize; 8]>(),
+    align = align_of::<usize>(),
+    get = 0,
+    set = size_of::<usize>(),
+    call = 2 * size_of::<usize>(),
+    list = 3 * size_of::<usize>(),
+    remove = 4 * size_of::<usize>(),
+    clone = 5 * size_of::<usize>(),
+    drop = 6 * size_of::<usize>(),
+    action = 7 * size_of::<usize>()
+);
+assert_layout!(
+    RawSharedData,
+    size = 3 * size_of::<usize>(),
+    align = align_of::<usize>(),
+    action = 0,
+    data = size_of::<usize>(),
+    len = 2 * size_of::<usize>()
+);
+assert_layout!(
+    PluginDeclaration,
+    size = 2 * size_of::<usize>(),
+    align = align_of::<usize>(),
+    abi_version = 0,
+    init = size_of::<usize>()
+);
+
 mod export {
+    use std::panic::{catch_unwind, AssertUnwindSafe};
+    use std::slice::{from_raw_parts, from_raw_parts_mut};
+    use std::str::from_utf8;
+
     use super::*;
+    use {is_internable, Error, ErrorKind, InternedPathBuf, NodeHandle, NodeHandleRef, Result, Value,
+         ValueArray};
+
+    /// Runs `f`, turning a panic into `ErrorKind::Panicked` since it must not unwind into C.
+    fn guard<F>(f: F) -> RawResult
+    where
+        F: FnOnce() -> Result<Value<'static>>,
+    {
+        match catch_unwind(AssertUnwindSafe(f)) {
+            Ok(res) => res.into(),
+            Err(_) => Err(ErrorKind::Panicked.into()).into(),
+        }
+    }
+
+    unsafe fn bytes<'a>(ptr: *const u8, len: usize) -> &'a [u8] {
+        match len {
+            0 => &[],
+            len => from_raw_parts(ptr, len),
+        }
+    }
+
+    unsafe fn raw_values<'a>(values: *const RawValue, len: usize) -> &'a [RawValue] {
+        match len {
+            0 => &[],
+            len => from_raw_parts(values, len),
+        }
+    }
 
-    pub unsafe extern "C" fn vnodes_context_insert(
+    /// Releases `value` and resets it to void, leaking it if it has unknown flags.
+    unsafe fn free(value: &mut RawValue) {
+        match value.validate() {
+            Ok(()) => value.drop_in_place(),
+            Err(_) => *value = Value::Void.into(),
+        }
+    }
+
+    unsafe fn path_from_raw(path: *const u8, len: usize) -> Result<InternedPathBuf> {
+        let path = from_utf8(bytes(path, len)).map_err(|_| ErrorKind::Utf8Error)?;
+
+        match path.split('/').find(|name| !is_internable(name)) {
+            Some(name) => Err(Error::new(ErrorKind::InvalidSyntax)
+                .with_message(format!("invalid name `{}` in path `{}`", name, path))),
+            None => Ok(InternedPathBuf::from(path)),
+        }
+    }
+
+    /// Takes ownership of `value`; values with unknown flags are leaked.
+    unsafe fn value_from_raw(value: RawValue) -> Result<Value<'static>> {
+        value.validate()?;
+
+        Ok(Value::from_raw(value).make_owned())
+    }
+
+    /// Calls a plugin's `init` function on behalf of `export_plugin!`.
+    #[doc(hidden)]
+    pub unsafe fn plugin_init(
         context: RawContextPtr,
-        ident: Interned,
+        init: fn(&Vnodes) -> Result<NodeHandle>,
+    ) -> RawResult {
+        guard(|| init(&*context).map(Value::Node))
+    }
+
+    /// Creates a new, empty tree; free it with `vnodes_context_free`.
+    #[no_mangle]
+    pub extern "C" fn vnodes_context_new() -> RawContextPtr {
+        Box::into_raw(Box::new(Vnodes::new()))
+    }
+
+    /// Frees a tree created by `vnodes_context_new`.
+    ///
+    /// # Safety
+    ///
+    /// `context` must be null or a tree returned by `vnodes_context_new`
+    /// which has not been freed before.
+    #[no_mangle]
+    pub unsafe extern "C" fn vnodes_context_free(context: RawContextPtr) {
+        if !context.is_null() {
+            drop(Box::from_raw(context));
+        }
+    }
+
+    /// Returns the value at `path`; the value is owned by the caller.
+    ///
+    /// # Safety
+    ///
+    /// `context` must be a valid tree and `path` must point to `path_len` bytes of UTF-8.
+    #[no_mangle]
+    pub unsafe extern "C" fn vnodes_get(
+        context: RawContextPtr,
+        path: *const u8,
+        path_len: usize,
+    ) -> RawResult {
+        guard(|| (*context).get(path_from_raw(path, path_len)?))
+    }
+
+    /// Inserts `value` at `path`, taking ownership of it.
+    ///
+    /// # Safety
+    ///
+    /// `context` must be a valid tree, `path` must point to `path_len` bytes of UTF-8
+    /// and `value` must be a valid raw value.
+    #[no_mangle]
+    pub unsafe extern "C" fn vnodes_insert(
+        context: RawContextPtr,
+        path: *const u8,
+        path_len: usize,
         value: RawValue,
-    ) {
-        let context = &*context;
+    ) -> RawResult {
+        guard(|| {
+            let value = value_from_raw(value)?;
+            (*context).insert(path_from_raw(path, path_len)?, value)?;
+
+            Ok(Value::Void)
+        })
+    }
+
+    /// Removes the entry at `path`, returning its value.
+    ///
+    /// # Safety
+    ///
+    /// `context` must be a valid tree and `path` must point to `path_len` bytes of UTF-8.
+    #[no_mangle]
+    pub unsafe extern "C" fn vnodes_remove(
+        context: RawContextPtr,
+        path: *const u8,
+        path_len: usize,
+    ) -> RawResult {
+        guard(|| (*context).remove(path_from_raw(path, path_len)?))
+    }
+
+    /// Lists the children of the node at `path` as an array of interned identifiers.
+    ///
+    /// # Safety
+    ///
+    /// `context` must be a valid tree and `path` must point to `path_len` bytes of UTF-8.
+    #[no_mangle]
+    pub unsafe extern "C" fn vnodes_list(
+        context: RawContextPtr,
+        path: *const u8,
+        path_len: usize,
+    ) -> RawResult {
+        guard(|| {
+            let list = (*context).list(path_from_raw(path, path_len)?)?;
+
+            Ok(Value::ValueArray(list.into_iter().map(Value::Interned).collect()))
+        })
+    }
+
+    /// Calls the node at `path`, taking ownership of `args`.
+    ///
+    /// Multiple arguments are passed as value array.
+    ///
+    /// # Safety
+    ///
+    /// `context` must be a valid tree, `path` must point to `path_len` bytes of UTF-8
+    /// and `args` must be a valid raw value.
+    #[no_mangle]
+    pub unsafe extern "C" fn vnodes_call(
+        context: RawContextPtr,
+        path: *const u8,
+        path_len: usize,
+        args: RawValue,
+    ) -> RawResult {
+        guard(|| {
+            let args = value_from_raw(args)?;
+            (*context).call(path_from_raw(path, path_len)?, args)
+        })
+    }
+
+    /// Interns the `len` bytes at `ptr`.
+    ///
+    /// Returns an interned value with all bits set (`UINT64_MAX`) if the bytes
+    /// aren't a name of at most 10 ASCII letters, digits, `-`, `_` and `.`;
+    /// no name interns to it.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must point to `len` bytes.
+    #[no_mangle]
+    pub unsafe extern "C" fn vnodes_intern(ptr: *const u8, len: usize) -> Interned {
+        match from_utf8(bytes(ptr, len)) {
+            Ok(name) if is_internable(name) => Interned::from(name),
+            _ => Interned(!0),
+        }
+    }
+
+    /// Writes the name of `interned` to `buf` and returns its length.
+    ///
+    /// Codes which don't stand for a character are written as `?`.
+    ///
+    /// # Safety
+    ///
+    /// `buf` must be valid for writes of 10 bytes.
+    #[no_mangle]
+    pub unsafe extern "C" fn vnodes_un_intern(interned: Interned, buf: *mut u8) -> usize {
+        interned.un_intern_raw(from_raw_parts_mut(buf, 10))
+    }
+
+    #[no_mangle]
+    pub extern "C" fn vnodes_value_void() -> RawValue {
+        Value::Void.into()
+    }
+
+    #[no_mangle]
+    pub extern "C" fn vnodes_value_bool(value: bool) -> RawValue {
+        Value::Bool(value).into()
+    }
+
+    #[no_mangle]
+    pub extern "C" fn vnodes_value_signed(value: i64) -> RawValue {
+        Value::Signed(value).into()
+    }
+
+    #[no_mangle]
+    pub extern "C" fn vnodes_value_unsigned(value: u64) -> RawValue {
+        Value::Unsigned(value).into()
+    }
+
+    #[no_mangle]
+    pub extern "C" fn vnodes_value_float(value: f64) -> RawValue {
+        Value::Float(value).into()
+    }
+
+    #[no_mangle]
+    pub extern "C" fn vnodes_value_interned(value: Interned) -> RawValue {
+        Value::Interned(value).into()
+    }
+
+    /// Creates an owned string value by copying `len` bytes of UTF-8 from `ptr`.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must point to `len` bytes.
+    #[no_mangle]
+    pub unsafe extern "C" fn vnodes_value_string(ptr: *const u8, len: usize) -> RawValue {
+        Value::String(bytes(ptr, len).into()).into()
+    }
+
+    /// Creates an owned blob value by copying `len` bytes from `ptr`.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must point to `len` bytes.
+    #[no_mangle]
+    pub unsafe extern "C" fn vnodes_value_blob(ptr: *const u8, len: usize) -> RawValue {
+        Value::Blob(bytes(ptr, len).into()).into()
+    }
+
+    /// Creates an owned node value holding a new strong handle to `node`.
+    ///
+    /// # Safety
+    ///
+    /// `node` must be a valid node.
+    #[no_mangle]
+    pub unsafe extern "C" fn vnodes_value_node(node: RawNodePtr) -> RawValue {
+        Value::Node(NodeHandleRef::from_raw(node).to_handle()).into()
+    }
+
+    /// Creates an owned array, moving the `len` values at `values` into it.
+    ///
+    /// The values must not be used or freed afterwards. Returns void if one of
+    /// them has unknown flags, in which case the values are leaked.
+    ///
+    /// # Safety
+    ///
+    /// `values` must point to `len` valid raw values.
+    #[no_mangle]
+    pub unsafe extern "C" fn vnodes_value_array(values: *const RawValue, len: usize) -> RawValue {
+        let values = raw_values(values, len);
+        if values.iter().any(|value| value.validate().is_err()) {
+            return Value::Void.into();
+        }
+
+        let array: ValueArray = values.iter().map(|&value| Value::from_raw(value)).collect();
+
+        Value::ValueArray(array).into()
+    }
+
+    /// Creates a deep copy of `*value`; returns void if it has unknown flags.
+    ///
+    /// # Safety
+    ///
+    /// `value` must point to a valid raw value.
+    #[no_mangle]
+    pub unsafe extern "C" fn vnodes_value_clone(value: *const RawValue) -> RawValue {
+        match (*value).validate() {
+            Ok(()) => (*value).clone_raw(),
+            Err(_) => Value::Void.into(),
+        }
+    }
+
+    /// Releases the payload of `*value` (if it's owned) and resets it to void.
+    ///
+    /// Values with unknown flags are leaked.
+    ///
+    /// # Safety
+    ///
+    /// `value` must be null or point to a valid raw value.
+    #[no_mangle]
+    pub unsafe extern "C" fn vnodes_value_free(value: *mut RawValue) {
+        if let Some(value) = value.as_mut() {
+            free(value);
+        }
+    }
+
+    /// Releases the value or error message of `*result` and resets it to a void success.
+    ///
+    /// # Safety
+    ///
+    /// `result` must be null or point to a valid raw result.
+    #[no_mangle]
+    pub unsafe extern "C" fn vnodes_result_free(result: *mut RawResult) {
+        if let Some(result) = result.as_mut() {
+            result.status = 0;
+            free(&mut result.value);
+        }
+    }
+
+    /// Acquires another strong reference to `node`.
+    ///
+    /// # Safety
+    ///
+    /// `node` must be a valid node.
+    #[no_mangle]
+    pub unsafe extern "C" fn vnodes_node_clone(node: RawNodePtr) -> RawNodePtr {
+        NodeHandle::into_raw(NodeHandleRef::from_raw(node).to_handle())
+    }
+
+    /// Releases a strong reference to `node`; the node is freed with the last one.
+    ///
+    /// # Safety
+    ///
+    /// `node` must be null or a strong reference owned by the caller.
+    #[no_mangle]
+    pub unsafe extern "C" fn vnodes_node_drop(node: RawNodePtr) {
+        if !node.is_null() {
+            drop(NodeHandle::from_raw(node));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use {ErrorKind, Value, ValueKind};
+
+    fn status(res: RawResult) -> Option<ErrorKind> {
+        unsafe { res.into_result() }.err().map(|e| e.kind())
+    }
+
+    #[test]
+    fn check_invalid_input() {
+        let context = vnodes_context_new();
+        let path = |s: &'static str| (s.as_ptr(), s.len());
+
+        unsafe {
+            let name = "Hello World";
+            assert_eq!(vnodes_intern(name.as_ptr(), name.len()), Interned(!0));
+            let name = "Hello";
+            assert_eq!(vnodes_intern(name.as_ptr(), name.len()), Interned::from("hello"));
+
+            let (ptr, len) = path("/hello world");
+            assert_eq!(status(vnodes_get(context, ptr, len)), Some(ErrorKind::InvalidSyntax));
+
+            let mut value: RawValue = Value::Unsigned(1).into();
+            value.flags = Flags::from_bits_truncate(0x4000_0000);
+            let (ptr, len) = path("/a");
+            let res = vnodes_insert(context, ptr, len, value);
+            assert_eq!(status(res), Some(ErrorKind::UnknownTypeFlags));
+            let res = vnodes_call(context, ptr, len, value);
+            assert_eq!(status(res), Some(ErrorKind::UnknownTypeFlags));
+
+            let mut values = [vnodes_value_bool(true), value];
+            let array = vnodes_value_array(values.as_ptr(), 2);
+            assert_eq!(array.kind(), Some(ValueKind::Void));
+            vnodes_value_free(&mut values[1]);
+            assert_eq!(values[1].kind(), Some(ValueKind::Void));
+
+            let mut buf = [0; 10];
+            assert_eq!(vnodes_un_intern(Interned(!0), buf.as_mut_ptr()), 10);
 
-        // TODO
-        //context.insert(&[ident], Value::from_raw(value));
-        unimplemented!()
+            vnodes_context_free(context);
+        }
     }
 }
@@ -0,0 +1,218 @@
+use std::fmt::{Debug, Formatter, Result as FmtResult};
+use std::mem::forget;
+use std::ops::Deref;
+use std::process::abort;
+use std::slice::from_raw_parts;
+use std::str::from_utf8;
+use std::sync::atomic::{fence, AtomicUsize, Ordering};
+
+use raw::*;
+use {ErrorKind, Result};
+
+/// A reference counted, immutable byte buffer.
+///
+/// Cloning a `SharedBuffer` only increments a reference count,
+/// which makes it suitable for large assets stored in the tree.
+pub struct SharedBuffer {
+    inner: *mut RawSharedData,
+}
+
+impl SharedBuffer {
+    pub fn new<B>(bytes: B) -> Self
+    where
+        B: Into<Box<[u8]>>,
+    {
+        let buf = bytes.into();
+        let boxed = Box::new(SharedData {
+            _raw: RawSharedData {
+                action: raw_action_shared_data,
+                data: buf.as_ptr(),
+                len: buf.len(),
+            },
+            strong: AtomicUsize::new(1),
+            buf,
+        });
+
+        unsafe { SharedBuffer::from_raw(Box::into_raw(boxed) as *mut RawSharedData) }
+    }
+
+    /// Takes ownership of one reference count of `inner`.
+    ///
+    /// # Safety
+    ///
+    /// `inner` must point to a live buffer whose reference count includes this handle.
+    pub unsafe fn from_raw(inner: *mut RawSharedData) -> Self {
+        SharedBuffer { inner }
+    }
+
+    /// Gives up ownership of this handle without decrementing the reference count.
+    pub fn into_raw(this: Self) -> *mut RawSharedData {
+        let raw = this.inner;
+
+        forget(this);
+
+        raw
+    }
+
+    pub fn as_raw(&self) -> *mut RawSharedData {
+        self.inner
+    }
+
+    pub fn as_slice(&self) -> &[u8] {
+        unsafe {
+            let raw = &*self.inner;
+
+            from_raw_parts(raw.data, raw.len)
+        }
+    }
+
+    /// Returns the buffer as string slice, e.g. for the payload of a `SharedString`.
+    pub fn as_str(&self) -> Result<&str> {
+        from_utf8(self.as_slice()).map_err(|_| ErrorKind::Utf8Error.into())
+    }
+
+    /// Returns `true` if both handles point to the same buffer.
+    pub fn ptr_eq(&self, other: &Self) -> bool {
+        self.inner == other.inner
+    }
+}
+
+impl Clone for SharedBuffer {
+    fn clone(&self) -> Self {
+        unsafe {
+            ((*self.inner).action)(self.inner, Action::Clone.into_raw());
+
+            SharedBuffer::from_raw(self.inner)
+        }
+    }
+}
+
+impl Debug for SharedBuffer {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        f.debug_struct("SharedBuffer")
+            .field("len", &self.len())
+            .finish()
+    }
+}
+
+impl Deref for SharedBuffer {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        self.as_slice()
+    }
+}
+
+impl Drop for SharedBuffer {
+    fn drop(&mut self) {
+        unsafe {
+            ((*self.inner).action)(self.inner, Action::Drop.into_raw());
+        }
+    }
+}
+
+impl PartialEq for SharedBuffer {
+    fn eq(&self, other: &Self) -> bool {
+        self.as_slice() == other.as_slice()
+    }
+}
+
+impl From<&[u8]> for SharedBuffer {
+    fn from(bytes: &[u8]) -> Self {
+        SharedBuffer::new(bytes)
+    }
+}
+
+impl From<Box<[u8]>> for SharedBuffer {
+    fn from(bytes: Box<[u8]>) -> Self {
+        SharedBuffer::new(bytes)
+    }
+}
+
+impl From<Vec<u8>> for SharedBuffer {
+    fn from(bytes: Vec<u8>) -> Self {
+        SharedBuffer::new(bytes)
+    }
+}
+
+impl From<String> for SharedBuffer {
+    fn from(s: String) -> Self {
+        SharedBuffer::new(s.into_bytes())
+    }
+}
+
+unsafe impl Send for SharedBuffer {}
+unsafe impl Sync for SharedBuffer {}
+
+#[repr(C)]
+struct SharedData {
+    _raw: RawSharedData,
+    strong: AtomicUsize,
+    buf: Box<[u8]>,
+}
+
+unsafe extern "C" fn raw_action_shared_data(data: *mut RawSharedData, action: RawAction) {
+    let this = &*(data as *const SharedData);
+
+    match Action::from_raw(action) {
+        Some(Action::Clone) => {
+            let old = this.strong.fetch_add(1, Ordering::Relaxed);
+
+            // Refcount is way too high, we got serious memory leaks.
+            if old > (!0 >> 1) {
+                abort();
+            }
+        }
+        Some(Action::Drop) => {
+            if this.strong.fetch_sub(1, Ordering::Release) != 1 {
+                return;
+            }
+
+            // Synchronize with all previous decrements before freeing the buffer
+            fence(Ordering::Acquire);
+
+            drop(Box::from_raw(data as *mut SharedData));
+        }
+        _ => error!("invalid action for shared buffer: {:#x}", action),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use {MapNode, Value, Vnodes};
+
+    #[test]
+    fn check_clone_is_shallow() {
+        let buf = SharedBuffer::from(vec![7; 4096]);
+        let other = buf.clone();
+
+        assert!(buf.ptr_eq(&other));
+        assert_eq!(buf.as_ptr(), other.as_ptr());
+        assert_eq!(&other[..3], &[7, 7, 7]);
+    }
+
+    #[test]
+    fn check_get_without_copy() {
+        let nodes = Vnodes::new();
+        let buf = SharedBuffer::from(vec![42; 1 << 20]);
+
+        nodes.insert("/meshes", MapNode::new_node()).unwrap();
+        nodes.insert("/meshes/cube", buf.clone()).unwrap();
+        nodes
+            .insert("/meshes/name", Value::SharedString(SharedBuffer::from("cube".to_owned())))
+            .unwrap();
+
+        let first: SharedBuffer = nodes.get("/meshes/cube").unwrap();
+        let second: SharedBuffer = nodes.get("/meshes/cube").unwrap();
+        assert!(first.ptr_eq(&buf));
+        assert!(second.ptr_eq(&buf));
+
+        assert_eq!(nodes.get("/meshes/name"), Ok("cube".to_owned()));
+
+        let name: SharedBuffer = nodes.get("/meshes/name").unwrap();
+        assert_eq!(name.as_str(), Ok("cube"));
+        let invalid = SharedBuffer::from(vec![0xff]);
+        assert_eq!(invalid.as_str().map_err(|e| e.kind()), Err(ErrorKind::Utf8Error));
+    }
+}
@@ -40,11 +40,11 @@ pub fn is_fat<T: ?Sized>() -> bool {
     size_of::<&T>() != size_of::<usize>()
 }
 
-pub unsafe fn slice_from_raw<'a, T>(raw: *const T, len: u32) -> &'a [T] {
-    from_raw_parts(raw, len as usize)
+pub unsafe fn slice_from_raw<'a, T>(raw: *const T, len: usize) -> &'a [T] {
+    from_raw_parts(raw, len)
 }
 
-pub unsafe fn boxed_slice_from_raw<'a, T>(raw: *const T, len: u32) -> Box<[T]> {
+pub unsafe fn boxed_slice_from_raw<'a, T>(raw: *const T, len: usize) -> Box<[T]> {
     let slice = slice_from_raw(raw, len);
 
     Box::from_raw(slice as *const [T] as *mut [T])
@@ -0,0 +1,829 @@
+//! Nodes running WebAssembly modules in a sandbox.
+//!
+//! A module has to export its `memory` and an allocator
+//! `vnodes_alloc(len: i32) -> i32`. Every other exported function with the
+//! signature `(i32, i32) -> i64` becomes a callable child: it's passed the
+//! pointer and length of its encoded arguments (an array) and returns the
+//! pointer and length of its encoded result as `ptr << 32 | len`.
+//!
+//! The module can import these functions from `vnodes`, with paths passed
+//! as pointer and length of a UTF-8 string:
+//!
+//! * `get(path, path_len) -> i64` returns an encoded value like above, or
+//!   `code << 32 | 0xFFFF_FFFF` for an error, since lengths never exceed
+//!   `i32::MAX`
+//! * `set(path, path_len, value, value_len) -> i32` returns zero or the
+//!   error code
+//!
+//! Values are encoded as a tag byte followed by the little endian payload:
+//!
+//! | tag | value    | payload                                       |
+//! |-----|----------|-----------------------------------------------|
+//! | 0   | void     |                                               |
+//! | 1   | bool     | `u8`                                          |
+//! | 2   | unsigned | `u64`                                         |
+//! | 3   | signed   | `i64`                                         |
+//! | 4   | float    | `f64`                                         |
+//! | 5   | string   | `u32` length, UTF-8 bytes                     |
+//! | 6   | blob     | `u32` length, bytes                           |
+//! | 7   | interned | `u64`                                         |
+//! | 8   | array    | `u32` count, values                           |
+//! | 9   | map      | `u32` count, entries of `u64` key and a value |
+//!
+//! Arrays and maps can be nested up to 64 levels deep. Nodes can't be
+//! passed to a module.
+
+use std::cell::RefCell;
+use std::fs;
+use std::path::Path;
+use std::ptr::null;
+use std::str::from_utf8;
+use std::sync::Arc;
+
+use parking_lot::ReentrantMutex;
+use wasmi::core::ValType;
+use wasmi::{
+    AsContext, AsContextMut, Caller, Config, Engine, ExternType, Linker, Memory, Module, Store,
+    StoreLimits, StoreLimitsBuilder, TypedFunc,
+};
+
+use intern::is_internable;
+use {
+    Error, ErrorKind, Interned, InternedPathBuf, Node, NodeHandle, NodeMeta, Result, Value, Vnodes,
+};
+
+type Callable = TypedFunc<(i32, i32), i64>;
+
+/// A node running a WebAssembly module, see the module documentation for
+/// the interface a module has to implement.
+///
+/// Each call may consume up to a fixed amount of fuel, which bounds the
+/// number of instructions it executes; calls which run out of fuel or trap
+/// fail with `ErrorKind::Panicked`. Memories and tables can't grow beyond
+/// the `WasmLimits` either.
+pub struct WasmNode {
+    sandbox: Arc<Sandbox>,
+    functions: Vec<(Interned, Callable)>,
+}
+
+/// The resources a `WasmNode` may use.
+#[derive(Clone, Copy, Debug)]
+pub struct WasmLimits {
+    /// The fuel each call may consume.
+    pub fuel: u64,
+    /// The maximum size of the memory in bytes.
+    pub memory: usize,
+    /// The maximum number of elements of a table.
+    pub table_elements: u32,
+}
+
+impl WasmLimits {
+    /// Limits calls to `fuel`, with 16 MiB of memory and tables of up to
+    /// 10000 elements.
+    pub fn new(fuel: u64) -> Self {
+        WasmLimits {
+            fuel,
+            memory: 16 << 20,
+            table_elements: 10_000,
+        }
+    }
+}
+
+impl WasmNode {
+    /// Loads the module at `path`.
+    pub fn load<P>(path: P, limits: WasmLimits) -> Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let wasm = fs::read(path)
+            .map_err(|e| Error::new(ErrorKind::LibraryLoad).with_message(e.to_string()))?;
+
+        WasmNode::new(&wasm, limits)
+    }
+
+    pub fn load_node<P>(path: P, limits: WasmLimits) -> Result<NodeHandle>
+    where
+        P: AsRef<Path>,
+    {
+        WasmNode::load(path, limits).map(NodeHandle::new)
+    }
+
+    /// Instantiates the binary module `wasm`.
+    pub fn new(wasm: &[u8], limits: WasmLimits) -> Result<Self> {
+        let mut config = Config::default();
+        config.consume_fuel(true);
+        let engine = Engine::new(&config);
+        let module = Module::new(&engine, wasm).map_err(load_error)?;
+
+        let mut linker = Linker::new(&engine);
+        linker
+            .func_wrap("vnodes", "get", host_get)
+            .and_then(|linker| linker.func_wrap("vnodes", "set", host_set))
+            .map_err(load_error)?;
+
+        let host = Host {
+            context: null(),
+            exports: None,
+            limits: StoreLimitsBuilder::new()
+                .memory_size(limits.memory)
+                .table_elements(limits.table_elements)
+                .instances(1)
+                .memories(1)
+                .tables(4)
+                .build(),
+        };
+        let mut store = Store::new(&engine, host);
+        store.limiter(|host| &mut host.limits);
+        // Start functions run without a context and may only use up the fuel of a call
+        store.set_fuel(limits.fuel).map_err(load_error)?;
+        let instance = linker
+            .instantiate(&mut store, &module)
+            .and_then(|instance| instance.start(&mut store))
+            .map_err(load_error)?;
+
+        let memory = instance
+            .get_memory(&store, "memory")
+            .ok_or_else(|| missing_export("memory"))?;
+        let alloc = instance
+            .get_typed_func::<i32, i32>(&store, "vnodes_alloc")
+            .map_err(|_| missing_export("vnodes_alloc"))?;
+        store.data_mut().exports = Some((memory, alloc));
+
+        let functions = module
+            .exports()
+            .filter(|export| match *export.ty() {
+                ExternType::Func(ref ty) => {
+                    ty.params() == [ValType::I32, ValType::I32] && ty.results() == [ValType::I64]
+                }
+                _ => false,
+            })
+            .filter(|export| is_internable(export.name()))
+            .map(|export| {
+                let function = instance
+                    .get_typed_func::<(i32, i32), i64>(&store, export.name())
+                    .map_err(load_error)?;
+
+                Ok((Interned::from(export.name()), function))
+            })
+            .collect::<Result<_>>()?;
+
+        Ok(WasmNode {
+            sandbox: Arc::new(Sandbox {
+                store: ReentrantMutex::new(RefCell::new(store)),
+                fuel: limits.fuel,
+            }),
+            functions,
+        })
+    }
+
+    pub fn new_node(wasm: &[u8], limits: WasmLimits) -> Result<NodeHandle> {
+        WasmNode::new(wasm, limits).map(NodeHandle::new)
+    }
+}
+
+impl Node for WasmNode {
+    fn get(&self, _: &Vnodes, ident: Interned) -> Result<Value<'_>> {
+        let &(_, function) = self
+            .functions
+            .iter()
+            .find(|&&(name, _)| name == ident)
+            .ok_or(ErrorKind::NoSuchEntry)?;
+
+        Ok(Value::Node(NodeHandle::new(WasmFunction {
+            sandbox: self.sandbox.clone(),
+            function,
+        })))
+    }
+
+    fn list(&self, _: &Vnodes) -> Result<Vec<Interned>> {
+        Ok(self.functions.iter().map(|&(name, _)| name).collect())
+    }
+
+    fn meta(&self, _: &Vnodes) -> NodeMeta {
+        NodeMeta {
+            list: true,
+            read_only: true,
+            children: Some(self.functions.len() as u64),
+            ..NodeMeta::new::<Self>()
+        }
+    }
+}
+
+/// An exported function of a `WasmNode`.
+struct WasmFunction {
+    sandbox: Arc<Sandbox>,
+    function: Callable,
+}
+
+impl Node for WasmFunction {
+    fn call(&self, context: &Vnodes, args: &[Value]) -> Result<Value<'_>> {
+        let mut args_buf = Vec::new();
+        encode_array(args, &mut args_buf)?;
+
+        self.sandbox.enter(context, |store| {
+            let (ptr, len) = write(&mut *store, &args_buf)?;
+            let packed = self.function.call(&mut *store, (ptr, len))?;
+            let bytes = read(&*store, (packed >> 32) as i32, packed as i32)?;
+
+            decode(&mut &*bytes).map_err(host_error)
+        })
+    }
+
+    fn get(&self, _: &Vnodes, _: Interned) -> Result<Value<'_>> {
+        Err(ErrorKind::NoSuchEntry.into())
+    }
+
+    fn meta(&self, _: &Vnodes) -> NodeMeta {
+        NodeMeta {
+            call: true,
+            ..NodeMeta::new::<Self>()
+        }
+    }
+}
+
+struct Sandbox {
+    // Locked for the whole call, so other threads wait for it to finish;
+    // the thread running it fails to borrow the store instead of deadlocking
+    store: ReentrantMutex<RefCell<Store<Host>>>,
+    fuel: u64,
+}
+
+impl Sandbox {
+    /// Runs `f` with a full tank and the host functions using `context`.
+    fn enter<F, R>(&self, context: &Vnodes, f: F) -> Result<R>
+    where
+        F: FnOnce(&mut Store<Host>) -> WasmResult<R>,
+    {
+        let store = self.store.lock();
+        let mut store = store
+            .try_borrow_mut()
+            .map_err(|_| trap("module entered recursively"))?;
+        store.set_fuel(self.fuel).map_err(trap)?;
+
+        store.data_mut().context = context;
+        let res = f(&mut store);
+        store.data_mut().context = null();
+
+        res.map_err(trap)
+    }
+}
+
+type WasmResult<T> = ::std::result::Result<T, wasmi::Error>;
+
+/// The data of a store.
+struct Host {
+    // Only valid while a call is running
+    context: *const Vnodes,
+    // Not available while the module is instantiated
+    exports: Option<(Memory, TypedFunc<i32, i32>)>,
+    limits: StoreLimits,
+}
+
+// The context is only used by the thread holding the lock of the store
+unsafe impl Send for Host {}
+
+impl Host {
+    fn context<'a>(&self) -> WasmResult<&'a Vnodes> {
+        match self.context.is_null() {
+            true => Err(wasmi::Error::new(
+                "vnodes can't be used while instantiating",
+            )),
+            false => Ok(unsafe { &*self.context }),
+        }
+    }
+
+    fn exports(&self) -> WasmResult<(Memory, TypedFunc<i32, i32>)> {
+        self.exports
+            .ok_or_else(|| wasmi::Error::new("vnodes can't be used while instantiating"))
+    }
+}
+
+fn host_get(mut caller: Caller<'_, Host>, path: i32, path_len: i32) -> WasmResult<i64> {
+    let path = read_path(&caller, path, path_len)?;
+
+    let mut value_buf = Vec::new();
+    match caller
+        .data()
+        .context()?
+        .get::<_, Value>(path)
+        .and_then(|value| encode(&value, &mut value_buf))
+    {
+        Ok(()) => {
+            let (ptr, len) = write(&mut caller, &value_buf)?;
+
+            Ok(pack(ptr, len))
+        }
+        Err(e) => Ok(((e.kind().code() as u64) << 32 | u32::MAX as u64) as i64),
+    }
+}
+
+fn host_set(
+    caller: Caller<'_, Host>,
+    path: i32,
+    path_len: i32,
+    value: i32,
+    value_len: i32,
+) -> WasmResult<i32> {
+    let path = read_path(&caller, path, path_len)?;
+    let bytes = read(&caller, value, value_len)?;
+    let value = decode(&mut &*bytes).map_err(host_error)?;
+
+    match caller.data().context()?.insert(path, value) {
+        Ok(()) => Ok(0),
+        Err(e) => Ok(e.kind().code() as i32),
+    }
+}
+
+fn read<C>(ctx: C, ptr: i32, len: i32) -> WasmResult<Vec<u8>>
+where
+    C: AsContext<Data = Host>,
+{
+    let (memory, _) = ctx.as_context().data().exports()?;
+    let start = ptr as u32 as usize;
+    let end = start.saturating_add(len as u32 as usize);
+
+    // Checked before allocating, the length is controlled by the module
+    match memory.data(&ctx).get(start..end) {
+        Some(bytes) => Ok(bytes.to_vec()),
+        None => Err(host_error("out of bounds memory access")),
+    }
+}
+
+fn read_path<C>(ctx: C, ptr: i32, len: i32) -> WasmResult<InternedPathBuf>
+where
+    C: AsContext<Data = Host>,
+{
+    let bytes = read(ctx, ptr, len)?;
+    let path = from_utf8(&bytes).map_err(host_error)?;
+
+    match path.split('/').all(is_internable) {
+        true => Ok(InternedPathBuf::from(path)),
+        false => Err(wasmi::Error::new(format!("invalid path `{}`", path))),
+    }
+}
+
+/// Packs a pointer and length as `ptr << 32 | len`, without sign extension.
+fn pack(ptr: i32, len: i32) -> i64 {
+    ((ptr as u32 as u64) << 32 | len as u32 as u64) as i64
+}
+
+/// Copies `bytes` into memory allocated by the module.
+fn write<C>(mut ctx: C, bytes: &[u8]) -> WasmResult<(i32, i32)>
+where
+    C: AsContextMut<Data = Host>,
+{
+    let (memory, alloc) = ctx.as_context().data().exports()?;
+    if bytes.len() > i32::MAX as usize {
+        return Err(host_error("value too large for the module"));
+    }
+    let len = bytes.len() as i32;
+    let ptr = alloc.call(&mut ctx, len)?;
+    memory
+        .write(&mut ctx, ptr as u32 as usize, bytes)
+        .map_err(host_error)?;
+
+    Ok((ptr, len))
+}
+
+const VOID: u8 = 0;
+const BOOL: u8 = 1;
+const UNSIGNED: u8 = 2;
+const SIGNED: u8 = 3;
+const FLOAT: u8 = 4;
+const STRING: u8 = 5;
+const BLOB: u8 = 6;
+const INTERNED: u8 = 7;
+const ARRAY: u8 = 8;
+const MAP: u8 = 9;
+
+/// The maximum nesting of arrays and maps passed by a module.
+const MAX_DEPTH: usize = 64;
+
+fn encode(value: &Value, out: &mut Vec<u8>) -> Result<()> {
+    fn bytes(tag: u8, bytes: &[u8], out: &mut Vec<u8>) -> Result<()> {
+        out.push(tag);
+        encode_len(bytes.len(), out)?;
+        out.extend_from_slice(bytes);
+
+        Ok(())
+    }
+
+    match value.clone().make_owned() {
+        Value::Void => out.push(VOID),
+        Value::Bool(b) => out.extend_from_slice(&[BOOL, b as u8]),
+        Value::Unsigned(u) => {
+            out.push(UNSIGNED);
+            out.extend_from_slice(&u.to_le_bytes());
+        }
+        Value::Signed(i) => {
+            out.push(SIGNED);
+            out.extend_from_slice(&i.to_le_bytes());
+        }
+        Value::Float(f) => {
+            out.push(FLOAT);
+            out.extend_from_slice(&f.to_bits().to_le_bytes());
+        }
+        Value::String(string) => bytes(STRING, &string, out)?,
+        Value::SharedString(string) => bytes(STRING, string.as_slice(), out)?,
+        Value::Blob(blob) => bytes(BLOB, &blob, out)?,
+        Value::SharedBlob(blob) => bytes(BLOB, blob.as_slice(), out)?,
+        Value::Interned(interned) => {
+            out.push(INTERNED);
+            out.extend_from_slice(&interned.into_inner().to_le_bytes());
+        }
+        Value::ValueArray(array) => {
+            out.push(ARRAY);
+            encode_len(array.len(), out)?;
+            for value in array {
+                encode(&value, out)?;
+            }
+        }
+        Value::Map(map) => {
+            out.push(MAP);
+            encode_len(map.len(), out)?;
+            for (key, value) in map {
+                out.extend_from_slice(&key.into_inner().to_le_bytes());
+                encode(&value, out)?;
+            }
+        }
+        value => {
+            return Err(
+                Error::new(ErrorKind::InvalidArgumentTypes).with_message(format!(
+                    "{} values can't be passed to WebAssembly",
+                    value.kind()
+                )),
+            )
+        }
+    }
+
+    Ok(())
+}
+
+fn encode_array(values: &[Value], out: &mut Vec<u8>) -> Result<()> {
+    out.push(ARRAY);
+    encode_len(values.len(), out)?;
+    values.iter().try_for_each(|value| encode(value, out))
+}
+
+fn encode_len(len: usize, out: &mut Vec<u8>) -> Result<()> {
+    if len > u32::MAX as usize {
+        return Err(ErrorKind::OutOfRange.into());
+    }
+    out.extend_from_slice(&(len as u32).to_le_bytes());
+
+    Ok(())
+}
+
+fn decode(input: &mut &[u8]) -> Result<Value<'static>> {
+    decode_nested(input, 0)
+}
+
+fn decode_nested(input: &mut &[u8], depth: usize) -> Result<Value<'static>> {
+    fn take<'a>(input: &mut &'a [u8], len: usize) -> Result<&'a [u8]> {
+        if input.len() < len {
+            return Err(Error::new(ErrorKind::OutOfRange).with_message("truncated value"));
+        }
+        let (bytes, rest) = input.split_at(len);
+        *input = rest;
+
+        Ok(bytes)
+    }
+
+    fn take_u32(input: &mut &[u8]) -> Result<u32> {
+        let mut bytes = [0; 4];
+        bytes.copy_from_slice(take(input, 4)?);
+
+        Ok(u32::from_le_bytes(bytes))
+    }
+
+    fn take_u64(input: &mut &[u8]) -> Result<u64> {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(take(input, 8)?);
+
+        Ok(u64::from_le_bytes(bytes))
+    }
+
+    let tag = take(input, 1)?[0];
+    if (tag == ARRAY || tag == MAP) && depth == MAX_DEPTH {
+        return Err(Error::new(ErrorKind::OutOfRange).with_message("value nested too deeply"));
+    }
+
+    Ok(match tag {
+        VOID => Value::Void,
+        BOOL => Value::Bool(take(input, 1)?[0] != 0),
+        UNSIGNED => Value::Unsigned(take_u64(input)?),
+        SIGNED => Value::Signed(take_u64(input)? as i64),
+        FLOAT => Value::Float(f64::from_bits(take_u64(input)?)),
+        STRING => {
+            let len = take_u32(input)? as usize;
+            let string = take(input, len)?;
+            from_utf8(string).map_err(|_| ErrorKind::Utf8Error)?;
+
+            Value::String(string.into())
+        }
+        BLOB => {
+            let len = take_u32(input)? as usize;
+
+            Value::Blob(take(input, len)?.into())
+        }
+        INTERNED => Value::Interned(Interned(take_u64(input)?)),
+        ARRAY => {
+            let count = take_u32(input)?;
+
+            Value::ValueArray(
+                (0..count)
+                    .map(|_| decode_nested(input, depth + 1))
+                    .collect::<Result<_>>()?,
+            )
+        }
+        MAP => {
+            let count = take_u32(input)?;
+
+            Value::Map(
+                (0..count)
+                    .map(|_| Ok((Interned(take_u64(input)?), decode_nested(input, depth + 1)?)))
+                    .collect::<Result<_>>()?,
+            )
+        }
+        tag => {
+            return Err(Error::new(ErrorKind::UnknownTypeFlags)
+                .with_message(format!("unknown tag {}", tag)))
+        }
+    })
+}
+
+fn load_error<E: ToString>(e: E) -> Error {
+    Error::new(ErrorKind::LibraryLoad).with_message(e.to_string())
+}
+
+fn missing_export(name: &str) -> Error {
+    Error::new(ErrorKind::LibraryLoad).with_message(format!("missing export `{}`", name))
+}
+
+fn host_error<E: ToString>(e: E) -> wasmi::Error {
+    wasmi::Error::new(e.to_string())
+}
+
+fn trap<E: ToString>(e: E) -> Error {
+    Error::new(ErrorKind::Panicked).with_message(e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::process;
+    use wat;
+    use {InternedMap, MapNode, ValueConv};
+
+    const FUEL: u64 = 100_000;
+
+    const MODULE: &str = r#"
+    (module
+      (import "vnodes" "get" (func $get (param i32 i32) (result i64)))
+      (import "vnodes" "set" (func $set (param i32 i32 i32 i32) (result i32)))
+      (memory (export "memory") 1)
+      (global $next (mut i32) (i32.const 1024))
+      (data (i32.const 0) "/input")
+      (data (i32.const 16) "/output")
+      (data (i32.const 32) "/hook/x")
+
+      (func $alloc (export "vnodes_alloc") (param $len i32) (result i32)
+        (local $ptr i32)
+        (local.set $ptr (global.get $next))
+        (global.set $next (i32.add (global.get $next) (local.get $len)))
+        (local.get $ptr))
+
+      (func $pack (param $ptr i32) (param $len i32) (result i64)
+        (i64.or
+          (i64.shl (i64.extend_i32_u (local.get $ptr)) (i64.const 32))
+          (i64.extend_i32_u (local.get $len))))
+
+      (func $unsigned (param $value i64) (result i64)
+        (local $ptr i32)
+        (local.set $ptr (call $alloc (i32.const 9)))
+        (i32.store8 (local.get $ptr) (i32.const 2))
+        (i64.store offset=1 align=1 (local.get $ptr) (local.get $value))
+        (call $pack (local.get $ptr) (i32.const 9)))
+
+      ;; Expects two unsigned arguments, which start after the tag and
+      ;; count of the array
+      (func (export "add") (param $args i32) (param $len i32) (result i64)
+        (call $unsigned
+          (i64.add
+            (i64.load offset=6 align=1 (local.get $args))
+            (i64.load offset=15 align=1 (local.get $args)))))
+
+      (func (export "echo") (param $args i32) (param $len i32) (result i64)
+        (call $pack (local.get $args) (local.get $len)))
+
+      (func (export "double") (param i32 i32) (result i64)
+        (local $input i64)
+        (local $output i64)
+        (local.set $input (call $get (i32.const 0) (i32.const 6)))
+        (if (i32.eq (i32.wrap_i64 (local.get $input)) (i32.const -1)) (then unreachable))
+        (local.set $output
+          (call $unsigned
+            (i64.mul
+              (i64.load offset=1 align=1
+                (i32.wrap_i64 (i64.shr_u (local.get $input) (i64.const 32))))
+              (i64.const 2))))
+        (drop
+          (call $set
+            (i32.const 16) (i32.const 7)
+            (i32.wrap_i64 (i64.shr_u (local.get $output) (i64.const 32))) (i32.const 9)))
+        (local.get $output))
+
+      ;; Returns the value of `/hook/x` or the error code as unsigned
+      (func (export "hook") (param i32 i32) (result i64)
+        (local $value i64)
+        (local.set $value (call $get (i32.const 32) (i32.const 7)))
+        (if (result i64) (i32.eq (i32.wrap_i64 (local.get $value)) (i32.const -1))
+          (then (call $unsigned (i64.shr_u (local.get $value) (i64.const 32))))
+          (else (local.get $value))))
+
+      ;; Returns the previous number of pages, or -1 if growing failed
+      (func (export "grow") (param $args i32) (param $len i32) (result i64)
+        (local $ptr i32)
+        (local.set $ptr (call $alloc (i32.const 9)))
+        (i32.store8 (local.get $ptr) (i32.const 3))
+        (i64.store offset=1 align=1 (local.get $ptr)
+          (i64.extend_i32_s
+            (memory.grow (i32.wrap_i64 (i64.load offset=6 align=1 (local.get $args))))))
+        (call $pack (local.get $ptr) (i32.const 9)))
+
+      (func (export "huge") (param i32 i32) (result i64)
+        (i64.const -1))
+
+      (func (export "spin") (param i32 i32) (result i64)
+        (loop $forever (br $forever))
+        (i64.const 0))
+
+      (func (export "fail") (param i32 i32) (result i64)
+        unreachable)
+
+      (func (export "ignored") (param i32) (result i32)
+        (local.get 0)))
+    "#;
+
+    fn load(nodes: &Vnodes) {
+        let wasm = wat::parse_str(MODULE).unwrap();
+        let node = WasmNode::new_node(&wasm, WasmLimits::new(FUEL)).unwrap();
+        nodes.insert("/wasm", node).unwrap();
+    }
+
+    #[test]
+    fn check_functions() {
+        let nodes = Vnodes::new();
+        load(&nodes);
+
+        let mut names = nodes.list("/wasm").unwrap();
+        names.sort_by_key(|i| i.into_inner());
+        let mut expected: Vec<_> = [
+            "add", "double", "echo", "fail", "grow", "hook", "huge", "spin",
+        ]
+            .iter()
+            .map(|&name| Interned::from(name))
+            .collect();
+        expected.sort_by_key(|i| i.into_inner());
+        assert_eq!(names, expected);
+
+        assert_eq!(nodes.call("/wasm/add", (1u64, 2u64)), Ok(3u64));
+        assert_eq!(nodes.call("/wasm/add", (40u64, 2u64)), Ok(42u64));
+
+        let meta = nodes.meta("/wasm/add").unwrap();
+        assert!(meta.call);
+        assert!(!meta.list && !meta.set && !meta.remove);
+        let meta = nodes.meta("/wasm").unwrap();
+        assert!(meta.list && meta.read_only);
+        assert_eq!(meta.children, Some(8));
+        assert!(!nodes.exists("/wasm/ignored"));
+        assert!(!nodes.exists("/wasm/vnodes_alloc"));
+    }
+
+    #[test]
+    fn check_encoding() {
+        let nodes = Vnodes::new();
+        load(&nodes);
+
+        let args = ((), true, 7u64, -7i64, "text");
+        let (void, b, u, i, s): ((), bool, u64, i64, String) =
+            nodes.call("/wasm/echo", args).unwrap();
+        assert_eq!(void, ());
+        assert!(b);
+        assert_eq!((u, i), (7, -7));
+        assert_eq!(s, "text");
+
+        let mut map = InternedMap::new();
+        map.insert(Interned::from("x"), vec![1.5f64, -2.0]);
+        let args = (Interned::from("name"), map.clone());
+        assert_eq!(nodes.call("/wasm/echo", args.clone()), Ok(args));
+
+        let blob: Box<[u8]> = vec![0u8, 1, 2].into_boxed_slice();
+        let args = Value::ValueArray(vec![blob.clone().into_value()].into_iter().collect());
+        let echoed: Vec<Box<[u8]>> = nodes.call("/wasm/echo", args).unwrap();
+        assert_eq!(echoed, vec![blob]);
+
+        let error = nodes
+            .call::<_, _, Value>("/wasm/echo", MapNode::new_node())
+            .unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidArgumentTypes);
+    }
+
+    #[test]
+    fn check_context() {
+        let nodes = Vnodes::new();
+        load(&nodes);
+
+        let error = nodes.call::<_, _, u64>("/wasm/double", ()).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::Panicked);
+
+        nodes.insert("/input", 21u64).unwrap();
+        assert_eq!(nodes.call("/wasm/double", ()), Ok(42u64));
+        assert_eq!(nodes.get("/output"), Ok(42u64));
+    }
+
+    #[test]
+    fn check_fuel() {
+        let nodes = Vnodes::new();
+        load(&nodes);
+
+        let error = nodes.call::<_, _, Value>("/wasm/spin", ()).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::Panicked);
+        assert!(error.message().unwrap().contains("fuel"));
+
+        let error = nodes.call::<_, _, Value>("/wasm/fail", ()).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::Panicked);
+
+        // Every call gets a full tank
+        assert_eq!(nodes.call("/wasm/add", (1u64, 2u64)), Ok(3u64));
+    }
+
+    #[test]
+    fn check_limits() {
+        let nodes = Vnodes::new();
+        load(&nodes);
+
+        let error = nodes.call::<_, _, Value>("/wasm/huge", ()).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::Panicked);
+        assert!(error.message().unwrap().contains("out of bounds"));
+
+        let nested = |depth| {
+            let mut bytes = [ARRAY, 1, 0, 0, 0].repeat(depth);
+            bytes.push(VOID);
+            bytes
+        };
+        assert!(decode(&mut &*nested(MAX_DEPTH)).is_ok());
+        let error = decode(&mut &*nested(MAX_DEPTH + 1)).err().unwrap();
+        assert_eq!(error.kind(), ErrorKind::OutOfRange);
+        let error = decode(&mut &*nested(100_000)).err().unwrap();
+        assert_eq!(error.kind(), ErrorKind::OutOfRange);
+
+        // One page is in use and 16 MiB are 256 pages
+        assert_eq!(nodes.call("/wasm/grow", 65_536u64), Ok(-1i64));
+        assert_eq!(nodes.call("/wasm/grow", 256u64), Ok(-1i64));
+        assert_eq!(nodes.call("/wasm/grow", 1u64), Ok(1i64));
+
+        assert_eq!(pack(-1, -1), -1);
+        assert_eq!(pack(i32::MIN, 1), 1 << 63 | 1);
+    }
+
+    struct Hook;
+
+    impl Node for Hook {
+        fn get(&self, context: &Vnodes, _: Interned) -> Result<Value<'_>> {
+            context.call("/wasm/add", (1u64, 2u64))
+        }
+    }
+
+    #[test]
+    fn check_reentry() {
+        let nodes = Vnodes::new();
+        load(&nodes);
+        nodes.insert("/hook", NodeHandle::new(Hook)).unwrap();
+
+        assert_eq!(nodes.get("/hook/x"), Ok(3u64));
+        let code = ErrorKind::Panicked.code() as u64;
+        assert_eq!(nodes.call("/wasm/hook", ()), Ok(code));
+    }
+
+    #[test]
+    fn check_load() {
+        let path = env::temp_dir().join(format!("vnodes-{}.wasm", process::id()));
+        fs::write(&path, wat::parse_str(MODULE).unwrap()).unwrap();
+        let node = WasmNode::load_node(&path, WasmLimits::new(FUEL));
+        fs::remove_file(&path).unwrap();
+
+        let nodes = Vnodes::new();
+        nodes.insert("/wasm", node.unwrap()).unwrap();
+        assert_eq!(nodes.call("/wasm/add", (1u64, 2u64)), Ok(3u64));
+
+        let error = WasmNode::load(&path, WasmLimits::new(FUEL)).err().unwrap();
+        assert_eq!(error.kind(), ErrorKind::LibraryLoad);
+        let error = WasmNode::new(b"not wasm", WasmLimits::new(FUEL)).err().unwrap();
+        assert_eq!(error.kind(), ErrorKind::LibraryLoad);
+        let no_memory = wat::parse_str("(module)").unwrap();
+        let error = WasmNode::new(&no_memory, WasmLimits::new(FUEL)).err().unwrap();
+        assert_eq!(error.message(), Some("missing export `memory`"));
+    }
+}
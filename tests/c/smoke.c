@@ -0,0 +1,94 @@
+/* Exercises the C interface; built and run by `tests/c_api.rs`. */
+
+#include <stdio.h>
+#include <string.h>
+
+#include "vnodes.h"
+
+#define CHECK(cond)                                                          \
+    do {                                                                     \
+        if (!(cond)) {                                                       \
+            fprintf(stderr, "%s:%d: check failed: %s\n", __FILE__, __LINE__, \
+                    #cond);                                                  \
+            return 1;                                                        \
+        }                                                                    \
+    } while (0)
+
+#define PATH(s) (const uint8_t *)(s), strlen(s)
+
+int main(void) {
+    RawContextPtr ctx = vnodes_context_new();
+    RawResult res;
+
+    /* Plain values */
+    res = vnodes_insert(ctx, PATH("/answer"), vnodes_value_unsigned(42));
+    CHECK(res.status == 0);
+    res = vnodes_get(ctx, PATH("/answer"));
+    CHECK(res.status == 0);
+    CHECK(res.value.flags.bits == Flags_INTEGER.bits);
+    CHECK(res.value.value.unsigned_ == 42);
+    vnodes_result_free(&res);
+
+    /* Owned strings */
+    res = vnodes_insert(ctx, PATH("/name"), vnodes_value_string(PATH("vnodes")));
+    CHECK(res.status == 0);
+    res = vnodes_get(ctx, PATH("/name"));
+    CHECK(res.status == 0);
+    CHECK(res.value.flags.bits == Flags_STRING_BOXED.bits);
+    CHECK(res.value.extra == 6);
+    CHECK(memcmp(res.value.value.string, "vnodes", 6) == 0);
+    vnodes_result_free(&res);
+    CHECK(res.status == 0 && res.value.flags.bits == Flags_VOID.bits);
+
+    /* Arrays take ownership of their elements */
+    RawValue elements[2] = {
+        vnodes_value_signed(-1),
+        vnodes_value_blob((const uint8_t *)"\x00\x01", 2),
+    };
+    RawValue array = vnodes_value_array(elements, 2);
+    RawValue copy = vnodes_value_clone(&array);
+    vnodes_value_free(&array);
+    CHECK(array.flags.bits == Flags_VOID.bits);
+    res = vnodes_insert(ctx, PATH("/array"), copy);
+    CHECK(res.status == 0);
+    res = vnodes_get(ctx, PATH("/array"));
+    CHECK(res.status == 0);
+    CHECK(res.value.extra == 2);
+    CHECK(res.value.value.value_array[0].value.signed_ == -1);
+    CHECK(res.value.value.value_array[1].extra == 2);
+    vnodes_result_free(&res);
+
+    /* Interned names */
+    uint8_t buf[10];
+    Interned name = vnodes_intern(PATH("name"));
+    CHECK(vnodes_un_intern(name, buf) == 4);
+    CHECK(memcmp(buf, "name", 4) == 0);
+    res = vnodes_get(ctx, PATH("/name"));
+    CHECK(res.status == 0);
+    vnodes_result_free(&res);
+
+    /* Removing returns the old value */
+    res = vnodes_remove(ctx, PATH("/answer"));
+    CHECK(res.status == 0);
+    CHECK(res.value.value.unsigned_ == 42);
+    vnodes_result_free(&res);
+
+    /* Errors */
+    res = vnodes_get(ctx, PATH("/answer"));
+    CHECK(res.status == 0x4); /* NoSuchEntry */
+    vnodes_result_free(&res);
+    res = vnodes_call(ctx, PATH("/name"), vnodes_value_void());
+    CHECK(res.status == 0x8); /* WrongType */
+    vnodes_result_free(&res);
+    res = vnodes_list(ctx, PATH("/array"));
+    CHECK(res.status == 0x8); /* WrongType */
+    vnodes_result_free(&res);
+    res = vnodes_get(ctx, (const uint8_t *)"/\xff", 2);
+    CHECK(res.status == 0x7); /* Utf8Error */
+    vnodes_result_free(&res);
+
+    vnodes_context_free(ctx);
+
+    puts("ok");
+    return 0;
+}
@@ -0,0 +1,88 @@
+extern crate cbindgen;
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+fn manifest_dir() -> &'static Path {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+}
+
+/// The target directory of the static library built by `c_smoke_test`.
+///
+/// It's separate from the one of the tests, so its location doesn't depend
+/// on the profile or `CARGO_TARGET_DIR`.
+fn target_dir() -> PathBuf {
+    Path::new(env!("CARGO_TARGET_TMPDIR")).join("c_api")
+}
+
+fn generate_header() -> Vec<u8> {
+    let config = cbindgen::Config::from_file(manifest_dir().join("cbindgen.toml")).unwrap();
+    let bindings = cbindgen::Builder::new()
+        .with_crate(manifest_dir())
+        .with_config(config)
+        .generate()
+        .unwrap();
+
+    let mut header = Vec::new();
+    bindings.write(&mut header);
+
+    header
+}
+
+#[test]
+fn header_is_up_to_date() {
+    let path = manifest_dir().join("include/vnodes.h");
+    let header = generate_header();
+
+    if env::var_os("VNODES_BLESS").is_some() {
+        fs::write(&path, &header).unwrap();
+    } else {
+        let current = fs::read(&path).unwrap_or_default();
+        assert!(
+            current == header,
+            "`include/vnodes.h` is out of date; run `VNODES_BLESS=1 cargo test --test c_api`"
+        );
+    }
+}
+
+#[test]
+fn c_smoke_test() {
+    // `cargo test` doesn't build the static library, so ask for it explicitly
+    let target = target_dir();
+    let output = Command::new(env!("CARGO"))
+        .args(["build", "--lib", "--target-dir"])
+        .arg(&target)
+        .current_dir(manifest_dir())
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let exe = target.join("vnodes_smoke");
+    let status = Command::new(env::var("CC").unwrap_or_else(|_| "cc".to_owned()))
+        .arg("-std=c99")
+        .arg("-Wall")
+        .arg("-Werror")
+        .arg("-I")
+        .arg(manifest_dir().join("include"))
+        .arg(manifest_dir().join("tests/c/smoke.c"))
+        .arg(target.join("debug/libvnodes.a"))
+        .args(["-lpthread", "-ldl", "-lm", "-o"])
+        .arg(&exe)
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    let output = Command::new(&exe).output().unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(output.stdout, b"ok\n");
+}
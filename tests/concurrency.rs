@@ -0,0 +1,195 @@
+//! Stress tests for sharing node handles between threads.
+
+extern crate vnodes;
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Barrier};
+use std::thread;
+
+use vnodes::{ErrorKind, Interned, NodeHandle, Result, Value, Vnodes};
+
+const THREADS: usize = 8;
+const ITERATIONS: usize = 10_000;
+
+/// Records how often it has been dropped and whether it was still in use then.
+struct Tracked {
+    drops: Arc<AtomicUsize>,
+    uses: AtomicUsize,
+}
+
+impl vnodes::Node for Tracked {
+    fn call(&self, _: &Vnodes, _: &[Value]) -> Result<Value<'_>> {
+        Err(ErrorKind::ActionNotSupported.into())
+    }
+
+    fn get(&self, _: &Vnodes, _: Interned) -> Result<Value<'_>> {
+        self.uses.fetch_add(1, Ordering::Relaxed);
+
+        Ok(Value::Void)
+    }
+
+    fn set(&self, _: &Vnodes, _: Interned, _: Value<'static>) -> Result<()> {
+        Err(ErrorKind::ActionNotSupported.into())
+    }
+}
+
+impl Drop for Tracked {
+    fn drop(&mut self) {
+        // Every use has to be visible to the thread dropping the node
+        assert_eq!(self.uses.load(Ordering::Relaxed), THREADS * ITERATIONS);
+
+        self.drops.fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+fn tracked() -> (NodeHandle, Arc<AtomicUsize>) {
+    let drops = Arc::new(AtomicUsize::new(0));
+    let node = NodeHandle::new(Tracked {
+        drops: drops.clone(),
+        uses: AtomicUsize::new(0),
+    });
+
+    (node, drops)
+}
+
+#[test]
+fn clone_and_drop_across_threads() {
+    for _ in 0..10 {
+        let (node, drops) = tracked();
+        let barrier = Arc::new(Barrier::new(THREADS));
+
+        let threads: Vec<_> = (0..THREADS)
+            .map(|_| {
+                let node = node.clone();
+                let barrier = barrier.clone();
+
+                thread::spawn(move || {
+                    let context = Vnodes::new();
+                    barrier.wait();
+
+                    for _ in 0..ITERATIONS {
+                        let clone = node.clone();
+                        let _ = clone.get(&context, Interned::from("x"));
+                        drop(clone);
+                    }
+                })
+            })
+            .collect();
+
+        // The last handle may be dropped by any thread
+        drop(node);
+
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        assert_eq!(drops.load(Ordering::SeqCst), 1);
+    }
+}
+
+#[test]
+fn upgrade_races_with_drop() {
+    for _ in 0..10 {
+        let drops = Arc::new(AtomicUsize::new(0));
+        // Doesn't track uses, so it may be dropped at any point
+        let node = NodeHandle::new(Tracked {
+            drops: drops.clone(),
+            uses: AtomicUsize::new(THREADS * ITERATIONS),
+        });
+        let weak = node.downgrade().unwrap();
+        let barrier = Arc::new(Barrier::new(THREADS + 1));
+
+        let threads: Vec<_> = (0..THREADS)
+            .map(|_| {
+                let weak = weak.clone();
+                let barrier = barrier.clone();
+
+                thread::spawn(move || {
+                    barrier.wait();
+
+                    for _ in 0..ITERATIONS {
+                        drop(weak.upgrade());
+                    }
+                })
+            })
+            .collect();
+
+        barrier.wait();
+        drop(node);
+
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        assert_eq!(drops.load(Ordering::SeqCst), 1);
+        assert!(weak.upgrade().is_none());
+    }
+}
+
+#[test]
+fn weak_handles_across_threads() {
+    let (node, drops) = tracked();
+    let weak = node.downgrade().unwrap();
+    let barrier = Arc::new(Barrier::new(THREADS));
+
+    let threads: Vec<_> = (0..THREADS)
+        .map(|_| {
+            let weak = weak.clone();
+            let barrier = barrier.clone();
+
+            thread::spawn(move || {
+                let context = Vnodes::new();
+                barrier.wait();
+
+                for _ in 0..ITERATIONS {
+                    let strong = weak.upgrade().expect("node is kept alive by the main thread");
+                    let _ = strong.get(&context, Interned::from("x"));
+                    drop(weak.clone());
+                }
+            })
+        })
+        .collect();
+
+    for thread in threads {
+        thread.join().unwrap();
+    }
+
+    drop(node);
+    assert_eq!(drops.load(Ordering::SeqCst), 1);
+    assert!(weak.upgrade().is_none());
+}
+
+#[test]
+fn create_dirs_across_threads() {
+    for _ in 0..1000 {
+        let nodes = Arc::new(Vnodes::new());
+        let barrier = Arc::new(Barrier::new(THREADS));
+
+        let threads: Vec<_> = (0..THREADS)
+            .map(|i| {
+                let nodes = nodes.clone();
+                let barrier = barrier.clone();
+
+                thread::spawn(move || {
+                    barrier.wait();
+
+                    nodes.create_dirs("/a/b/x").unwrap();
+                    nodes
+                        .insert(&*format!("/a/b/x/t{}", i), i as u64)
+                        .unwrap();
+                    let value = nodes
+                        .get_or_insert_with(&*format!("/a/c{}", i % 2), || i as u64)
+                        .unwrap();
+                    assert!(value % 2 == (i % 2) as u64);
+                })
+            })
+            .collect();
+
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        // No thread replaced a node another one had already inserted into
+        assert_eq!(nodes.list("/a/b/x").unwrap().len(), THREADS);
+    }
+}
@@ -0,0 +1,357 @@
+//! Checks that every kind of `RawValue` releases its payload exactly once.
+
+extern crate vnodes;
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+
+use vnodes::raw::{vnodes_value_free, Flags, RawResult, RawValue};
+use vnodes::{Error, ErrorKind, Interned, InternedPathBuf, MapNode, NodeHandle, SharedBuffer,
+             Value, ValueArray, ValueConv, ValueMap, Vnodes};
+
+struct CountingAlloc;
+
+thread_local! {
+    static LIVE: Cell<isize> = const { Cell::new(0) };
+}
+
+fn track(diff: isize) {
+    let _ = LIVE.try_with(|live| live.set(live.get() + diff));
+}
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        track(1);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        track(-1);
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static ALLOC: CountingAlloc = CountingAlloc;
+
+fn live() -> isize {
+    LIVE.with(Cell::get)
+}
+
+/// Asserts that `f` frees everything it allocates (on this thread).
+fn check_no_leaks<F>(name: &str, f: F)
+where
+    F: FnOnce(),
+{
+    let before = live();
+    f();
+    let after = live();
+
+    assert_eq!(before, after, "{}: {} allocations leaked", name, after - before);
+}
+
+const ALL_FLAGS: &[Flags] = &[
+    Flags::BLOB,
+    Flags::BLOB_BOXED,
+    Flags::BLOB_SHARED,
+    Flags::BOOL,
+    Flags::FLOAT,
+    Flags::INTEGER,
+    Flags::INTEGER_SIGNED,
+    Flags::INTERNED,
+    Flags::INTERNED_PATH,
+    Flags::INTERNED_PATH_BUF,
+    Flags::MAP,
+    Flags::MAP_BOXED,
+    Flags::NODE,
+    Flags::NODE_BOXED,
+    Flags::STRING,
+    Flags::STRING_BOXED,
+    Flags::STRING_SHARED,
+    Flags::VALUE_ARRAY,
+    Flags::VALUE_ARRAY_BOXED,
+    Flags::VOID,
+];
+
+/// Data borrowed by the `*Ref` samples; created before counting starts.
+struct Backing {
+    node: NodeHandle,
+    path: InternedPathBuf,
+    array: ValueArray<'static>,
+    map: ValueMap<'static>,
+}
+
+impl Backing {
+    fn new() -> Self {
+        let array = vec![
+            Value::String(Box::new(*b"owned")),
+            Value::Node(MapNode::new_node()),
+        ];
+        let map = vec![
+            (Interned::from("a"), Value::Unsigned(1)),
+            (Interned::from("b"), Value::Blob(Box::new(*b"owned"))),
+        ];
+
+        Backing {
+            node: MapNode::new_node(),
+            path: InternedPathBuf::from("/some/path"),
+            array: array.into_iter().collect(),
+            map: map.into_iter().collect(),
+        }
+    }
+}
+
+/// One value for every flags combination.
+fn samples(backing: &Backing) -> Vec<Value<'_>> {
+    let nested_array: ValueArray = vec![
+        Value::String(Box::new(*b"nested")),
+        Value::SharedBlob(SharedBuffer::from(vec![1, 2, 3])),
+        Value::Node(MapNode::new_node()),
+        Value::ValueArray(vec![Value::Node(MapNode::new_node())].into_iter().collect()),
+    ].into_iter()
+        .collect();
+    let nested_map: ValueMap = vec![
+        (Interned::from("node"), Value::Node(MapNode::new_node())),
+        (Interned::from("array"), Value::ValueArray(nested_array.clone())),
+        (Interned::from("path"), Value::InternedPathBuf(backing.path.clone())),
+    ].into_iter()
+        .collect();
+
+    vec![
+        Value::BlobRef(b"blob"),
+        Value::Blob(Box::new(*b"blob")),
+        Value::SharedBlob(SharedBuffer::from(vec![0; 64])),
+        Value::Bool(true),
+        Value::Float(1.5),
+        Value::Unsigned(7),
+        Value::Signed(-7),
+        Value::Interned(Interned::from("ident")),
+        Value::InternedPathRef(backing.path.path()),
+        Value::InternedPathBuf(backing.path.clone()),
+        Value::MapRef(&backing.map),
+        Value::Map(nested_map),
+        Value::NodeRef(backing.node.handle_ref()),
+        Value::Node(MapNode::new_node()),
+        Value::StringRef(b"string"),
+        Value::String(Box::new(*b"string")),
+        Value::SharedString(SharedBuffer::from("shared".to_owned())),
+        Value::ValueArrayRef(&backing.array),
+        Value::ValueArray(nested_array),
+        Value::Void,
+    ]
+}
+
+#[test]
+fn samples_cover_all_flags() {
+    let backing = Backing::new();
+    let mut flags: Vec<Flags> = samples(&backing)
+        .into_iter()
+        .map(|value| {
+            let mut raw = RawValue::from(value);
+            let flags = raw.flags;
+            unsafe { raw.drop_in_place() };
+
+            flags
+        })
+        .collect();
+    flags.sort();
+
+    let mut all = ALL_FLAGS.to_vec();
+    all.sort();
+
+    assert_eq!(flags, all);
+}
+
+#[test]
+fn from_raw_releases() {
+    let backing = Backing::new();
+
+    check_no_leaks("from_raw", || {
+        for value in samples(&backing) {
+            let raw = RawValue::from(value);
+            drop(unsafe { Value::from_raw(raw) });
+        }
+    });
+}
+
+#[test]
+fn drop_in_place_releases() {
+    let backing = Backing::new();
+
+    check_no_leaks("drop_in_place", || {
+        for value in samples(&backing) {
+            let mut raw = RawValue::from(value);
+            unsafe {
+                raw.drop_in_place();
+                // Resets to void, so a second call is harmless
+                raw.drop_in_place();
+            }
+            assert_eq!(raw.flags, Flags::VOID);
+        }
+    });
+}
+
+#[test]
+fn value_free_releases() {
+    let backing = Backing::new();
+
+    check_no_leaks("vnodes_value_free", || {
+        for value in samples(&backing) {
+            let mut raw = RawValue::from(value);
+            unsafe {
+                vnodes_value_free(&mut raw);
+            }
+        }
+    });
+}
+
+#[test]
+fn as_value_borrows() {
+    let backing = Backing::new();
+
+    check_no_leaks("as_value", || {
+        for value in samples(&backing) {
+            let mut raw = RawValue::from(value);
+            unsafe {
+                drop(raw.as_value());
+                drop(raw.as_value().make_owned());
+                raw.drop_in_place();
+            }
+        }
+    });
+}
+
+#[test]
+fn clones_release() {
+    let backing = Backing::new();
+
+    check_no_leaks("clone", || {
+        for value in samples(&backing) {
+            let cloned = value.clone();
+            let mut raw = RawValue::from(value);
+            let mut raw_clone = unsafe { raw.clone_raw() };
+            unsafe {
+                raw.drop_in_place();
+                raw_clone.drop_in_place();
+            }
+            drop(cloned);
+        }
+    });
+}
+
+#[test]
+fn make_owned_releases() {
+    let backing = Backing::new();
+
+    check_no_leaks("make_owned", || {
+        for value in samples(&backing) {
+            drop(value.make_owned());
+        }
+    });
+}
+
+#[test]
+fn nested_release() {
+    let backing = Backing::new();
+
+    check_no_leaks("nested in array", || {
+        let array: ValueArray = samples(&backing).into_iter().collect();
+        drop(array);
+    });
+
+    check_no_leaks("nested in map", || {
+        let map: ValueMap = samples(&backing)
+            .into_iter()
+            .enumerate()
+            .map(|(i, value)| (Interned(i as u64 + 1), value))
+            .collect();
+        drop(map);
+    });
+
+    check_no_leaks("partially moved out of array", || {
+        let array: ValueArray = samples(&backing).into_iter().collect();
+        let mut iter = array.into_iter();
+        drop(iter.next());
+        drop(iter);
+    });
+
+    check_no_leaks("duplicate map keys", || {
+        let map: ValueMap = samples(&backing)
+            .into_iter()
+            .map(|value| (Interned::from("same"), value))
+            .collect();
+        assert_eq!(map.len(), 1);
+    });
+}
+
+#[test]
+fn tuples_release() {
+    let backing = Backing::new();
+
+    check_no_leaks("tuple", || {
+        for value in samples(&backing) {
+            let pair = (Interned::from("key"), value).into_value();
+            let _ = <(Interned, Value)>::from_value(pair);
+        }
+    });
+
+    check_no_leaks("tuple with extra elements", || {
+        let triple = (1u64, "extra".to_owned(), MapNode::new_node()).into_value();
+        let raw = RawValue::from(triple);
+        let pair: (u64, String) = ValueConv::from_value(unsafe { Value::from_raw(raw) }).unwrap();
+
+        assert_eq!(pair, (1, "extra".to_owned()));
+    });
+}
+
+#[test]
+fn results_release() {
+    let backing = Backing::new();
+
+    check_no_leaks("ok result", || {
+        for value in samples(&backing) {
+            let raw = RawResult::from(Ok(value));
+            drop(unsafe { raw.into_result() });
+        }
+    });
+
+    check_no_leaks("error result", || {
+        let errors = vec![
+            Error::new(ErrorKind::NoSuchEntry),
+            Error::custom(1, "message").with_path("/some/path"),
+        ];
+
+        for error in errors {
+            let raw = RawResult::from(Err(error));
+            drop(unsafe { raw.into_result() });
+        }
+    });
+}
+
+#[test]
+fn tree_releases() {
+    let backing = Backing::new();
+
+    check_no_leaks("tree", || {
+        let nodes = Vnodes::new();
+        nodes.insert("/dir", MapNode::new_node()).unwrap();
+
+        for (i, value) in samples(&backing).into_iter().enumerate() {
+            let path = InternedPathBuf::from_iter(vec![
+                Interned(0),
+                Interned::from("dir"),
+                Interned(i as u64 + 1),
+            ]);
+            let value = value.make_owned();
+            let _ = nodes.insert(path.clone(), value);
+            let _ = nodes.get::<_, Value>(path);
+        }
+
+        // Replacing values releases the old ones
+        nodes.insert("/dir", ()).unwrap();
+    });
+}
@@ -0,0 +1,128 @@
+#![cfg(feature = "plugin")]
+
+extern crate libloading;
+extern crate vnodes;
+
+use std::env;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::Once;
+
+use libloading::library_filename;
+use vnodes::{ErrorKind, Interned, NodeHandle, Value, ValueConv, Vnodes};
+
+/// Builds `examples/plugin` and returns the directory containing the libraries.
+fn build_plugins() -> PathBuf {
+    static BUILD: Once = Once::new();
+
+    let manifest = Path::new(env!("CARGO_MANIFEST_DIR")).join("examples/plugin/Cargo.toml");
+    let mut target = env::current_exe().unwrap();
+    target.pop();
+    if target.ends_with("deps") {
+        target.pop();
+    }
+    target.push("plugin");
+
+    BUILD.call_once(|| {
+        let output = Command::new(env!("CARGO"))
+            .args(["build", "--lib", "--examples", "--manifest-path"])
+            .arg(&manifest)
+            .arg("--target-dir")
+            .arg(&target)
+            .output()
+            .unwrap();
+        assert!(
+            output.status.success(),
+            "{}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+    });
+
+    target.join("debug")
+}
+
+fn load_example(nodes: &Vnodes) {
+    let library = build_plugins().join(library_filename("vnodes_example_plugin"));
+
+    unsafe { nodes.load_plugin(library, "/plugin").unwrap() };
+}
+
+#[test]
+fn load_and_mount() {
+    let nodes = Vnodes::new();
+    load_example(&nodes);
+
+    assert_eq!(nodes.get::<_, String>("/plugin/name").unwrap(), "plugin");
+    assert_eq!(
+        nodes.call::<_, _, String>("/plugin/greet", "vnodes").unwrap(),
+        "Hello, vnodes!"
+    );
+    assert_eq!(
+        nodes.call::<_, _, String>("/plugin/greet", ()).unwrap(),
+        "Hello, plugin!"
+    );
+
+    nodes.insert("/plugin/name", "host").unwrap();
+    assert_eq!(
+        nodes.call::<_, _, String>("/plugin/greet", ()).unwrap(),
+        "Hello, host!"
+    );
+
+    let mut list = nodes.list("/plugin").unwrap();
+    list.sort_by_key(|i| i.into_inner());
+    let mut expected = vec![
+        Interned::from("greet"),
+        Interned::from("name"),
+        Interned::from("publish"),
+    ];
+    expected.sort_by_key(|i| i.into_inner());
+    assert_eq!(list, expected);
+}
+
+#[test]
+fn handles_keep_library_loaded() {
+    let nodes = Vnodes::new();
+    load_example(&nodes);
+
+    let greet: NodeHandle = nodes.get("/plugin/greet").unwrap();
+    let _: Value = nodes.remove("/plugin").unwrap();
+    assert!(!nodes.exists("/plugin"));
+
+    let greeting = greet.call(&nodes, "library".into_value()).unwrap();
+    assert_eq!(String::from_value(greeting).unwrap(), "Hello, library!");
+}
+
+#[test]
+fn published_nodes_keep_library_loaded() {
+    let nodes = Vnodes::new();
+    load_example(&nodes);
+
+    nodes.call::<_, _, ()>("/plugin/publish", ()).unwrap();
+    let _: Value = nodes.remove("/plugin").unwrap();
+
+    assert_eq!(
+        nodes.call::<_, _, String>("/greeter", "tree").unwrap(),
+        "Hello, tree!"
+    );
+    assert_eq!(nodes.get::<_, String>("/greeting").unwrap(), "Hello!");
+
+    let greeter: Value = nodes.remove("/greeter").unwrap();
+    let greeting: Value = nodes.remove("/greeting").unwrap();
+    drop((greeter, greeting));
+}
+
+#[test]
+fn reject_invalid_plugins() {
+    let nodes = Vnodes::new();
+    let dir = build_plugins();
+
+    let outdated = dir.join("examples").join(library_filename("outdated_plugin"));
+    let error = unsafe { nodes.load_plugin(outdated, "/outdated") }.unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::AbiMismatch);
+
+    let missing = dir.join(library_filename("missing_plugin"));
+    let error = unsafe { nodes.load_plugin(missing, "/missing") }.unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::LibraryLoad);
+
+    assert!(!nodes.exists("/outdated"));
+}
@@ -0,0 +1,101 @@
+#![cfg(feature = "cli")]
+
+use std::env;
+use std::fs;
+use std::io::Write;
+use std::process::{Command, Output, Stdio};
+
+/// Runs `vnodes-sh` with `args`, piping `input` to it.
+fn shell(args: &[&str], input: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_vnodes-sh"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    // The shell might exit before reading its input
+    let _ = child.stdin.take().unwrap().write_all(input.as_bytes());
+
+    child.wait_with_output().unwrap()
+}
+
+fn stdout(output: &Output) -> String {
+    String::from_utf8(output.stdout.clone()).unwrap()
+}
+
+#[test]
+fn commands() {
+    let output = shell(
+        &[],
+        r#"mkdir /graphics/quality
+set /graphics/vsync true
+set /graphics/size 800 600
+set /name "the game"
+cd graphics
+pwd
+ls
+get size
+/name
+tree /
+rm vsync
+ls
+get missing
+set /a "open
+"#,
+    );
+
+    assert!(output.status.success());
+    assert_eq!(
+        stdout(&output),
+        r#"/graphics
+quality/
+size
+vsync
+[800, 600]
+"the game"
+/
+  graphics/
+    quality/
+    size = [800, 600]
+    vsync = true
+  name = "the game"
+true
+quality/
+size
+"#
+    );
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("No such entry at `/graphics/missing`"));
+    assert!(stderr.contains("Invalid syntax: unterminated quote"));
+}
+
+#[test]
+fn script_and_mount() {
+    let dir = env::temp_dir().join(format!("vnodes-sh-{}", std::process::id()));
+    fs::create_dir_all(dir.join("assets")).unwrap();
+    fs::write(dir.join("assets/readme.txt"), "hello").unwrap();
+    fs::write(dir.join("init"), "# comment\nset /level 3\nexit\n").unwrap();
+    fs::write(dir.join("broken"), "set /level 3\nset /a/b 1\n").unwrap();
+
+    let mount = format!("/data/assets={}", dir.join("assets").display());
+    let script = dir.join("init").display().to_string();
+    let output = shell(
+        &["--mount", &mount, &script],
+        "get /level\nget /data/assets/readme.txt\n",
+    );
+    // `exit` in the script ends the shell
+    assert!(output.status.success());
+    assert_eq!(stdout(&output), "");
+
+    let output = shell(&["--mount", &mount], "get /data/assets/readme.txt\n");
+    assert_eq!(stdout(&output), "\"hello\"\n");
+
+    let script = dir.join("broken").display().to_string();
+    let output = shell(&[&script], "");
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("broken:2: No such entry"));
+
+    fs::remove_dir_all(&dir).unwrap();
+}